@@ -0,0 +1,2 @@
+-- Track when a todo was last modified
+ALTER TABLE todos ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT now();
@@ -0,0 +1,2 @@
+-- Archived todos are hidden from the default list but kept, unlike deleted ones
+ALTER TABLE todos ADD COLUMN archived_at TIMESTAMP;
@@ -0,0 +1,3 @@
+-- Optional due time of a todo, and when the reminder webhook was sent for it
+ALTER TABLE todos ADD COLUMN due_date TIMESTAMP;
+ALTER TABLE todos ADD COLUMN reminded_at TIMESTAMP;
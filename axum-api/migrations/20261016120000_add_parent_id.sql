@@ -0,0 +1,4 @@
+-- Optional parent of a todo, subtasks are nested one level
+-- Deleting a parent deletes its subtasks, unless the API blocks it, see SUBTASK_DELETE
+ALTER TABLE todos ADD COLUMN parent_id UUID REFERENCES todos(id) ON DELETE CASCADE;
+CREATE INDEX todos_parent_id_idx ON todos(parent_id);
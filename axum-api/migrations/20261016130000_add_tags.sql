@@ -0,0 +1,3 @@
+-- Tags of a todo, stored lowercased and trimmed
+ALTER TABLE todos ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+CREATE INDEX todos_tags_idx ON todos USING GIN (tags);
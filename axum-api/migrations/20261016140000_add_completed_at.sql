@@ -0,0 +1,4 @@
+-- When the todo was completed, cleared when it is reopened
+ALTER TABLE todos ADD COLUMN completed_at TIMESTAMP;
+-- The completion time of existing todos is unknown, their last update is the closest estimate
+UPDATE todos SET completed_at = updated_at WHERE completed;
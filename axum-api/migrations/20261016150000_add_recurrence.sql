@@ -0,0 +1,2 @@
+-- How a todo repeats, completing a recurring todo creates its next occurrence
+ALTER TABLE todos ADD COLUMN recurrence TEXT CHECK (recurrence IN ('daily', 'weekly'));
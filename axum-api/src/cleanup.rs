@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use crate::store::TodoStore;
+use crate::vars::env_var;
+
+// This module provides the background cleanup task for the todo application.
+// The task periodically deletes completed todos that have not been updated
+// within the retention period.
+// The interval and retention are read from the CLEANUP_INTERVAL_SECS and
+// CLEANUP_RETENTION_DAYS environment variables.
+
+#[derive(Debug, Clone, Copy)]
+pub struct CleanupConfig {
+    pub interval: Duration,
+    pub retention_days: i32,
+}
+
+impl CleanupConfig {
+    /// Read the cleanup configuration from the environment
+    /// Defaults to running every hour and keeping completed todos for 30 days.
+    /// A zero interval or a negative retention is ignored, like an unparsable one.
+    pub fn from_env() -> Self {
+        Self::from_vars(env_var)
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let interval_secs = var("CLEANUP_INTERVAL_SECS")
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|&secs| secs > 0)
+            .unwrap_or(3600);
+        let retention_days = var("CLEANUP_RETENTION_DAYS")
+            .and_then(|v| v.parse::<i32>().ok())
+            .filter(|&days| days >= 0)
+            .unwrap_or(30);
+
+        CleanupConfig {
+            interval: Duration::from_secs(interval_secs),
+            retention_days,
+        }
+    }
+}
+
+/// spawn_cleanup_task
+/// This function spawns the periodic cleanup task on the tokio runtime.
+/// Each cycle deletes the expired completed todos through the store and logs how many were removed.
+pub fn spawn_cleanup_task(store: Arc<dyn TodoStore>, config: CleanupConfig) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            match store.delete_completed_older_than(config.retention_days).await {
+                Ok(removed) => info!("Cleanup removed {} completed todos", removed),
+                Err(e) => warn!("Cleanup failed: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{test_todo, Todo};
+    use crate::store::MemTodoStore;
+    use crate::vars::test_vars;
+    use uuid::Uuid;
+
+    #[test]
+    fn defaults_apply_when_unset() {
+        let config = CleanupConfig::from_vars(test_vars(&[]));
+        assert_eq!(config.interval, Duration::from_secs(3600));
+        assert_eq!(config.retention_days, 30);
+    }
+
+    #[test]
+    fn valid_values_are_used() {
+        let config = CleanupConfig::from_vars(test_vars(&[("CLEANUP_INTERVAL_SECS", "60"), ("CLEANUP_RETENTION_DAYS", "0")]));
+        assert_eq!(config.interval, Duration::from_secs(60));
+        assert_eq!(config.retention_days, 0);
+    }
+
+    #[test]
+    fn zero_interval_and_negative_retention_fall_back_to_the_defaults() {
+        let config = CleanupConfig::from_vars(test_vars(&[("CLEANUP_INTERVAL_SECS", "0"), ("CLEANUP_RETENTION_DAYS", "-5")]));
+        assert_eq!(config.interval, Duration::from_secs(3600));
+        assert_eq!(config.retention_days, 30);
+    }
+
+    #[tokio::test]
+    async fn each_cycle_deletes_the_completed_todos_older_than_the_retention() {
+        let days_ago = |days| chrono::Utc::now().naive_utc() - chrono::Duration::days(days);
+        let old_completed = Todo { completed: true, updated_at: days_ago(31), ..test_todo("old completed") };
+        let old_subtask = Todo { parent_id: Some(old_completed.id), updated_at: days_ago(31), ..test_todo("old subtask") };
+        let recent_completed = Todo { completed: true, updated_at: days_ago(29), ..test_todo("recent completed") };
+        let old_open = Todo { updated_at: days_ago(31), ..test_todo("old open") };
+        let kept = vec![recent_completed.id, old_open.id];
+        let store = Arc::new(MemTodoStore::default());
+        *store.todos.lock().unwrap() = vec![old_completed, old_subtask, recent_completed, old_open];
+
+        let config = CleanupConfig { interval: Duration::from_secs(3600), retention_days: 30 };
+        let task = spawn_cleanup_task(store.clone(), config);
+        // The first cycle runs right away
+        for _ in 0..100 {
+            if store.todos.lock().unwrap().len() == kept.len() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        task.abort();
+
+        let remaining: Vec<Uuid> = store.todos.lock().unwrap().iter().map(|todo| todo.id).collect();
+        assert_eq!(remaining, kept);
+    }
+}
@@ -1,14 +1,108 @@
-use sqlx::postgres::PgPoolOptions;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use sqlx::{postgres::PgPoolOptions, Executor};
 use std::env;
+use crate::vars::env_var;
+
+// Statement timeout used when STATEMENT_TIMEOUT_MS is not set
+const DEFAULT_STATEMENT_TIMEOUT_MS: u64 = 30_000;
 
 /// This module provides the database connection pool for the todo application.
-/// It initializes the connection pool using the DATABASE_URL environment variable.
+/// It initializes the connection pool using the DATABASE_URL environment variable,
+/// or the DB_* environment variables when it is not set, see build_connection_string.
+/// Every connection gets a statement timeout, STATEMENT_TIMEOUT_MS milliseconds (default 30000),
+/// so a slow query is cancelled by postgres and answered with 504 instead of holding the connection.
 pub async fn get_db_pool() -> sqlx::PgPool {
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgPoolOptions::new()
-    .max_connections(5)
+    let database_url = build_connection_string().expect("DATABASE_URL or DB_HOST must be set");
+    let statement_timeout = env::var("STATEMENT_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_STATEMENT_TIMEOUT_MS);
+
+    pool_options(statement_timeout)
     .connect(&database_url)
     .await
     .expect("Failed to create database pool")
 
-}
\ No newline at end of file
+}
+
+// Pool settings, every new connection gets the statement timeout in milliseconds
+pub fn pool_options(statement_timeout: u64) -> PgPoolOptions {
+    PgPoolOptions::new()
+    .max_connections(5)
+    .after_connect(move |conn, _meta| Box::pin(async move {
+        conn.execute(format!("SET statement_timeout = {}", statement_timeout).as_str()).await?;
+        Ok(())
+    }))
+}
+
+/// build_connection_string
+/// This function returns the postgres connection string of the database.
+/// DATABASE_URL is used as is when it is set. Otherwise the connection string is assembled from
+/// DB_HOST, DB_PORT (default 5432), DB_USER (default postgres), DB_PASSWORD, DB_NAME and DB_SSLMODE,
+/// e.g. postgres://app:p%40ss@db:5432/todos?sslmode=require.
+/// The user and password are percent-encoded, so special characters like @ or / are safe.
+/// It returns None when neither DATABASE_URL nor DB_HOST is set.
+pub fn build_connection_string() -> Option<String> {
+    connection_string_from(env_var)
+}
+
+// Assemble the connection string from the variables returned by `var`, see build_connection_string
+fn connection_string_from(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    if let Some(database_url) = var("DATABASE_URL") {
+        return Some(database_url);
+    }
+
+    let host = var("DB_HOST")?;
+    let port = var("DB_PORT").unwrap_or_else(|| "5432".to_string());
+    let user = var("DB_USER").unwrap_or_else(|| "postgres".to_string());
+    let encode = |value: &str| utf8_percent_encode(value, NON_ALPHANUMERIC).to_string();
+
+    let mut url = format!("postgres://{}", encode(&user));
+    if let Some(password) = var("DB_PASSWORD") {
+        url.push_str(&format!(":{}", encode(&password)));
+    }
+    url.push_str(&format!("@{}:{}", host, port));
+    if let Some(name) = var("DB_NAME") {
+        url.push_str(&format!("/{}", encode(&name)));
+    }
+    if let Some(sslmode) = var("DB_SSLMODE") {
+        url.push_str(&format!("?sslmode={}", encode(&sslmode)));
+    }
+    Some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vars::test_vars;
+
+    #[test]
+    fn database_url_takes_precedence() {
+        let url = connection_string_from(test_vars(&[("DATABASE_URL", "postgres://a@b/c"), ("DB_HOST", "db")]));
+        assert_eq!(url.as_deref(), Some("postgres://a@b/c"));
+    }
+
+    #[test]
+    fn missing_host_gives_none() {
+        assert_eq!(connection_string_from(test_vars(&[])), None);
+        assert_eq!(connection_string_from(test_vars(&[("DB_USER", "app"), ("DB_NAME", "todos")])), None);
+    }
+
+    #[test]
+    fn host_alone_uses_the_defaults() {
+        assert_eq!(connection_string_from(test_vars(&[("DB_HOST", "db")])).as_deref(), Some("postgres://postgres@db:5432"));
+    }
+
+    #[test]
+    fn every_part_is_assembled_and_encoded() {
+        let url = connection_string_from(test_vars(&[
+            ("DB_HOST", "db"),
+            ("DB_PORT", "6543"),
+            ("DB_USER", "app"),
+            ("DB_PASSWORD", "p@ss/w:rd"),
+            ("DB_NAME", "todos"),
+            ("DB_SSLMODE", "require"),
+        ]));
+        assert_eq!(url.as_deref(), Some("postgres://app:p%40ss%2Fw%3Ard@db:6543/todos?sslmode=require"));
+    }
+}
@@ -0,0 +1,225 @@
+use axum::{
+    http::{header::{CONTENT_TYPE, RETRY_AFTER}, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use tracing::error;
+use crate::validation::FieldError;
+
+// This module defines the shared error type for the todo handlers.
+// Every variant is returned as an RFC 7807 application/problem+json body with a matching status code,
+// InvalidFields adds the invalid fields as {"errors": [{"field": ..., "message": ...}]}.
+// Database errors are mapped centrally, so handlers can use `?` on sqlx results.
+// Internal error text is only shown in development, see verbose_errors.
+
+// Seconds a client is asked to wait before retrying when the pool is exhausted
+const RETRY_AFTER_SECS: &str = "5";
+
+// Media type of the error responses
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+// Postgres error code of a statement cancelled by statement_timeout
+const QUERY_CANCELED: &str = "57014";
+
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    BadRequest(String),
+    Conflict(String),
+    Validation(String),
+    InvalidFields(Vec<FieldError>),
+    Database(sqlx::Error),
+}
+
+/// verbose_errors
+/// This function tells whether error responses may include internal error details.
+/// It is true when APP_ENV is dev or development, then the detail of an unexpected database error
+/// holds the sqlx error text. Otherwise, e.g. in production, the detail stays generic.
+fn verbose_errors() -> bool {
+    is_development(std::env::var("APP_ENV").ok().as_deref())
+}
+
+// Whether the APP_ENV value names a development environment
+fn is_development(app_env: Option<&str>) -> bool {
+    matches!(app_env, Some("dev" | "development"))
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        AppError::Database(err)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        self.into_response_with(verbose_errors())
+    }
+}
+
+impl AppError {
+    /// Build the error response, `verbose` adds the internal error text to the detail, see verbose_errors
+    pub fn into_response_with(self, verbose: bool) -> Response {
+        let (status, detail) = match self {
+            AppError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            AppError::Conflict(message) => (StatusCode::CONFLICT, message),
+            AppError::Validation(message) => (StatusCode::UNPROCESSABLE_ENTITY, message),
+            // Every invalid field of the payload is listed, not just the first one
+            AppError::InvalidFields(errors) => {
+                return problem_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "The request payload has invalid fields",
+                    Some(json!({ "errors": errors })),
+                );
+            }
+            // No connection could be acquired in time, ask the client to back off
+            AppError::Database(sqlx::Error::PoolTimedOut) => {
+                let mut response = problem_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "database is busy, retry later",
+                    None,
+                );
+                response.headers_mut().insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+                return response;
+            }
+            // The query ran longer than the statement timeout set in db.rs
+            AppError::Database(sqlx::Error::Database(err)) if err.code().as_deref() == Some(QUERY_CANCELED) => {
+                (StatusCode::GATEWAY_TIMEOUT, "database query timed out".to_string())
+            }
+            AppError::Database(err) => {
+                error!("database error: {}", err);
+                let detail = if verbose {
+                    format!("database error: {}", err)
+                } else {
+                    "database error".to_string()
+                };
+                (StatusCode::INTERNAL_SERVER_ERROR, detail)
+            }
+        };
+
+        problem_response(status, &detail, None)
+    }
+}
+
+/// problem_response
+/// This function builds an RFC 7807 application/problem+json error response.
+/// The body holds the type, title, status and detail members, the title is the status reason,
+/// e.g. {"type": "about:blank", "title": "Not Found", "status": 404, "detail": "Task not found"}.
+/// The members of `extensions`, if any, are added to the body, e.g. the invalid fields.
+pub fn problem_response(status: StatusCode, detail: &str, extensions: Option<Value>) -> Response {
+    let mut body = json!({
+        "type": "about:blank",
+        "title": status.canonical_reason().unwrap_or("Error"),
+        "status": status.as_u16(),
+        "detail": detail,
+    });
+    if let (Some(Value::Object(extensions)), Value::Object(body)) = (extensions, &mut body) {
+        body.extend(extensions);
+    }
+
+    (
+        status,
+        [(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON))],
+        body.to_string(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+
+    async fn body_of(response: Response) -> Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn errors_map_to_their_status_code() {
+        let cases = [
+            (AppError::NotFound("Task not found".into()), StatusCode::NOT_FOUND),
+            (AppError::BadRequest("bad".into()), StatusCode::BAD_REQUEST),
+            (AppError::Conflict("conflict".into()), StatusCode::CONFLICT),
+            (AppError::Validation("invalid".into()), StatusCode::UNPROCESSABLE_ENTITY),
+            (AppError::Database(sqlx::Error::RowNotFound), StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+        for (err, status) in cases {
+            let response = err.into_response();
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+            let body = body_of(response).await;
+            assert_eq!(body["status"], status.as_u16());
+            assert_eq!(body["title"], status.canonical_reason().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn not_found_keeps_its_message_as_detail() {
+        let body = body_of(AppError::NotFound("Task not found".into()).into_response()).await;
+        assert_eq!(body["type"], "about:blank");
+        assert_eq!(body["detail"], "Task not found");
+    }
+
+    #[tokio::test]
+    async fn pool_timed_out_error_converts_to_503_with_retry_after() {
+        let response = AppError::Database(sqlx::Error::PoolTimedOut).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], RETRY_AFTER_SECS);
+        assert_eq!(body_of(response).await["detail"], "database is busy, retry later");
+    }
+
+    #[sqlx::test]
+    #[ignore]
+    async fn exhausted_pool_is_503_with_retry_after(pool: sqlx::PgPool) {
+        let exhausted = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(std::time::Duration::from_millis(100))
+            .connect_with((*pool.connect_options()).clone())
+            .await
+            .unwrap();
+        let _held = exhausted.acquire().await.unwrap();
+
+        let err = sqlx::query("SELECT 1").execute(&exhausted).await.unwrap_err();
+        let response = AppError::from(err).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], RETRY_AFTER_SECS);
+    }
+
+    #[tokio::test]
+    async fn invalid_fields_are_listed() {
+        let errors = vec![
+            FieldError { field: "title", message: "Title must not be empty".into() },
+            FieldError { field: "tags", message: "Tags must not be empty".into() },
+        ];
+        let response = AppError::InvalidFields(errors).into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = body_of(response).await;
+        assert_eq!(body["errors"][0], json!({"field": "title", "message": "Title must not be empty"}));
+        assert_eq!(body["errors"][1]["field"], "tags");
+    }
+
+    #[test]
+    fn only_dev_environments_are_verbose() {
+        assert!(is_development(Some("dev")));
+        assert!(is_development(Some("development")));
+        assert!(!is_development(Some("production")));
+        assert!(!is_development(None));
+    }
+
+    #[tokio::test]
+    async fn database_error_text_is_only_shown_in_development() {
+        let response = AppError::Database(sqlx::Error::RowNotFound).into_response_with(true);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+        let body = body_of(response).await;
+        assert_eq!(body["status"], 500);
+        assert_eq!(body["detail"], format!("database error: {}", sqlx::Error::RowNotFound));
+
+        let response = AppError::Database(sqlx::Error::RowNotFound).into_response_with(false);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+        let body = body_of(response).await;
+        assert_eq!(body["status"], 500);
+        assert_eq!(body["detail"], "database error");
+    }
+}
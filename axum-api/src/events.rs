@@ -0,0 +1,259 @@
+use async_trait::async_trait;
+use axum::response::sse::Event;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{postgres::PgListener, PgPool};
+use std::time::Duration;
+use tokio::{sync::broadcast, task::JoinHandle};
+use tracing::warn;
+use uuid::Uuid;
+use crate::models::Todo;
+
+// This module defines the change events published by the todo handlers.
+// Handlers publish events with Postgres NOTIFY on the todo_changes channel, so every
+// API instance connected to the same database sees the changes of the others.
+// A listener task per instance forwards the notifications to a broadcast channel,
+// which the SSE endpoint streams to the connected clients.
+// Handlers publish through the EventPublisher in the app state, PgEventPublisher in the server.
+// Each event is sent as an SSE message named created, updated or deleted,
+// with the todo (or the id of the deleted todo) as JSON in the data field.
+
+// Number of events kept for slow subscribers before they start missing events
+pub const EVENT_CAPACITY: usize = 100;
+
+// Postgres channel the events are sent on
+pub const EVENT_CHANNEL: &str = "todo_changes";
+
+// Postgres rejects NOTIFY payloads of 8000 bytes or more
+const MAX_NOTIFY_PAYLOAD: usize = 7999;
+
+pub type EventSender = broadcast::Sender<TodoEvent>;
+
+// The NOTIFY payload is the event as JSON, e.g. {"event": "deleted", "data": "<uuid>"}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "lowercase")]
+pub enum TodoEvent {
+    Created(Todo),
+    Updated(Todo),
+    Deleted(Uuid),
+}
+
+// A NOTIFY payload, the event itself or, when the todo is too large for NOTIFY, only the kind
+// of the event and the id of the todo, e.g. {"event": "updated", "id": "<uuid>"}.
+// The listener loads the todo of such a reference before forwarding the event.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Notification {
+    Event(TodoEvent),
+    Reference { event: ChangeKind, id: Uuid },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ChangeKind {
+    Created,
+    Updated,
+}
+
+impl TodoEvent {
+    /// Convert the event into an SSE message
+    pub fn to_sse(&self) -> Result<Event, axum::Error> {
+        match self {
+            TodoEvent::Created(todo) => Event::default().event("created").json_data(todo),
+            TodoEvent::Updated(todo) => Event::default().event("updated").json_data(todo),
+            TodoEvent::Deleted(id) => Event::default().event("deleted").json_data(json!({ "id": id })),
+        }
+    }
+}
+
+// Encode the NOTIFY payload of an event, falling back to a reference when the event is too large
+fn notify_payload(event: &TodoEvent) -> Result<String, serde_json::Error> {
+    let payload = serde_json::to_string(event)?;
+    if payload.len() <= MAX_NOTIFY_PAYLOAD {
+        return Ok(payload);
+    }
+
+    let reference = match event {
+        TodoEvent::Created(todo) => Notification::Reference { event: ChangeKind::Created, id: todo.id },
+        TodoEvent::Updated(todo) => Notification::Reference { event: ChangeKind::Updated, id: todo.id },
+        TodoEvent::Deleted(_) => return Ok(payload),
+    };
+    serde_json::to_string(&reference)
+}
+
+/// Publish an event to every API instance with NOTIFY
+/// Events too large for a NOTIFY payload are sent as a reference to the todo, see Notification.
+/// The write it describes has already succeeded, so a failure is only logged.
+pub async fn publish(pool: &PgPool, event: &TodoEvent) {
+    let payload = match notify_payload(event) {
+        Ok(payload) => payload,
+        Err(err) => {
+            warn!("failed to encode event: {}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(EVENT_CHANNEL)
+        .bind(payload)
+        .execute(pool)
+        .await
+    {
+        warn!("failed to publish event: {}", err);
+    }
+}
+
+/// Publishes the change events of the handlers
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+    async fn publish(&self, event: TodoEvent);
+}
+
+// Publishes the events to every API instance with NOTIFY, see publish
+pub struct PgEventPublisher {
+    pool: PgPool,
+}
+
+impl PgEventPublisher {
+    pub fn new(pool: PgPool) -> Self {
+        PgEventPublisher { pool }
+    }
+}
+
+#[async_trait]
+impl EventPublisher for PgEventPublisher {
+    async fn publish(&self, event: TodoEvent) {
+        publish(&self.pool, &event).await;
+    }
+}
+
+// Publisher of the handler tests, it keeps the events for inspection
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemEventPublisher {
+    pub events: std::sync::Mutex<Vec<TodoEvent>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl EventPublisher for MemEventPublisher {
+    async fn publish(&self, event: TodoEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+/// Spawn the task that forwards the notifications of the todo_changes channel to `sender`
+/// The listener holds its own connection, it reconnects when the connection is lost,
+/// the notifications sent while it was disconnected are missed.
+pub fn spawn_event_listener(pool: PgPool, sender: EventSender) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if let Err(err) = listen(&pool, &sender).await {
+                warn!("event listener failed: {}, retrying in 5s", err);
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    })
+}
+
+// Listen on the channel until the connection fails
+async fn listen(pool: &PgPool, sender: &EventSender) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(EVENT_CHANNEL).await?;
+
+    loop {
+        let notification = listener.recv().await?;
+        let event = match serde_json::from_str::<Notification>(notification.payload()) {
+            Ok(Notification::Event(event)) => event,
+            Ok(Notification::Reference { event, id }) => match load_event(pool, event, id).await {
+                Some(event) => event,
+                None => continue,
+            },
+            Err(err) => {
+                warn!("ignoring invalid event {:?}: {}", notification.payload(), err);
+                continue;
+            }
+        };
+        // Sending only fails when nobody is listening, which is fine
+        let _ = sender.send(event);
+    }
+}
+
+// Load the todo of a referenced event, None when it was deleted in the meantime or the query failed
+async fn load_event(pool: &PgPool, kind: ChangeKind, id: Uuid) -> Option<TodoEvent> {
+    let todo = sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .unwrap_or_else(|err| {
+            warn!("failed to load the todo {} of an event: {}", id, err);
+            None
+        })?;
+
+    Some(match kind {
+        ChangeKind::Created => TodoEvent::Created(todo),
+        ChangeKind::Updated => TodoEvent::Updated(todo),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_todo;
+
+    #[test]
+    fn small_events_are_sent_whole() {
+        let todo = test_todo("buy milk");
+        let payload = notify_payload(&TodoEvent::Updated(todo.clone())).unwrap();
+
+        match serde_json::from_str::<Notification>(&payload).unwrap() {
+            Notification::Event(TodoEvent::Updated(sent)) => assert_eq!(sent.title, todo.title),
+            other => panic!("unexpected notification {:?}", other),
+        }
+    }
+
+    #[test]
+    fn large_events_are_sent_as_a_reference() {
+        let todo = test_todo(&"x".repeat(MAX_NOTIFY_PAYLOAD));
+        let payload = notify_payload(&TodoEvent::Created(todo.clone())).unwrap();
+
+        assert!(payload.len() <= MAX_NOTIFY_PAYLOAD);
+        match serde_json::from_str::<Notification>(&payload).unwrap() {
+            Notification::Reference { event, id } => {
+                assert_eq!(event, ChangeKind::Created);
+                assert_eq!(id, todo.id);
+            }
+            other => panic!("unexpected notification {:?}", other),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore]
+    async fn published_events_reach_the_subscribers(pool: PgPool) {
+        let (sender, mut receiver) = broadcast::channel(EVENT_CAPACITY);
+        let listener = spawn_event_listener(pool.clone(), sender);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let large = test_todo(&"x".repeat(MAX_NOTIFY_PAYLOAD));
+        sqlx::query("INSERT INTO todos (id, title) VALUES ($1, $2)")
+            .bind(large.id)
+            .bind(&large.title)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let deleted = Uuid::new_v4();
+        publish(&pool, &TodoEvent::Updated(large.clone())).await;
+        publish(&pool, &TodoEvent::Deleted(deleted)).await;
+
+        let wait = Duration::from_secs(5);
+        match tokio::time::timeout(wait, receiver.recv()).await.unwrap().unwrap() {
+            TodoEvent::Updated(todo) => assert_eq!((todo.id, todo.title), (large.id, large.title)),
+            other => panic!("unexpected event {:?}", other),
+        }
+        match tokio::time::timeout(wait, receiver.recv()).await.unwrap().unwrap() {
+            TodoEvent::Deleted(id) => assert_eq!(id, deleted),
+            other => panic!("unexpected event {:?}", other),
+        }
+        listener.abort();
+    }
+}
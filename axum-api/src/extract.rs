@@ -0,0 +1,53 @@
+use axum::{
+    extract::{rejection::JsonRejection, FromRequest},
+    response::{IntoResponse, Response},
+};
+use reqwest::StatusCode;
+use crate::error::problem_response;
+
+// This module provides the JSON extractor used by the todo handlers.
+// AppJson works like axum's Json extractor, but converts its rejections
+// into JsonError, which is returned as an application/problem+json body like AppError.
+// Invalid JSON returns 400 and a missing JSON content type returns 415.
+// Payloads that parse but do not match the target type, such as
+// unknown fields on structs marked with deny_unknown_fields, return 422
+// with the serde message naming the offending field.
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(JsonError))]
+pub struct AppJson<T>(pub T);
+
+#[derive(Debug)]
+pub struct JsonError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl From<JsonRejection> for JsonError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::JsonSyntaxError(e) => JsonError {
+                status: StatusCode::BAD_REQUEST,
+                message: e.body_text(),
+            },
+            JsonRejection::MissingJsonContentType(e) => JsonError {
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                message: e.body_text(),
+            },
+            JsonRejection::JsonDataError(e) => JsonError {
+                status: StatusCode::UNPROCESSABLE_ENTITY,
+                message: e.body_text(),
+            },
+            other => JsonError {
+                status: other.status(),
+                message: other.body_text(),
+            },
+        }
+    }
+}
+
+impl IntoResponse for JsonError {
+    fn into_response(self) -> Response {
+        problem_response(self.status, &self.message, None)
+    }
+}
@@ -1,41 +1,513 @@
 use axum::{
-    extract::{Path, State},
+    extract::{OriginalUri, Path, Query, State},
+    http::{header::{ACCEPT, CONTENT_TYPE, LINK, VARY}, HeaderMap, HeaderValue, Method},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response},
     Json,
-    response::{IntoResponse, Response},
 };
+use futures_util::stream::{self, Stream};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
 use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
 use serde::Deserialize;
+use serde_json::{json, Map, Value};
 use sqlx::PgPool;
-use crate::models::Todo;
-use reqwest::{Request, StatusCode};
+use crate::models::{DailyStats, DailyThroughput, Todo};
+use crate::extract::AppJson;
+use crate::error::{problem_response, AppError};
+use crate::validation::Validator;
+use crate::health::Health;
+use crate::events::{EventPublisher, EventSender, TodoEvent};
+use crate::store::{NewTodo, Page, TodoChanges, TodoStore};
+use crate::recurrence::{next_occurrence, Recurrence};
+use reqwest::StatusCode;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 
 // This module contains the handlers for the todo application.
 // It defines the functions to handle various HTTP requests related to todo items.
 // Each function corresponds to a specific route and performs operations like listing, creating, retrieving, and deleting todo items.
-// The handlers use Axum's extractors to get the database connection and request data.
+// The handlers use Axum's extractors to get the todo store and request data.
+// They go through the TodoStore trait instead of the database connection, see store.rs,
+// except for the daily_stats and completion_throughput reports, which query Postgres directly.
 // The CreateTodo struct is used to deserialize the request body for creating a new todo item.
+// With the camel-case feature request bodies use camelCase field names like the responses,
+// query parameters keep their snake_case names.
+// Request bodies are read with AppJson and reject unknown fields, so a typo like
+// {"titel": "x"} returns 422 naming the unexpected field instead of being ignored.
+// Handlers return AppError, which maps database errors to the right status code.
+// Payload fields are checked with a Validator, which reports all invalid fields at once.
+// Handlers that change todos publish a TodoEvent through the EventPublisher, which is streamed to SSE clients.
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
 pub struct CreateTodo {
-    pub title: String
+    pub title: String,
+    pub due_date: Option<DateTime<Utc>>,
+    pub parent_id: Option<Uuid>,
+    pub tags: Option<Vec<String>>,
+    pub recurrence: Option<Recurrence>
+}
+
+// Query parameters accepted by create_todo
+// unique=true rejects a title that already exists, ignoring case
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateParams {
+    pub unique: Option<bool>,
+}
+
+// Query parameters accepted by list_todos
+// archived=true lists the archived todos instead of the active ones
+// page and per_page select a page of the list, pages start at 1
+// fields is a comma separated list of the todo fields to return, e.g. id,title
+// tag lists only the todos carrying that tag
+#[derive(Debug, Default, Deserialize)]
+pub struct ListParams {
+    pub archived: Option<bool>,
+    pub tag: Option<String>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    pub fields: Option<String>,
+}
+
+// Page size used when only page is given, and the largest page size accepted
+const DEFAULT_PER_PAGE: i64 = 20;
+const MAX_PER_PAGE: i64 = 100;
+
+// Fields of a todo that can be selected with ?fields=
+// The names follow the casing of the JSON fields, see the camel-case feature
+#[cfg(not(feature = "camel-case"))]
+pub const TODO_FIELDS: [&str; 11] = [
+    "id", "title", "completed", "created_at", "updated_at", "archived_at", "due_date", "parent_id", "tags",
+    "completed_at", "recurrence",
+];
+#[cfg(feature = "camel-case")]
+pub const TODO_FIELDS: [&str; 11] = [
+    "id", "title", "completed", "createdAt", "updatedAt", "archivedAt", "dueDate", "parentId", "tags",
+    "completedAt", "recurrence",
+];
+
+// What delete_todo does with the subtasks of a deleted todo, read from SUBTASK_DELETE
+// block (the default) refuses to delete a todo that has subtasks, cascade deletes them with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtaskDeletion {
+    Block,
+    Cascade,
+}
+
+impl SubtaskDeletion {
+    pub fn from_env() -> Self {
+        match std::env::var("SUBTASK_DELETE").as_deref() {
+            Ok("cascade") => SubtaskDeletion::Cascade,
+            _ => SubtaskDeletion::Block,
+        }
+    }
+}
+
+// Query parameters accepted by recent_todos
+// since is an RFC 3339 timestamp, e.g. 2024-01-01T00:00:00Z
+#[derive(Debug, Default, Deserialize)]
+pub struct RecentParams {
+    pub since: Option<DateTime<Utc>>,
+}
+
+// Query parameters accepted by completion_throughput
+// days is the number of days of the window, ending today
+#[derive(Debug, Default, Deserialize)]
+pub struct ThroughputParams {
+    pub days: Option<i32>,
+}
+
+// Window of completion_throughput when days is not given, and the longest window accepted
+const DEFAULT_THROUGHPUT_DAYS: i32 = 7;
+const MAX_THROUGHPUT_DAYS: i32 = 366;
+
+// Query parameters accepted by poll_todos
+// since is an RFC 3339 timestamp, wait the number of seconds to wait for a change
+#[derive(Debug, Default, Deserialize)]
+pub struct PollParams {
+    pub since: Option<DateTime<Utc>>,
+    pub wait: Option<u64>,
+}
+
+// Seconds poll_todos waits when wait is not given, and the longest wait
+// The longest wait stays below the default request timeout of 30 seconds, see main.rs
+const DEFAULT_POLL_WAIT_SECS: u64 = 20;
+const MAX_POLL_WAIT_SECS: u64 = 25;
+
+// Time between the end of the longest poll and the request timeout
+const POLL_TIMEOUT_MARGIN: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Longest time poll_todos holds a request, MAX_POLL_WAIT_SECS or less with a short REQUEST_TIMEOUT_SECS,
+// so an empty poll is answered before the timeout layer of main.rs turns it into a 408
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollWaitLimit(pub std::time::Duration);
+
+impl PollWaitLimit {
+    pub fn for_timeout(request_timeout: std::time::Duration) -> Self {
+        let max_wait = std::time::Duration::from_secs(MAX_POLL_WAIT_SECS);
+        PollWaitLimit(max_wait.min(request_timeout.saturating_sub(POLL_TIMEOUT_MARGIN)))
+    }
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
 pub struct UpdateTodo {
     pub title: Option<String>,
     pub completed: Option<bool>,
+    pub due_date: Option<DateTime<Utc>>,
+    pub tags: Option<Vec<String>>,
+    pub recurrence: Option<Recurrence>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct UpdateTitle {
+    pub title: String
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct QueryTodos {
+    pub ids: Vec<Uuid>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct CompleteTodos {
+    pub ids: Vec<Uuid>,
+    pub completed: bool
+}
+
+/// normalize_title
+/// This function trims leading and trailing whitespace from a title
+/// and collapses internal runs of whitespace to a single space,
+/// so "  buy   milk  " becomes "buy milk".
+/// It is applied after the title is validated, so whitespace-only titles are still rejected.
+pub fn normalize_title(title: &str) -> String {
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// normalize_tags
+/// This function trims and lowercases every tag and drops repeated tags,
+/// so [" Work", "work", "Home "] becomes ["work", "home"].
+/// Like normalize_title it is applied after validation, so empty tags are still rejected.
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// health_check
+/// This function reports whether the database is reachable, as last seen by the health monitor.
+/// It answers from the cached state without querying the database, see health.rs.
+/// It returns 200 {"status": "ok"} or 503 {"status": "unavailable"}.
+pub async fn health_check(State(health): State<Health>) -> (StatusCode, Json<Value>) {
+    if health.is_healthy() {
+        (StatusCode::OK, Json(json!({ "status": "ok" })))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "status": "unavailable" })))
+    }
+}
+
+/// method_not_allowed
+/// This function answers requests whose method is not supported by an existing route, e.g. PATCH /todos.
+/// It returns 405 Method Not Allowed as application/problem+json, axum adds the Allow header
+/// listing the methods the route supports.
+pub async fn method_not_allowed(method: Method, OriginalUri(uri): OriginalUri) -> Response {
+    problem_response(
+        StatusCode::METHOD_NOT_ALLOWED,
+        &format!("Method {} is not allowed for {}", method, uri.path()),
+        None,
+    )
+}
+
+/// not_found
+/// This function answers requests to paths that match no route.
+/// It returns 404 Not Found as application/problem+json with the requested path in a `path` member.
+pub async fn not_found(OriginalUri(uri): OriginalUri) -> Response {
+    problem_response(
+        StatusCode::NOT_FOUND,
+        "not found",
+        Some(json!({ "path": uri.path() })),
+    )
 }
 
 /// list_todos
 /// This function retrieves all todo items from the database.
 /// It uses the SELECT SQL command to fetch all items.
 /// It returns a Json Todo list containing all todo items ordered by their creation date.
-pub async fn list_todos(State(pool): State<PgPool>) -> Json<Vec<Todo>> {
-    let todos = sqlx::query_as::<_, Todo>("SELECT * FROM todos ORDER BY created_at DESC")
-        .fetch_all(&pool)
-        .await
-        .unwrap();
-    Json(todos)
+/// Archived todos are left out, unless `?archived=true` is given, then only they are listed.
+/// With `?page=N` and/or `?per_page=M` a single page is returned, along with the X-Total-Count
+/// and X-Page headers and a Link header pointing to the first, prev, next and last pages.
+/// With `?fields=id,title` each todo only contains the listed fields, see TODO_FIELDS.
+/// With `?tag=work` only the todos carrying the tag are listed, the tag is normalized like the stored ones.
+/// The list is returned as CSV when the Accept header asks for text/csv, and as JSON otherwise.
+pub async fn list_todos(
+    State(store): State<Arc<dyn TodoStore>>,
+    OriginalUri(uri): OriginalUri,
+    request_headers: HeaderMap,
+    Query(params): Query<ListParams>,
+) -> Result<Response, AppError> {
+    let archived = params.archived.unwrap_or(false);
+    let tag = params.tag.as_deref().map(|tag| tag.trim().to_lowercase()).filter(|tag| !tag.is_empty());
+    let fields = params.fields.as_deref().map(parse_fields).transpose()?;
+
+    let mut headers = HeaderMap::new();
+    let todos = if params.page.is_none() && params.per_page.is_none() {
+        store.list(archived, tag.as_deref(), None).await?.0
+    } else {
+        let page = params.page.unwrap_or(1);
+        let per_page = params.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if page < 1 {
+            return Err(AppError::Validation("page must be at least 1".into()));
+        }
+        if !(1..=MAX_PER_PAGE).contains(&per_page) {
+            return Err(AppError::Validation(format!("per_page must be between 1 and {}", MAX_PER_PAGE)));
+        }
+
+        let page_of_list = Page { limit: per_page, offset: (page - 1) * per_page };
+        let (todos, total) = store.list(archived, tag.as_deref(), Some(page_of_list)).await?;
+
+        // The links keep the other query parameters, so every page is filtered the same way
+        let mut filters = String::new();
+        if let Some(archived) = params.archived {
+            filters.push_str(&format!("archived={}&", archived));
+        }
+        if let Some(tag) = &tag {
+            filters.push_str(&format!("tag={}&", utf8_percent_encode(tag, NON_ALPHANUMERIC)));
+        }
+        if let Some(fields) = &params.fields {
+            filters.push_str(&format!("fields={}&", fields));
+        }
+
+        let last = ((total + per_page - 1) / per_page).max(1);
+        headers.insert("x-total-count", HeaderValue::from(total));
+        headers.insert("x-page", HeaderValue::from(page));
+        let link = pagination_links(uri.path(), &filters, page, per_page, last);
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            headers.insert(LINK, link);
+        }
+        todos
+    };
+
+    let todos: Vec<Value> = todos
+        .iter()
+        .map(|todo| select_fields(todo, fields.as_deref()))
+        .collect();
+
+    // The body depends on the Accept header, caches must not mix up the formats
+    headers.insert(VARY, HeaderValue::from_static("accept"));
+    if wants_csv(&request_headers) {
+        let columns = fields.unwrap_or_else(|| TODO_FIELDS.iter().map(|f| f.to_string()).collect());
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/csv; charset=utf-8"));
+        return Ok((headers, to_csv(&columns, &todos)).into_response());
+    }
+    Ok((headers, Json(todos)).into_response())
+}
+
+/// wants_csv
+/// This function checks the Accept header of a request for text/csv.
+/// The first of text/csv, application/json or */* listed in the header decides,
+/// so a missing header, */* or application/json keep the JSON default.
+pub fn wants_csv(headers: &HeaderMap) -> bool {
+    let Some(accept) = headers.get(ACCEPT).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+
+    accept
+        .split(',')
+        .map(|media| media.split(';').next().unwrap_or("").trim())
+        .find(|media| matches!(*media, "text/csv" | "application/json" | "*/*"))
+        == Some("text/csv")
+}
+
+/// to_csv
+/// This function writes the todos as CSV, with a header row naming the columns.
+/// Missing and null values are left empty, values containing a comma, quote
+/// or line break are quoted and their quotes doubled (RFC 4180).
+pub fn to_csv(columns: &[String], todos: &[Value]) -> String {
+    let escape = |field: String| {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field
+        }
+    };
+
+    let mut csv = columns.join(",");
+    csv.push_str("\r\n");
+    for todo in todos {
+        let row: Vec<String> = columns
+            .iter()
+            .map(|column| match todo.get(column) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => escape(s.clone()),
+                Some(other) => escape(other.to_string()),
+            })
+            .collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// pagination_links
+/// This function builds the RFC 5988 Link header value for a page of list_todos,
+/// e.g. `</todos?page=3&per_page=10>; rel="next"`.
+/// `filters` holds the other query parameters of the request, each followed by `&`.
+/// The first and last links are always included, prev and next only when that page exists.
+pub fn pagination_links(path: &str, filters: &str, page: i64, per_page: i64, last: i64) -> String {
+    let link = |target: i64, rel: &str| {
+        format!("<{}?{}page={}&per_page={}>; rel=\"{}\"", path, filters, target, per_page, rel)
+    };
+
+    let mut links = vec![link(1, "first")];
+    if page > 1 {
+        links.push(link((page - 1).min(last), "prev"));
+    }
+    if page < last {
+        links.push(link(page + 1, "next"));
+    }
+    links.push(link(last, "last"));
+    links.join(", ")
+}
+
+/// parse_fields
+/// This function parses the comma separated `fields` query parameter of list_todos.
+/// Every name must be one of TODO_FIELDS, an unknown name is a 400 Bad Request error.
+pub fn parse_fields(fields: &str) -> Result<Vec<String>, AppError> {
+    fields
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            if TODO_FIELDS.contains(&field) {
+                Ok(field.to_string())
+            } else {
+                Err(AppError::BadRequest(format!(
+                    "Unknown field '{}', expected one of: {}",
+                    field,
+                    TODO_FIELDS.join(", ")
+                )))
+            }
+        })
+        .collect()
+}
+
+/// select_fields
+/// This function serializes a todo into a JSON object.
+/// With `fields` set, only the listed fields are kept, otherwise the full object is returned.
+pub fn select_fields(todo: &Todo, fields: Option<&[String]>) -> Value {
+    let value = serde_json::to_value(todo).expect("a todo always serializes to JSON");
+    let (Some(fields), Value::Object(object)) = (fields, &value) else {
+        return value;
+    };
+
+    let selected: Map<String, Value> = object
+        .iter()
+        .filter(|(key, _)| fields.contains(key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    Value::Object(selected)
+}
+
+/// recent_todos
+/// This function retrieves the todo items updated after the `since` query parameter.
+/// It is meant for clients doing incremental sync, so the items are ordered by updated_at ascending.
+/// Without `since` the todo items updated in the last 24 hours are returned.
+pub async fn recent_todos(
+    State(store): State<Arc<dyn TodoStore>>,
+    Query(params): Query<RecentParams>,
+) -> Result<Json<Vec<Todo>>, AppError> {
+    let since = params.since.unwrap_or_else(|| Utc::now() - Duration::hours(24));
+    Ok(Json(store.updated_since(since.naive_utc()).await?))
+}
+
+/// poll_todos
+/// This function is the long-poll variant of recent_todos, for clients that cannot use the SSE stream.
+/// It returns the todo items updated after the required `since` query parameter right away if there are any.
+/// Otherwise it holds the request for up to `?wait=N` seconds (default 20, at most 25 and less than the
+/// request timeout, see PollWaitLimit) until a change event
+/// arrives, see events.rs, and returns the changed items, or an empty list once the wait is over.
+/// Deleted todos cannot be returned, so a deletion alone keeps the request waiting.
+pub async fn poll_todos(
+    State(store): State<Arc<dyn TodoStore>>,
+    State(events): State<EventSender>,
+    State(limit): State<PollWaitLimit>,
+    Query(params): Query<PollParams>,
+) -> Result<Json<Vec<Todo>>, AppError> {
+    let since = params.since.ok_or(AppError::BadRequest("since is required".into()))?;
+    let wait = std::time::Duration::from_secs(params.wait.unwrap_or(DEFAULT_POLL_WAIT_SECS)).min(limit.0);
+    let deadline = tokio::time::Instant::now() + wait;
+
+    // Subscribe before the first query, so a change made in between is not missed
+    let mut receiver = events.subscribe();
+    loop {
+        let todos = store.updated_since(since.naive_utc()).await?;
+        if !todos.is_empty() {
+            return Ok(Json(todos));
+        }
+
+        // Query again after every event, a lagged receiver missed some, which is also a change
+        match tokio::time::timeout_at(deadline, receiver.recv()).await {
+            Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+            Ok(Err(RecvError::Closed)) | Err(_) => return Ok(Json(Vec::new())),
+        }
+    }
+}
+
+/// daily_stats
+/// This function returns the number of created and completed todo items per day.
+/// It uses a date_trunc('day', created_at) GROUP BY query, so days without activity are omitted.
+/// It returns a Json list of DailyStats ordered by day.
+pub async fn daily_stats(State(pool): State<PgPool>) -> Result<Json<Vec<DailyStats>>, AppError> {
+    let stats = sqlx::query_as::<_, DailyStats>(
+        "SELECT date_trunc('day', created_at)::date AS day, \
+                COUNT(*) AS created, \
+                COUNT(*) FILTER (WHERE completed) AS completed \
+         FROM todos GROUP BY 1 ORDER BY 1",
+    )
+    .fetch_all(&pool)
+    .await?;
+    Ok(Json(stats))
+}
+
+/// completion_throughput
+/// This function returns the number of todo items completed per day over the last `?days=N` days (default 7),
+/// today included, counted by the day of their completed_at.
+/// The days come from generate_series, so days without completions are returned with 0.
+/// It returns a Json list of DailyThroughput ordered by day.
+pub async fn completion_throughput(
+    State(pool): State<PgPool>,
+    Query(params): Query<ThroughputParams>,
+) -> Result<Json<Vec<DailyThroughput>>, AppError> {
+    let days = params.days.unwrap_or(DEFAULT_THROUGHPUT_DAYS);
+    if !(1..=MAX_THROUGHPUT_DAYS).contains(&days) {
+        return Err(AppError::Validation(format!("days must be between 1 and {}", MAX_THROUGHPUT_DAYS)));
+    }
+
+    let throughput = sqlx::query_as::<_, DailyThroughput>(
+        "SELECT series.day::date AS day, COUNT(todos.id) AS completed \
+         FROM generate_series(current_date - ($1 - 1), current_date, interval '1 day') AS series(day) \
+         LEFT JOIN todos ON todos.completed_at::date = series.day::date \
+         GROUP BY 1 ORDER BY 1",
+    )
+    .bind(days)
+    .fetch_all(&pool)
+    .await?;
+    Ok(Json(throughput))
 }
 
 /// create_todo
@@ -44,43 +516,158 @@ pub async fn list_todos(State(pool): State<PgPool>) -> Json<Vec<Todo>> {
 /// It returns the created Todo item as a Json<Todo>.
 /// It expects a CreateTodo struct in the request body, which contains the title of the todo item.
 /// It generates a new UUID for the todo item and inserts it into the database.
+/// The title is validated and normalized before it is inserted.
+/// With `?unique=true` a title that already exists (case-insensitively) is rejected
+/// with a 409 Conflict naming the existing todo's id. By default duplicates are allowed.
+/// With a `parent_id` the todo is created as a subtask of that todo, which must exist and be top-level.
+/// It responds with 201 Created on success.
 pub async fn create_todo(
-    State(pool): State<PgPool>,
-    Json(payload): Json<CreateTodo>,
-) -> Json<Todo> {
-        let todo = sqlx::query_as::<_, Todo>("Insert into todos(id, title) values ($1, $2) returning *")
-        .bind(Uuid::new_v4())
-        .bind(payload.title)
-        .fetch_one(&pool)
-        .await
-        .unwrap();
-    Json(todo)
+    State(store): State<Arc<dyn TodoStore>>,
+    State(publisher): State<Arc<dyn EventPublisher>>,
+    Query(params): Query<CreateParams>,
+    AppJson(payload): AppJson<CreateTodo>,
+) -> Result<(StatusCode, Json<Todo>), AppError> {
+    let parent = match payload.parent_id {
+        Some(parent_id) => store.get(parent_id).await?,
+        None => None,
+    };
+    let tags = payload.tags.unwrap_or_default();
+    Validator::new()
+        .title(&payload.title)
+        .due_date(payload.due_date)
+        .tags(&tags)
+        .parent(payload.parent_id, parent.as_ref())
+        .finish()?;
+    let title = normalize_title(&payload.title);
+
+    // Look for an existing todo with the same title, ignoring case
+    if params.unique.unwrap_or(false)
+        && let Some(existing) = store.find_by_title(&title).await?
+    {
+        return Err(AppError::Conflict(format!("Task already exists with id {}", existing.id)));
+    }
+
+    let todo = store
+        .create(NewTodo {
+            title,
+            due_date: payload.due_date.map(|due| due.naive_utc()),
+            parent_id: payload.parent_id,
+            tags: normalize_tags(&tags),
+            recurrence: payload.recurrence,
+        })
+        .await?;
+
+    publisher.publish(TodoEvent::Created(todo.clone())).await;
+    Ok((StatusCode::CREATED, Json(todo)))
+}
+
+/// duplicate_todo
+/// This function creates a copy of an existing todo item, e.g. for recurring tasks.
+/// The copy gets the title, due date, tags and parent of the source, with a new id and timestamps,
+/// and starts open (completed=false) and not archived.
+/// It responds with 201 Created and the new Todo, or a Not Found error if the source does not exist.
+pub async fn duplicate_todo(
+    Path(id): Path<Uuid>,
+    State(store): State<Arc<dyn TodoStore>>,
+    State(publisher): State<Arc<dyn EventPublisher>>,
+) -> Result<(StatusCode, Json<Todo>), AppError> {
+    let source = store.get(id).await?.ok_or(AppError::NotFound("Task not found".into()))?;
+
+    let todo = store
+        .create(NewTodo {
+            title: source.title,
+            due_date: source.due_date,
+            parent_id: source.parent_id,
+            tags: source.tags,
+            recurrence: source.recurrence,
+        })
+        .await?;
+
+    publisher.publish(TodoEvent::Created(todo.clone())).await;
+    Ok((StatusCode::CREATED, Json(todo)))
 }
 
 /// get_todo by id
 /// This function retrieves a todo item by its ID from the database.
 /// It uses the SELECT SQL command to fetch the item.
-/// It returns a Json<Todo> if found, or a StatusCode::NOT_FOUND if the item does not exist.
-pub async fn get_todo(Path(id): Path<Uuid>, State(pool): State<PgPool>) -> Result<Json<Todo>, StatusCode>{ 
-    let todo = sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE id = $1")
-       .bind(id)
-       .fetch_optional(&pool)
-       .await
-       .unwrap();
+/// It returns a Json<Todo> if found, or a Not Found error if the item does not exist.
+pub async fn get_todo(Path(id): Path<Uuid>, State(store): State<Arc<dyn TodoStore>>) -> Result<Json<Todo>, AppError>{ 
+    let todo = store.get(id).await?;
+
+    todo.map(Json).ok_or(AppError::NotFound("Task not found".into()))
+}
+
+/// slugify
+/// This function normalizes a title into a slug used for human-friendly URLs.
+/// The title is lowercased and each run of whitespace is replaced by a single dash,
+/// so "Buy  Milk" becomes "buy-milk".
+pub fn slugify(title: &str) -> String {
+    title
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// get_todo_by_slug
+/// This function retrieves a todo item by its title slug from the database.
+/// The slug is compared against the titles normalized the same way as `slugify`,
+/// see TodoStore::find_by_slug.
+/// It returns a Json<Todo> on a unique match, a Not Found error if no todo matches,
+/// or a Conflict error if more than one todo matches the slug.
+pub async fn get_todo_by_slug(
+    Path(slug): Path<String>,
+    State(store): State<Arc<dyn TodoStore>>,
+) -> Result<Json<Todo>, AppError> {
+    let mut todos = store.find_by_slug(&slugify(&slug)).await?;
 
-    todo.map(Json).ok_or(StatusCode::NOT_FOUND)
+    match todos.len() {
+        0 => Err(AppError::NotFound("Task not found".into())),
+        1 => Ok(Json(todos.remove(0))),
+        _ => Err(AppError::Conflict(format!("{} tasks match the slug '{}'", todos.len(), slug))),
+    }
 }
 
 /// delete_todo
 /// This function deletes a todo item by its ID and returns a simple confirmation message.
 /// It uses the DELETE SQL command to remove the item from the database.
+/// A todo with subtasks is rejected with a 409 Conflict, or deleted together with its subtasks
+/// when SUBTASK_DELETE=cascade, see SubtaskDeletion.
 /// It returns a static string "Deleted" upon successful deletion.
-pub async fn delete_todo(Path(id): Path<Uuid>, State(pool): State<PgPool>) -> &'static str {
-    let _ = sqlx::query("DELETE FROM todos WHERE id = $1")
-        .bind(id)
-        .execute(&pool)
-        .await;
-    "Deleted"
+pub async fn delete_todo(
+    Path(id): Path<Uuid>,
+    State(store): State<Arc<dyn TodoStore>>,
+    State(publisher): State<Arc<dyn EventPublisher>>,
+    State(subtask_deletion): State<SubtaskDeletion>,
+) -> Result<&'static str, AppError> {
+    let subtasks = store.subtasks(id).await?;
+    if !subtasks.is_empty() && subtask_deletion == SubtaskDeletion::Block {
+        return Err(AppError::Conflict(format!(
+            "Task has {} subtask(s), delete them first",
+            subtasks.len()
+        )));
+    }
+
+    if store.delete(id).await? {
+        for subtask in subtasks {
+            publisher.publish(TodoEvent::Deleted(subtask.id)).await;
+        }
+        publisher.publish(TodoEvent::Deleted(id)).await;
+    }
+    Ok("Deleted")
+}
+
+/// list_subtasks
+/// This function retrieves the subtasks of a todo item, oldest first.
+/// It returns a Json Todo list, or a Not Found error if the todo item does not exist.
+pub async fn list_subtasks(
+    Path(id): Path<Uuid>,
+    State(store): State<Arc<dyn TodoStore>>,
+) -> Result<Json<Vec<Todo>>, AppError> {
+    if store.get(id).await?.is_none() {
+        return Err(AppError::NotFound("Task not found".into()));
+    }
+    Ok(Json(store.subtasks(id).await?))
 }
 
 /// update_todo
@@ -89,51 +676,1024 @@ pub async fn delete_todo(Path(id): Path<Uuid>, State(pool): State<PgPool>) -> &'
 /// It returns the updated Todo item as a Json<Todo>.
 pub async fn update_todo(
     Path(id): Path<Uuid>,
-    State(pool): State<PgPool>,
-    Json(payload): Json<UpdateTodo>,
-) -> Result<Json<Todo>, (StatusCode, String)> {
+    State(store): State<Arc<dyn TodoStore>>,
+    State(publisher): State<Arc<dyn EventPublisher>>,
+    AppJson(payload): AppJson<UpdateTodo>,
+) -> Result<Json<Todo>, AppError> {
+    // Validate the new fields, if any, before touching the database
+    let mut validator = Validator::new();
+    if let Some(title) = &payload.title {
+        validator.title(title);
+    }
+    if let Some(tags) = &payload.tags {
+        validator.tags(tags);
+    }
+    validator.due_date(payload.due_date).finish()?;
+
     // Fetch existing todo
-    // The SQL SELECT command is used to retrieve the existing todo item by its ID.
-    // The fetch_optional method returns an Option<Todo>, which is either Some(todo) 
+    // The store returns an Option<Todo>, which is either Some(todo)
     // if found or None if not found.
-    let existing = sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE id = $1")
-        .bind(id)
-        .fetch_optional(&pool)
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "database error".into()))?;
+    let existing = store.get(id).await?;
 
     // Check if the todo exists
     // If the todo item does not exist, return a Not Found error.
     // The Option<Todo> type is used to handle the case where the item might not be found.
     let existing = match existing {
         Some(todo) => todo,
-        None => return Err((StatusCode::NOT_FOUND, "Task not found".into())),
+        None => return Err(AppError::NotFound("Task not found".into())),
     };
 
     // Merge fields
     // If the payload has a title, use it; otherwise, keep the existing title.
     // If the payload has a completed status, use it; otherwise, keep the existing completed status.
     // This allows partial updates to the todo item.
-    let new_title = payload.title.unwrap_or(existing.title);
-    let new_completed = payload.completed.unwrap_or(existing.completed);
+    let changes = TodoChanges {
+        title: payload.title.map(|t| normalize_title(&t)).unwrap_or(existing.title),
+        completed: payload.completed.unwrap_or(existing.completed),
+        due_date: payload.due_date.map(|due| due.naive_utc()).or(existing.due_date),
+        tags: payload.tags.map(|tags| normalize_tags(&tags)).unwrap_or(existing.tags),
+        recurrence: payload.recurrence.or(existing.recurrence),
+    };
 
     // Update DB
-    // The SQL UPDATE command is used to modify the existing todo item in the database.
-    let updated = sqlx::query_as::<_, Todo>(
-        "UPDATE todos SET title = $1, completed = $2 WHERE id = $3 RETURNING *",
-    )
-    .bind(new_title)
-    .bind(new_completed)
-    .bind(id)
-    .fetch_one(&pool)
-    .await
-    // Handle errors during the update operation
-    // If the update fails, return an error with a status code and message. 
-    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Update failed".into()))?;
+    // The store writes the merged fields, a todo deleted in the meantime is Not Found.
+    // It also tells whether this update completed the todo, read under the same lock as the write,
+    // so concurrent requests completing the same todo create a single next occurrence.
+    // If the update fails, the error is converted into an AppError response.
+    let (updated, completed_now) = store
+        .update(id, changes)
+        .await?
+        .ok_or(AppError::NotFound("Task not found".into()))?;
+
+    publisher.publish(TodoEvent::Updated(updated.clone())).await;
+
+    // Completing a recurring todo creates its next occurrence, see recurrence.rs
+    if completed_now
+        && let Some(next) = next_occurrence(&updated, Utc::now().naive_utc())
+    {
+        let next = store.create(next).await?;
+        publisher.publish(TodoEvent::Created(next)).await;
+    }
 
     // Return the updated todo item as a JSON response
     // The Json<Todo> type is used to serialize the updated todo item into a JSON response.
     Ok(Json(updated))
 }
 
+/// update_todo_title
+/// This function updates only the title of an existing todo item by its ID.
+/// It writes only the title, skipping the SELECT and merge done by update_todo.
+/// It returns the updated Todo item as a Json<Todo>, or a Not Found error if the item does not exist.
+pub async fn update_todo_title(
+    Path(id): Path<Uuid>,
+    State(store): State<Arc<dyn TodoStore>>,
+    State(publisher): State<Arc<dyn EventPublisher>>,
+    AppJson(payload): AppJson<UpdateTitle>,
+) -> Result<Json<Todo>, AppError> {
+    Validator::new().title(&payload.title).finish()?;
+
+    let updated = store.set_title(id, &normalize_title(&payload.title)).await?;
+    let updated = updated.ok_or(AppError::NotFound("Task not found".into()))?;
+    publisher.publish(TodoEvent::Updated(updated.clone())).await;
+    Ok(Json(updated))
+}
+
+/// complete_todos
+/// This function sets the completed flag of many todo items at once, e.g. for "mark all done".
+/// The todos are updated at once by TodoStore::complete_many, ids that do not exist are skipped.
+/// completed_at is set for the todos that get completed and cleared for the reopened ones.
+/// Recurring todos that get completed get their next occurrence, see recurrence.rs.
+/// It returns the number of updated todo items as {"updated": n}.
+/// An empty id list is rejected with 422 Unprocessable Entity.
+pub async fn complete_todos(
+    State(store): State<Arc<dyn TodoStore>>,
+    State(publisher): State<Arc<dyn EventPublisher>>,
+    AppJson(payload): AppJson<CompleteTodos>,
+) -> Result<Json<Value>, AppError> {
+    if payload.ids.is_empty() {
+        return Err(AppError::Validation("ids must not be empty".into()));
+    }
+
+    let (updated, opened) = store.complete_many(&payload.ids, payload.completed).await?;
+
+    let count = updated.len();
+    let now = Utc::now().naive_utc();
+    for todo in updated {
+        let next = if payload.completed && opened.contains(&todo.id) {
+            next_occurrence(&todo, now)
+        } else {
+            None
+        };
+        publisher.publish(TodoEvent::Updated(todo)).await;
+        if let Some(next) = next {
+            let next = store.create(next).await?;
+            publisher.publish(TodoEvent::Created(next)).await;
+        }
+    }
+    Ok(Json(json!({ "updated": count })))
+}
+
+/// query_todos
+/// This function retrieves many todo items by id in one request, e.g. {"ids": [...]}.
+/// The todos are fetched at once with TodoStore::get_many and put back in the order of the ids.
+/// Ids that do not exist are left out, an id listed twice is returned once, at its first position.
+/// An empty id list returns an empty list.
+pub async fn query_todos(
+    State(store): State<Arc<dyn TodoStore>>,
+    AppJson(payload): AppJson<QueryTodos>,
+) -> Result<Json<Vec<Todo>>, AppError> {
+    if payload.ids.is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+
+    let todos = store.get_many(&payload.ids).await?;
+
+    let mut by_id: HashMap<Uuid, Todo> = todos.into_iter().map(|todo| (todo.id, todo)).collect();
+    let ordered = payload.ids.iter().filter_map(|id| by_id.remove(id)).collect();
+    Ok(Json(ordered))
+}
+
+/// archive_todo
+/// This function archives a todo item by its ID, setting its archived_at timestamp.
+/// Archived todos are hidden from the default list but not deleted, see restore_todo.
+/// Archiving an already archived todo keeps the original timestamp.
+/// It returns the archived Todo item as a Json<Todo>, or a Not Found error if the item does not exist.
+pub async fn archive_todo(
+    Path(id): Path<Uuid>,
+    State(store): State<Arc<dyn TodoStore>>,
+    State(publisher): State<Arc<dyn EventPublisher>>,
+) -> Result<Json<Todo>, AppError> {
+    let archived = store.set_archived(id, true).await?;
+    let archived = archived.ok_or(AppError::NotFound("Task not found".into()))?;
+    publisher.publish(TodoEvent::Updated(archived.clone())).await;
+    Ok(Json(archived))
+}
+
+/// restore_todo
+/// This function restores an archived todo item by its ID, clearing its archived_at timestamp.
+/// It returns the restored Todo item as a Json<Todo>, or a Not Found error if the item does not exist.
+pub async fn restore_todo(
+    Path(id): Path<Uuid>,
+    State(store): State<Arc<dyn TodoStore>>,
+    State(publisher): State<Arc<dyn EventPublisher>>,
+) -> Result<Json<Todo>, AppError> {
+    let restored = store.set_archived(id, false).await?;
+    let restored = restored.ok_or(AppError::NotFound("Task not found".into()))?;
+    publisher.publish(TodoEvent::Updated(restored.clone())).await;
+    Ok(Json(restored))
+}
+
+/// todo_stream
+/// This function streams todo changes to the client as server-sent events.
+/// Every created, updated or deleted todo produces one event, see events.rs.
+/// Clients only receive the events that happen after they connect,
+/// a client that falls too far behind skips the events it missed.
+pub async fn todo_stream(State(events): State<EventSender>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = stream::unfold(events.subscribe(), |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => match event.to_sse() {
+                    Ok(sse) => return Some((Ok(sse), receiver)),
+                    Err(_) => continue,
+                },
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use crate::error::PROBLEM_JSON;
+    use crate::events::MemEventPublisher;
+    use crate::store::MemTodoStore;
+    use crate::tests::{send, test_router};
+    use futures_util::StreamExt;
+    use tower::ServiceExt;
+
+    // JSON name of a todo field, camelCase with the camel-case feature
+    fn field(name: &str) -> String {
+        if !cfg!(feature = "camel-case") {
+            return name.to_string();
+        }
+        let mut parts = name.split('_');
+        let mut camel = parts.next().unwrap_or_default().to_string();
+        for part in parts {
+            let mut chars = part.chars();
+            camel.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+            camel.push_str(chars.as_str());
+        }
+        camel
+    }
+
+    // Create a todo through the API, returning it as JSON
+    async fn create(store: &Arc<MemTodoStore>, body: &str) -> Value {
+        let (status, todo) = send(test_router(store.clone(), false), Method::POST, "/todos", body).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", todo);
+        todo
+    }
+
+    async fn get(store: &Arc<MemTodoStore>, uri: &str) -> (StatusCode, Value) {
+        send(test_router(store.clone(), false), Method::GET, uri, "").await
+    }
+
+    #[tokio::test]
+    async fn todo_is_found_by_its_title_slug() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "Buy  Milk"}"#).await;
+        create(&store, r#"{"title": "Walk the dog"}"#).await;
+
+        let (status, body) = get(&store, "/todos/by-title/buy-milk").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], todo["id"]);
+
+        let (status, body) = get(&store, "/todos/by-title/Buy%20Milk").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], todo["id"]);
+    }
+
+    #[tokio::test]
+    async fn unknown_slug_is_404() {
+        let store = Arc::new(MemTodoStore::default());
+        create(&store, r#"{"title": "Buy milk"}"#).await;
+
+        let (status, body) = get(&store, "/todos/by-title/buy-bread").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["detail"], "Task not found");
+    }
+
+    #[tokio::test]
+    async fn ambiguous_slug_is_409() {
+        let store = Arc::new(MemTodoStore::default());
+        create(&store, r#"{"title": "Buy milk"}"#).await;
+        create(&store, r#"{"title": "buy MILK"}"#).await;
+
+        let (status, body) = get(&store, "/todos/by-title/buy-milk").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["detail"], "2 tasks match the slug 'buy-milk'");
+    }
+
+    #[tokio::test]
+    async fn patch_title_changes_only_the_title() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "Buy milk", "tags": ["home"]}"#).await;
+        let uri = format!("/todos/{}/title", todo["id"].as_str().unwrap());
+
+        let (status, body) = send(test_router(store.clone(), false), Method::PATCH, &uri, r#"{"title": " Buy  bread "}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["title"], "Buy bread");
+        assert_eq!(body["tags"], todo["tags"]);
+        assert_eq!(body["completed"], todo["completed"]);
+        assert_eq!(store.todos.lock().unwrap()[0].title, "Buy bread");
+    }
+
+    #[tokio::test]
+    async fn patch_title_rejects_an_empty_title_and_unknown_todos() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "Buy milk"}"#).await;
+        let uri = format!("/todos/{}/title", todo["id"].as_str().unwrap());
+
+        let (status, _) = send(test_router(store.clone(), false), Method::PATCH, &uri, r#"{"title": "  "}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(store.todos.lock().unwrap()[0].title, "Buy milk");
+
+        let uri = format!("/todos/{}/title", Uuid::new_v4());
+        let (status, _) = send(test_router(store, false), Method::PATCH, &uri, r#"{"title": "x"}"#).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn titles_are_normalized_on_create_and_update() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "  buy \t milk  "}"#).await;
+        assert_eq!(todo["title"], "buy milk");
+
+        let uri = format!("/todos/{}", todo["id"].as_str().unwrap());
+        let (status, body) = send(test_router(store, false), Method::PUT, &uri, r#"{"title": "\n water   plants "}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["title"], "water plants");
+    }
+
+    #[tokio::test]
+    async fn whitespace_only_title_is_rejected_on_update() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "buy milk"}"#).await;
+
+        let uri = format!("/todos/{}", todo["id"].as_str().unwrap());
+        let (status, body) = send(test_router(store.clone(), false), Method::PUT, &uri, r#"{"title": " \t "}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "title");
+        assert_eq!(store.todos.lock().unwrap()[0].title, "buy milk");
+    }
+
+    #[tokio::test]
+    async fn unknown_payload_fields_are_422_naming_the_field() {
+        let store = Arc::new(MemTodoStore::default());
+
+        let (status, body) = send(test_router(store.clone(), false), Method::POST, "/todos", r#"{"titel": "x"}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["detail"].as_str().unwrap().contains("unknown field `titel`"), "{}", body);
+        assert!(store.todos.lock().unwrap().is_empty());
+
+        let todo = create(&store, r#"{"title": "x", "tags": []}"#).await;
+        let uri = format!("/todos/{}", todo["id"].as_str().unwrap());
+        let (status, body) = send(test_router(store, false), Method::PUT, &uri, r#"{"done": true}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["detail"].as_str().unwrap().contains("unknown field `done`"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_400_and_a_missing_content_type_415() {
+        let store = Arc::new(MemTodoStore::default());
+
+        let (status, body) = send(test_router(store.clone(), false), Method::POST, "/todos", r#"{"title": "x""#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["status"], 400);
+        assert!(body["detail"].is_string());
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/todos")
+            .body(Body::from(r#"{"title": "x"}"#))
+            .unwrap();
+        let response = test_router(store.clone(), false).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+        assert!(store.todos.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unique_create_rejects_a_title_that_exists_ignoring_case() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "Buy milk"}"#).await;
+
+        let router = test_router(store.clone(), false);
+        let (status, body) = send(router, Method::POST, "/todos?unique=true", r#"{"title": "BUY  MILK"}"#).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["detail"], format!("Task already exists with id {}", todo["id"].as_str().unwrap()));
+        assert_eq!(store.todos.lock().unwrap().len(), 1);
+
+        let router = test_router(store.clone(), false);
+        let (status, _) = send(router, Method::POST, "/todos?unique=true", r#"{"title": "Buy bread"}"#).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn duplicate_titles_are_allowed_by_default() {
+        let store = Arc::new(MemTodoStore::default());
+        create(&store, r#"{"title": "Buy milk"}"#).await;
+        create(&store, r#"{"title": "buy milk"}"#).await;
+        assert_eq!(store.todos.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn recent_lists_the_todos_updated_after_since_oldest_first() {
+        let store = Arc::new(MemTodoStore::default());
+        let first = create(&store, r#"{"title": "first"}"#).await;
+        let second = create(&store, r#"{"title": "second"}"#).await;
+        let uri = format!("/todos/{}/title", first["id"].as_str().unwrap());
+        send(test_router(store.clone(), false), Method::PATCH, &uri, r#"{"title": "first again"}"#).await;
+
+        let (status, body) = get(&store, "/todos/recent?since=2000-01-01T00:00:00Z").await;
+        assert_eq!(status, StatusCode::OK);
+        let ids: Vec<&Value> = body.as_array().unwrap().iter().map(|todo| &todo["id"]).collect();
+        assert_eq!(ids, vec![&second["id"], &first["id"]]);
+
+        let (status, body) = get(&store, "/todos/recent?since=2999-01-01T00:00:00Z").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!([]));
+
+        // Without since the last 24 hours are listed
+        let (_, body) = get(&store, "/todos/recent").await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn recent_rejects_an_invalid_since() {
+        let store = Arc::new(MemTodoStore::default());
+        let (status, _) = get(&store, "/todos/recent?since=yesterday").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    async fn insert(pool: &PgPool, created_at: &str, completed_at: Option<&str>) {
+        sqlx::query(
+            "INSERT INTO todos (id, title, created_at, completed, completed_at) \
+             VALUES ($1, 'x', $2::timestamp, $3::timestamp IS NOT NULL, $3::timestamp)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(created_at)
+        .bind(completed_at)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore]
+    async fn daily_stats_count_created_and_completed_todos_per_day(pool: PgPool) {
+        insert(&pool, "2024-03-01 09:00", None).await;
+        insert(&pool, "2024-03-01 23:59", Some("2024-03-02 10:00")).await;
+        insert(&pool, "2024-03-03 00:00", Some("2024-03-03 12:00")).await;
+
+        let Json(stats) = daily_stats(State(pool)).await.unwrap();
+        let stats: Vec<(String, i64, i64)> = stats
+            .iter()
+            .map(|day| (day.day.to_string(), day.created, day.completed))
+            .collect();
+        assert_eq!(stats, vec![("2024-03-01".to_string(), 2, 1), ("2024-03-03".to_string(), 1, 1)]);
+    }
+
+    #[tokio::test]
+    async fn created_todo_is_streamed_as_an_sse_message() {
+        let store = Arc::new(MemTodoStore::default());
+        let publisher = Arc::new(MemEventPublisher::default());
+        let state = crate::tests::test_state_with(store.clone(), publisher.clone());
+        let events = state.events.clone();
+        let router = crate::build_router(state, false);
+
+        let request = Request::builder().uri("/todos/stream").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/event-stream");
+
+        // The listener of events.rs forwards the published event to the SSE clients
+        let (_, todo) = send(router, Method::POST, "/todos", r#"{"title": "buy milk"}"#).await;
+        let event = publisher.events.lock().unwrap().remove(0);
+        events.send(event).unwrap();
+
+        let mut body = response.into_body().into_data_stream();
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), body.next()).await.unwrap().unwrap().unwrap();
+        let frame = String::from_utf8(frame.to_vec()).unwrap();
+        assert!(frame.starts_with("event: created\n"), "{}", frame);
+        let data = frame.lines().find_map(|line| line.strip_prefix("data: ")).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(data).unwrap(), todo);
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_compressed_when_the_client_accepts_it() {
+        let store = Arc::new(MemTodoStore::default());
+        for i in 0..20 {
+            create(&store, &format!(r#"{{"title": "todo number {}"}}"#, i)).await;
+        }
+        let router = test_router(store, false).layer(crate::compression_layer());
+        let request = |uri: &str, encoding: Option<&str>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(encoding) = encoding {
+                request = request.header("accept-encoding", encoding);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = router.clone().oneshot(request("/todos", Some("gzip"))).await.unwrap();
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+
+        let response = router.clone().oneshot(request("/todos", None)).await.unwrap();
+        assert!(response.headers().get("content-encoding").is_none());
+
+        // Below COMPRESSION_MIN_SIZE the body is sent as is
+        let response = router.oneshot(request("/health", Some("gzip"))).await.unwrap();
+        assert!(response.headers().get("content-encoding").is_none());
+    }
+
+    fn ids(list: &Value) -> Vec<&Value> {
+        list.as_array().unwrap().iter().map(|todo| &todo["id"]).collect()
+    }
+
+    #[tokio::test]
+    async fn archived_todos_move_between_the_lists_and_back() {
+        let store = Arc::new(MemTodoStore::default());
+        let kept = create(&store, r#"{"title": "kept"}"#).await;
+        let todo = create(&store, r#"{"title": "archived"}"#).await;
+        let id = todo["id"].as_str().unwrap();
+
+        let uri = format!("/todos/{}/archive", id);
+        let (status, archived) = send(test_router(store.clone(), false), Method::POST, &uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(archived[&field("archived_at")].is_string());
+        assert_eq!(ids(&get(&store, "/todos").await.1), vec![&kept["id"]]);
+        assert_eq!(ids(&get(&store, "/todos?archived=true").await.1), vec![&todo["id"]]);
+
+        // Archiving again keeps the original timestamp
+        let (_, again) = send(test_router(store.clone(), false), Method::POST, &uri, "").await;
+        assert_eq!(again[&field("archived_at")], archived[&field("archived_at")]);
+
+        let uri = format!("/todos/{}/restore", id);
+        let (status, restored) = send(test_router(store.clone(), false), Method::POST, &uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(restored[&field("archived_at")].is_null());
+        assert_eq!(ids(&get(&store, "/todos").await.1).len(), 2);
+        assert_eq!(get(&store, "/todos?archived=true").await.1, json!([]));
+    }
+
+    #[tokio::test]
+    async fn archiving_an_unknown_todo_is_404() {
+        let store = Arc::new(MemTodoStore::default());
+        for action in ["archive", "restore"] {
+            let uri = format!("/todos/{}/{}", Uuid::new_v4(), action);
+            let (status, _) = send(test_router(store.clone(), false), Method::POST, &uri, "").await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_carry_the_total_count_and_links() {
+        let store = Arc::new(MemTodoStore::default());
+        for i in 0..5 {
+            create(&store, &format!(r#"{{"title": "todo {}", "tags": ["work"]}}"#, i)).await;
+        }
+
+        let request = Request::builder().uri("/todos?tag=work&page=2&per_page=2").body(Body::empty()).unwrap();
+        let response = test_router(store.clone(), false).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-total-count"], "5");
+        assert_eq!(response.headers()["x-page"], "2");
+        assert_eq!(
+            response.headers()[LINK],
+            "</todos?tag=work&page=1&per_page=2>; rel=\"first\", \
+             </todos?tag=work&page=1&per_page=2>; rel=\"prev\", \
+             </todos?tag=work&page=3&per_page=2>; rel=\"next\", \
+             </todos?tag=work&page=3&per_page=2>; rel=\"last\""
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unpaged_list_has_no_pagination_headers() {
+        let store = Arc::new(MemTodoStore::default());
+        create(&store, r#"{"title": "todo"}"#).await;
 
+        let request = Request::builder().uri("/todos").body(Body::empty()).unwrap();
+        let response = test_router(store, false).oneshot(request).await.unwrap();
+        assert!(response.headers().get("x-total-count").is_none());
+        assert!(response.headers().get(LINK).is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_page_parameters_are_422() {
+        let store = Arc::new(MemTodoStore::default());
+        for uri in ["/todos?page=0", "/todos?per_page=0", "/todos?per_page=101"] {
+            let (status, _) = get(&store, uri).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn fields_select_the_returned_todo_fields() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "buy milk", "tags": ["home"]}"#).await;
+
+        let uri = format!("/todos?fields=id,{}", field("created_at"));
+        let (status, body) = get(&store, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        let mut expected = Map::new();
+        expected.insert("id".to_string(), todo["id"].clone());
+        expected.insert(field("created_at"), todo[&field("created_at")].clone());
+        assert_eq!(body, json!([expected]));
+    }
+
+    #[tokio::test]
+    async fn unknown_field_is_400() {
+        let store = Arc::new(MemTodoStore::default());
+        let (status, body) = get(&store, "/todos?fields=id,owner").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["detail"].as_str().unwrap().starts_with("Unknown field 'owner'"));
+    }
+
+    #[tokio::test]
+    async fn complete_many_updates_the_listed_todos_and_skips_unknown_ids() {
+        let store = Arc::new(MemTodoStore::default());
+        let first = create(&store, r#"{"title": "first"}"#).await;
+        let second = create(&store, r#"{"title": "second"}"#).await;
+        let other = create(&store, r#"{"title": "other"}"#).await;
+
+        let body = json!({ "ids": [first["id"], second["id"], Uuid::new_v4()], "completed": true }).to_string();
+        let (status, result) = send(test_router(store.clone(), false), Method::POST, "/todos/complete", &body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result, json!({ "updated": 2 }));
+        for todo in store.todos.lock().unwrap().iter() {
+            assert_eq!(todo.completed, todo.id.to_string() != other["id"]);
+        }
+
+        let body = json!({ "ids": [first["id"]], "completed": false }).to_string();
+        let (_, result) = send(test_router(store.clone(), false), Method::POST, "/todos/complete", &body).await;
+        assert_eq!(result, json!({ "updated": 1 }));
+        assert!(!store.todos.lock().unwrap()[0].completed);
+    }
+
+    #[tokio::test]
+    async fn complete_many_rejects_an_empty_id_list() {
+        let store = Arc::new(MemTodoStore::default());
+        let body = r#"{"ids": [], "completed": true}"#;
+        let (status, _) = send(test_router(store, false), Method::POST, "/todos/complete", body).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore]
+    async fn query_over_the_statement_timeout_is_504(pool: PgPool) {
+        let slow_pool = crate::db::pool_options(100)
+            .connect_with((*pool.connect_options()).clone())
+            .await
+            .unwrap();
+
+        // Waiting for the lock counts towards the statement timeout
+        let mut lock = pool.begin().await.unwrap();
+        sqlx::query("LOCK TABLE todos IN ACCESS EXCLUSIVE MODE").execute(&mut *lock).await.unwrap();
+
+        let err = daily_stats(State(slow_pool)).await.unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["detail"], "database query timed out");
+        lock.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_is_csv_when_the_client_accepts_it() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "buy milk, bread"}"#).await;
+
+        let request = Request::builder()
+            .uri("/todos?fields=id,title")
+            .header(ACCEPT, "text/csv, application/json;q=0.5")
+            .body(Body::empty())
+            .unwrap();
+        let response = test_router(store.clone(), false).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/csv; charset=utf-8");
+        assert_eq!(response.headers()[VARY], "accept");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let expected = format!("id,title\r\n{},\"buy milk, bread\"\r\n", todo["id"].as_str().unwrap());
+        assert_eq!(String::from_utf8(bytes.to_vec()).unwrap(), expected);
+
+        for accept in ["application/json", "*/*"] {
+            let request = Request::builder().uri("/todos").header(ACCEPT, accept).body(Body::empty()).unwrap();
+            let response = test_router(store.clone(), false).oneshot(request).await.unwrap();
+            assert_eq!(response.headers()[CONTENT_TYPE], "application/json", "{}", accept);
+        }
+    }
+
+    #[tokio::test]
+    async fn health_reports_the_last_state_of_the_monitor() {
+        let state = crate::tests::test_state(Arc::new(MemTodoStore::default()));
+        let health = state.health.clone();
+        let router = crate::build_router(state, false);
+
+        let (status, body) = send(router.clone(), Method::GET, "/health", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "status": "ok" }));
+
+        assert!(health.set_healthy(false));
+        let (status, body) = send(router.clone(), Method::GET, "/health", "").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, json!({ "status": "unavailable" }));
+
+        assert!(health.set_healthy(true));
+        let (status, _) = send(router, Method::GET, "/health", "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn wrong_method_is_405_problem_json_with_allow() {
+        let store = Arc::new(MemTodoStore::default());
+        let request = Request::builder().method(Method::PATCH).uri("/todos").body(Body::empty()).unwrap();
+
+        let response = test_router(store, false).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+        let allow = response.headers()["allow"].to_str().unwrap().to_string();
+        let mut allow: Vec<&str> = allow.split(',').map(str::trim).collect();
+        allow.sort();
+        assert_eq!(allow, vec!["GET", "HEAD", "POST"]);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["status"], 405);
+        assert_eq!(body["title"], "Method Not Allowed");
+        assert_eq!(body["detail"], "Method PATCH is not allowed for /todos");
+    }
+
+    #[tokio::test]
+    async fn unknown_path_is_404_problem_json_naming_the_path() {
+        let store = Arc::new(MemTodoStore::default());
+        let request = Request::builder().uri("/todoz/1?x=2").body(Body::empty()).unwrap();
+
+        let response = test_router(store, false).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            json!({ "type": "about:blank", "title": "Not Found", "status": 404, "detail": "not found", "path": "/todoz/1" })
+        );
+    }
+
+    #[tokio::test]
+    async fn poll_returns_existing_changes_right_away() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "buy milk"}"#).await;
+
+        let (status, body) = get(&store, "/todos/poll?since=2000-01-01T00:00:00Z&wait=25").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!([todo]));
+    }
+
+    #[tokio::test]
+    async fn poll_waits_for_a_change_event() {
+        let store = Arc::new(MemTodoStore::default());
+        let publisher = Arc::new(MemEventPublisher::default());
+        let state = crate::tests::test_state_with(store.clone(), publisher.clone());
+        let events = state.events.clone();
+        let router = crate::build_router(state, false);
+
+        let since = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        let uri = format!("/todos/poll?since={}&wait=5", utf8_percent_encode(&since, NON_ALPHANUMERIC));
+        let poll_router = router.clone();
+        let poll = tokio::spawn(async move { send(poll_router, Method::GET, &uri, "").await });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!poll.is_finished());
+
+        let (_, todo) = send(router, Method::POST, "/todos", r#"{"title": "buy milk"}"#).await;
+        let event = publisher.events.lock().unwrap().remove(0);
+        events.send(event).unwrap();
+
+        let (status, body) = tokio::time::timeout(std::time::Duration::from_secs(2), poll).await.unwrap().unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!([todo]));
+    }
+
+    #[tokio::test]
+    async fn poll_without_changes_returns_an_empty_list() {
+        let store = Arc::new(MemTodoStore::default());
+        let (status, body) = get(&store, "/todos/poll?since=2000-01-01T00:00:00Z&wait=0").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn poll_requires_since() {
+        let store = Arc::new(MemTodoStore::default());
+        let (status, body) = get(&store, "/todos/poll").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["detail"], "since is required");
+    }
+
+    #[tokio::test]
+    async fn subtasks_are_created_and_listed_under_their_parent() {
+        let store = Arc::new(MemTodoStore::default());
+        let parent = create(&store, r#"{"title": "move house"}"#).await;
+        let subtask = |title: &str, parent: &Value| json!({ "title": title, field("parent_id"): parent["id"] }).to_string();
+        let first = create(&store, &subtask("pack books", &parent)).await;
+        let second = create(&store, &subtask("pack dishes", &parent)).await;
+        assert_eq!(first[&field("parent_id")], parent["id"]);
+
+        let uri = format!("/todos/{}/subtasks", parent["id"].as_str().unwrap());
+        let (status, body) = get(&store, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&body), vec![&first["id"], &second["id"]]);
+
+        // Subtasks are nested one level, and the parent must exist
+        for parent in [&first, &json!({ "id": Uuid::new_v4() })] {
+            let (status, body) = send(test_router(store.clone(), false), Method::POST, "/todos", &subtask("x", parent)).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(body["errors"][0]["field"], field("parent_id"));
+        }
+
+        let (status, _) = get(&store, &format!("/todos/{}/subtasks", Uuid::new_v4())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_parent_is_blocked_or_cascades() {
+        let store = Arc::new(MemTodoStore::default());
+        let parent = create(&store, r#"{"title": "move house"}"#).await;
+        let body = json!({ "title": "pack books", field("parent_id"): parent["id"] }).to_string();
+        create(&store, &body).await;
+        let uri = format!("/todos/{}", parent["id"].as_str().unwrap());
+
+        let (status, body) = send(test_router(store.clone(), false), Method::DELETE, &uri, "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["detail"], "Task has 1 subtask(s), delete them first");
+        assert_eq!(store.todos.lock().unwrap().len(), 2);
+
+        let publisher = Arc::new(MemEventPublisher::default());
+        let mut state = crate::tests::test_state_with(store.clone(), publisher.clone());
+        state.subtask_deletion = SubtaskDeletion::Cascade;
+        let (status, _) = send(crate::build_router(state, false), Method::DELETE, &uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(store.todos.lock().unwrap().is_empty());
+        assert_eq!(publisher.events.lock().unwrap().iter().filter(|event| matches!(event, TodoEvent::Deleted(_))).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn tags_are_normalized_and_filter_the_list() {
+        let store = Arc::new(MemTodoStore::default());
+        let work = create(&store, r#"{"title": "report", "tags": [" Work", "work", "Urgent "]}"#).await;
+        let home = create(&store, r#"{"title": "dishes", "tags": ["home"]}"#).await;
+        assert_eq!(work["tags"], json!(["work", "urgent"]));
+
+        assert_eq!(ids(&get(&store, "/todos?tag=WORK").await.1), vec![&work["id"]]);
+        assert_eq!(ids(&get(&store, "/todos?tag=home").await.1), vec![&home["id"]]);
+        assert_eq!(get(&store, "/todos?tag=garden").await.1, json!([]));
+
+        // Updating the tags replaces them
+        let uri = format!("/todos/{}", home["id"].as_str().unwrap());
+        let (status, body) = send(test_router(store.clone(), false), Method::PUT, &uri, r#"{"tags": ["Work"]}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["tags"], json!(["work"]));
+        assert_eq!(ids(&get(&store, "/todos?tag=work").await.1).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn empty_tag_is_rejected() {
+        let store = Arc::new(MemTodoStore::default());
+        let (status, body) = send(test_router(store, false), Method::POST, "/todos", r#"{"title": "x", "tags": [" "]}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "tags");
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed_and_added_to_error_bodies() {
+        let store = Arc::new(MemTodoStore::default());
+        let router = crate::with_request_id(test_router(store.clone(), false));
+        let missing = format!("/todos/{}", Uuid::new_v4());
+
+        let request = Request::builder().uri(&missing).header("x-request-id", "abc-123").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["request_id"], "abc-123");
+        assert_eq!(body["detail"], "Task not found");
+
+        // Without the header an id is generated
+        let request = Request::builder().uri(&missing).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let generated = response.headers()["x-request-id"].to_str().unwrap().to_string();
+        assert!(Uuid::parse_str(&generated).is_ok());
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["request_id"], generated);
+
+        // Successful responses keep their body
+        let (status, body) = send(router, Method::GET, "/todos", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn query_returns_the_todos_in_the_order_of_the_ids() {
+        let store = Arc::new(MemTodoStore::default());
+        let first = create(&store, r#"{"title": "first"}"#).await;
+        let second = create(&store, r#"{"title": "second"}"#).await;
+        create(&store, r#"{"title": "not asked"}"#).await;
+
+        let body = json!({ "ids": [second["id"], Uuid::new_v4(), first["id"], second["id"]] }).to_string();
+        let (status, result) = send(test_router(store.clone(), false), Method::POST, "/todos/query", &body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result, json!([second, first]));
+
+        let (status, _) = send(test_router(store, false), Method::POST, "/todos/query", r#"{"ids": ["nope"]}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn completed_at_is_set_on_completion_and_cleared_on_reopening() {
+        let store = Arc::new(MemTodoStore::default());
+        let todo = create(&store, r#"{"title": "buy milk"}"#).await;
+        assert!(todo[&field("completed_at")].is_null());
+        let uri = format!("/todos/{}", todo["id"].as_str().unwrap());
+        let put = |body: &'static str| send(test_router(store.clone(), false), Method::PUT, &uri, body);
+
+        let (_, completed) = put(r#"{"completed": true}"#).await;
+        assert!(completed[&field("completed_at")].is_string());
+
+        // Completing again keeps the first completion time
+        let (_, again) = put(r#"{"completed": true, "title": "buy oat milk"}"#).await;
+        assert_eq!(again[&field("completed_at")], completed[&field("completed_at")]);
+
+        let (_, reopened) = put(r#"{"completed": false}"#).await;
+        assert!(reopened[&field("completed_at")].is_null());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore]
+    async fn throughput_counts_completions_per_day_including_empty_days(pool: PgPool) {
+        for days_ago in [0, 0, 2, 5] {
+            sqlx::query(
+                "INSERT INTO todos (id, title, completed, completed_at) \
+                 VALUES ($1, 'x', true, current_date - $2::int + time '10:00')",
+            )
+            .bind(Uuid::new_v4())
+            .bind(days_ago)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let today: chrono::NaiveDate = sqlx::query_scalar("SELECT current_date").fetch_one(&pool).await.unwrap();
+        let params = ThroughputParams { days: Some(3) };
+        let Json(throughput) = completion_throughput(State(pool), Query(params)).await.unwrap();
+        let counts: Vec<(i64, i64)> = throughput
+            .iter()
+            .map(|day| ((today - day.day).num_days(), day.completed))
+            .collect();
+        assert_eq!(counts, vec![(2, 1), (1, 0), (0, 2)]);
+    }
+
+    #[tokio::test]
+    async fn throughput_rejects_a_window_out_of_range() {
+        let store = Arc::new(MemTodoStore::default());
+        for uri in ["/todos/analytics/throughput?days=0", "/todos/analytics/throughput?days=367"] {
+            let (status, _) = get(&store, uri).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn todo_json_uses_the_configured_field_names() {
+        let store = Arc::new(MemTodoStore::default());
+        let body = json!({ "title": "buy milk", field("due_date"): "2030-01-01T10:00:00Z" }).to_string();
+        let todo = create(&store, &body).await;
+
+        let mut keys: Vec<&str> = todo.as_object().unwrap().keys().map(String::as_str).collect();
+        let mut expected = TODO_FIELDS.to_vec();
+        keys.sort();
+        expected.sort();
+        assert_eq!(keys, expected);
+        assert_eq!(todo[&field("due_date")], "2030-01-01T10:00:00");
+    }
+
+    #[cfg(feature = "camel-case")]
+    #[tokio::test]
+    async fn snake_case_payload_fields_are_rejected_with_camel_case() {
+        let store = Arc::new(MemTodoStore::default());
+        let body = r#"{"title": "x", "due_date": "2030-01-01T10:00:00Z"}"#;
+        let (status, body) = send(test_router(store, false), Method::POST, "/todos", body).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["detail"].as_str().unwrap().contains("unknown field `due_date`"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn duplicate_copies_the_todo_as_a_new_open_todo() {
+        let store = Arc::new(MemTodoStore::default());
+        let source = create(&store, r#"{"title": "water plants", "tags": ["home"]}"#).await;
+        let id = source["id"].as_str().unwrap();
+        send(test_router(store.clone(), false), Method::PUT, &format!("/todos/{}", id), r#"{"completed": true}"#).await;
+
+        let uri = format!("/todos/{}/duplicate", id);
+        let (status, copy) = send(test_router(store.clone(), false), Method::POST, &uri, "").await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_ne!(copy["id"], source["id"]);
+        assert_eq!(copy["title"], "water plants");
+        assert_eq!(copy["tags"], json!(["home"]));
+        assert_eq!(copy["completed"], false);
+        assert_eq!(store.todos.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn duplicate_route_answers_errors_with_problem_json() {
+        let store = Arc::new(MemTodoStore::default());
+        let cases = [
+            (Method::POST, format!("/todos/{}/duplicate", Uuid::new_v4()), StatusCode::NOT_FOUND, "Task not found"),
+            (Method::GET, format!("/todos/{}/duplicate", Uuid::new_v4()), StatusCode::METHOD_NOT_ALLOWED, "Method GET is not allowed"),
+            (Method::POST, format!("/todos/{}/copy", Uuid::new_v4()), StatusCode::NOT_FOUND, "not found"),
+        ];
+        for (method, uri, status, detail) in cases {
+            let request = Request::builder().method(method).uri(&uri).body(Body::empty()).unwrap();
+            let response = test_router(store.clone(), false).oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{}", uri);
+            assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["status"], status.as_u16());
+            assert!(body["detail"].as_str().unwrap().starts_with(detail), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn completing_a_recurring_todo_creates_the_next_occurrence_once() {
+        let store = Arc::new(MemTodoStore::default());
+        let body = json!({ "title": "water plants", field("due_date"): "2030-01-01T10:00:00Z", "recurrence": "daily" });
+        let todo = create(&store, &body.to_string()).await;
+        let uri = format!("/todos/{}", todo["id"].as_str().unwrap());
+
+        let (status, _) = send(test_router(store.clone(), false), Method::PUT, &uri, r#"{"completed": true}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(store.todos.lock().unwrap().len(), 2);
+
+        // The todo is already completed, so completing it again creates nothing
+        let (status, _) = send(test_router(store.clone(), false), Method::PUT, &uri, r#"{"completed": true}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(store.todos.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,65 @@
+use sqlx::PgPool;
+use std::env;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+// This module provides the database health monitor for the todo application.
+// A background task pings the database with SELECT 1 every HEALTH_CHECK_INTERVAL_SECS
+// seconds (default 10) and caches the result, so GET /health answers from memory
+// instead of querying the database on every probe.
+
+#[derive(Debug, Clone)]
+pub struct Health {
+    healthy: Arc<AtomicBool>,
+}
+
+impl Health {
+    /// The database counts as healthy until the first ping says otherwise
+    pub fn new() -> Self {
+        Health { healthy: Arc::new(AtomicBool::new(true)) }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Store the result of a ping, returning whether the state changed
+    pub fn set_healthy(&self, healthy: bool) -> bool {
+        self.healthy.swap(healthy, Ordering::Relaxed) != healthy
+    }
+}
+
+/// spawn_health_monitor
+/// This function spawns the periodic database ping on the tokio runtime.
+/// Only changes are logged, i.e. when the database becomes unreachable and when it recovers.
+pub fn spawn_health_monitor(pool: PgPool, health: Health) -> JoinHandle<()> {
+    let interval_secs = env::var("HEALTH_CHECK_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(10);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+            match sqlx::query("SELECT 1").execute(&pool).await {
+                Ok(_) => {
+                    if health.set_healthy(true) {
+                        info!("Database is reachable again");
+                    }
+                }
+                Err(e) => {
+                    if health.set_healthy(false) {
+                        warn!("Database health check failed: {}", e);
+                    }
+                }
+            }
+        }
+    })
+}
@@ -1,38 +1,127 @@
 mod models;
 mod handlers; 
 mod db;
+mod cleanup;
+mod extract;
+mod error;
+mod events;
+mod reminders;
+mod validation;
+mod health;
+mod store;
+mod seed;
+mod request_id;
+mod recurrence;
+mod vars;
 
 use axum::{
-    routing::{get, post, delete},
-    Router
+    error_handling::HandleErrorLayer,
+    extract::FromRef,
+    middleware,
+    response::Response,
+    routing::{get, patch, post, MethodRouter},
+    BoxError, Router
 };
 
 use handlers::*;
 use db::get_db_pool;
-use tracing_subscriber;
+use cleanup::{spawn_cleanup_task, CleanupConfig};
+use events::{spawn_event_listener, EventPublisher, EventSender, PgEventPublisher, EVENT_CAPACITY};
+use reminders::{spawn_reminder_task, ReminderConfig};
+use health::{spawn_health_monitor, Health};
+use error::problem_response;
+use store::{PgTodoStore, TodoStore};
+use seed::seed;
+use request_id::add_request_id;
 use dotenv::dotenv;
-use std::net::SocketAddr;
+use sqlx::PgPool;
+use reqwest::StatusCode;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tower::{limit::GlobalConcurrencyLimitLayer, ServiceBuilder};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+};
+
+// Responses smaller than this many bytes are sent uncompressed
+const DEFAULT_COMPRESSION_MIN_SIZE: u16 = 1024;
+
+// Maximum number of requests handled at the same time
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 512;
+
+// Seconds a handler may take before the request times out
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+
+// Shared state of the todo application
+// Handlers extract the part they need, e.g. State<Arc<dyn TodoStore>> or State<EventSender>
+// The publisher sends the change events of this instance, `events` receives those of every instance
+#[derive(Clone, FromRef)]
+struct AppState {
+    pool: PgPool,
+    store: Arc<dyn TodoStore>,
+    publisher: Arc<dyn EventPublisher>,
+    events: EventSender,
+    health: Health,
+    subtask_deletion: SubtaskDeletion,
+    poll_wait: PollWaitLimit,
+}
 
 // Main function to start the Axum server
 // It initializes the database connection pool and sets up the routes for the todo application.
 // It listens on port 3000 and serves the application using the Axum framework.
+// With --seed it inserts the sample todos from seed.rs and exits instead.
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     tracing_subscriber::fmt::init();
 
     let pool = get_db_pool().await;
+    let store: Arc<dyn TodoStore> = Arc::new(PgTodoStore::new(pool.clone()));
+
+    if env::args().skip(1).any(|arg| arg == "--seed") {
+        match seed(store.as_ref()).await {
+            Ok(inserted) => println!("Seeded {} todos", inserted),
+            Err(err) => {
+                eprintln!("Failed to seed the database: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
+    // Spawn the background task that removes old completed todos
+    let cleanup = spawn_cleanup_task(store.clone(), CleanupConfig::from_env());
 
-    let app = Router::new()
-    // Define the routes for the todo application
-    // The routes include listing all todos, creating a new task, retrieving a task
-    // by ID, and deleting a todo by ID.
-    // Each route is associated with a specific handler function that processes 
-    // the request and interacts with the database.
-    .route("/todos", get(list_todos).post(create_todo))
-    .route("/todos/{id}", get(get_todo).delete(delete_todo).put(update_todo))
-    .with_state(pool);
+    // Spawn the reminder task for due todos, only when REMINDER_WEBHOOK_URL is set
+    let reminders = ReminderConfig::from_env().map(|config| spawn_reminder_task(pool.clone(), config));
+
+    // Spawn the database health monitor, its last result is served by /health
+    let health = Health::new();
+    let health_monitor = spawn_health_monitor(pool.clone(), health.clone());
+
+    // READ_ONLY=1 serves only the read routes, e.g. for demo deployments
+    let read_only = env::var("READ_ONLY").map(|v| v == "1" || v == "true").unwrap_or(false);
+
+    // Forward the change events of every API instance to the SSE clients of this one
+    let publisher: Arc<dyn EventPublisher> = Arc::new(PgEventPublisher::new(pool.clone()));
+    let (events, _) = broadcast::channel(EVENT_CAPACITY);
+    let event_listener = spawn_event_listener(pool.clone(), events.clone());
+    // SUBTASK_DELETE=cascade deletes the subtasks of a deleted todo instead of refusing the delete
+    let subtask_deletion = SubtaskDeletion::from_env();
+    // Empty long polls end before the request timeout
+    let timeout = request_timeout();
+    let poll_wait = PollWaitLimit::for_timeout(timeout);
+    let router = build_router(AppState { pool, store, publisher, events, health, subtask_deletion, poll_wait }, read_only);
+    // The request id is added around the limits, so the 408 and 503 bodies get it too
+    let app = with_request_id(with_limits(router, max_concurrent_requests(), timeout))
+        // Compression is the outermost layer, so the error bodies are complete when the request id is added
+        .layer(compression_layer());
 
     // Start the server and listen on port 3000
     // The server will handle incoming requests and route them to the appropriate handlers.
@@ -41,6 +130,348 @@ async fn main() {
     // The axum::serve function is used to bind the server to a TCP listener.
     println!("Server listening on port 3000");
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+
+    // Stop the background tasks together with the server
+    // Their loops never end on their own, so the handles returned by the spawn_* functions are aborted here.
+    cleanup.abort();
+    health_monitor.abort();
+    event_listener.abort();
+    if let Some(reminders) = reminders {
+        reminders.abort();
+    }
+}
+
+// Build the router for the todo application
+// The routes include listing all todos or the recently updated ones, long-polling for changes, creating a new task,
+// retrieving a task by ID, by title slug or many tasks by their IDs, listing its subtasks, duplicating, archiving and restoring a task, completing many tasks at once, and deleting a todo by ID.
+// Each route is associated with a specific handler function that processes
+// the request and interacts with the database.
+// In read-only mode the POST/PUT/PATCH/DELETE handlers that change todos are left out,
+// so calls to them are answered with 405 Method Not Allowed. Paths that only have write handlers,
+// like /todos/{id}/archive, stay routed without any method, so they answer 405 instead of 404.
+// POST /todos/query is kept: it only reads, POST just carries the id list in the body.
+// A 405 carries a JSON body and an Allow header listing the methods of the route.
+// Paths that match no route are answered with a JSON 404 naming the path.
+fn build_router(state: AppState, read_only: bool) -> Router {
+    let router = Router::new()
+    .route("/health", get(health_check))
+    .route("/todos/recent", get(recent_todos))
+    .route("/todos/poll", get(poll_todos))
+    .route("/todos/stream", get(todo_stream))
+    .route("/todos/stats/daily", get(daily_stats))
+    .route("/todos/analytics/throughput", get(completion_throughput))
+    .route("/todos/by-title/{slug}", get(get_todo_by_slug))
+    .route("/todos/{id}/subtasks", get(list_subtasks))
+    .route("/todos/query", post(query_todos));
+
+    let router = if read_only {
+        router
+        .route("/todos", get(list_todos))
+        .route("/todos/{id}", get(get_todo))
+        .route("/todos/{id}/title", MethodRouter::new())
+        .route("/todos/{id}/archive", MethodRouter::new())
+        .route("/todos/{id}/restore", MethodRouter::new())
+        .route("/todos/{id}/duplicate", MethodRouter::new())
+    } else {
+        router
+        .route("/todos", get(list_todos).post(create_todo))
+        .route("/todos/complete", post(complete_todos))
+        .route("/todos/{id}", get(get_todo).delete(delete_todo).put(update_todo))
+        .route("/todos/{id}/title", patch(update_todo_title))
+        .route("/todos/{id}/archive", post(archive_todo))
+        .route("/todos/{id}/restore", post(restore_todo))
+        .route("/todos/{id}/duplicate", post(duplicate_todo))
+    };
+
+    router
+    .method_not_allowed_fallback(method_not_allowed)
+    .fallback(not_found)
+    .with_state(state)
+}
+
+// Compress responses with gzip or brotli, depending on the client's Accept-Encoding header
+// COMPRESSION_MIN_SIZE sets the size in bytes below which responses are left uncompressed.
+// Event streams are never compressed, so SSE clients get every event right away.
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    let min_size = env::var("COMPRESSION_MIN_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_COMPRESSION_MIN_SIZE);
+
+    let predicate = SizeAbove::new(min_size)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::SSE);
+    CompressionLayer::new().compress_when(predicate)
+}
+
+// Maximum number of in-flight requests, read from MAX_CONCURRENT_REQUESTS
+// Requests beyond the limit are not queued but rejected right away with 503 Service Unavailable,
+// so an overloaded server sheds load instead of building up an unbounded backlog.
+// This limit is shared by all clients, unlike rate limiting which is per client.
+fn max_concurrent_requests() -> usize {
+    env::var("MAX_CONCURRENT_REQUESTS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS)
+}
+
+// Time a handler may take to respond, read from REQUEST_TIMEOUT_SECS
+// Slower requests are answered with 408 Request Timeout. Unlike the statement timeout in db.rs
+// this covers any slow path of a handler, not only its queries.
+// Only the time until the response starts counts, so SSE streams stay open.
+fn request_timeout() -> Duration {
+    let secs = env::var("REQUEST_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+// Add the load shedding, concurrency limit and timeout layers to the router
+// Router::layer wraps each route on its own, so the limit uses one semaphore shared by all routes,
+// otherwise every route would admit `max_concurrent` requests.
+fn with_limits(router: Router, max_concurrent: usize, timeout: Duration) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_middleware_error))
+            .load_shed()
+            .layer(GlobalConcurrencyLimitLayer::new(max_concurrent))
+            .timeout(timeout),
+    )
+}
+
+// Give every request an X-Request-Id, kept from the client or generated,
+// which is echoed in the response and added to the error bodies, see request_id.rs
+fn with_request_id(router: Router) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(middleware::from_fn(add_request_id)),
+    )
+}
+
+// Turn the error of a shed request into a 503 response, and a timed out one into a 408
+async fn handle_middleware_error(err: BoxError) -> Response {
+    if err.is::<tower::load_shed::error::Overloaded>() {
+        problem_response(StatusCode::SERVICE_UNAVAILABLE, "Server is overloaded, try again later", None)
+    } else if err.is::<tower::timeout::error::Elapsed>() {
+        problem_response(StatusCode::REQUEST_TIMEOUT, "The request took too long to process", None)
+    } else {
+        problem_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("Unhandled internal error: {}", err), None)
+    }
+}
+
+// Wait for Ctrl+C so the server and the background tasks can shut down gracefully
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install Ctrl+C handler");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::{to_bytes, Body}, http::{Method, Request}};
+    use serde_json::Value;
+    use events::MemEventPublisher;
+    use store::MemTodoStore;
+    use tower::ServiceExt;
+
+    // State on an in-memory store, the published events are dropped
+    // The pool is never connected, only the report handlers would use it
+    pub(crate) fn test_state(store: Arc<MemTodoStore>) -> AppState {
+        test_state_with(store, Arc::new(MemEventPublisher::default()))
+    }
+
+    // State on an in-memory store that keeps the published events in `publisher`
+    pub(crate) fn test_state_with(store: Arc<MemTodoStore>, publisher: Arc<MemEventPublisher>) -> AppState {
+        let pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        AppState {
+            pool,
+            store,
+            publisher,
+            events,
+            health: Health::new(),
+            subtask_deletion: SubtaskDeletion::Block,
+            poll_wait: PollWaitLimit::for_timeout(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS)),
+        }
+    }
+
+    pub(crate) fn test_router(store: Arc<MemTodoStore>, read_only: bool) -> Router {
+        build_router(test_state(store), read_only)
+    }
+
+    // Send a JSON request, returning the status and the body, Null when it is not JSON
+    pub(crate) async fn send(router: Router, method: Method, uri: &str, body: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn create_todo_stores_the_normalized_todo() {
+        let store = Arc::new(MemTodoStore::default());
+        let publisher = Arc::new(MemEventPublisher::default());
+        let router = build_router(test_state_with(store.clone(), publisher.clone()), false);
+
+        let (status, body) = send(router, Method::POST, "/todos", r#"{"title": "  buy   milk ", "tags": ["Home"]}"#).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["title"], "buy milk");
+        assert_eq!(body["completed"], false);
+        let todos = store.todos.lock().unwrap();
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].id.to_string(), body["id"].as_str().unwrap());
+        assert_eq!(todos[0].title, "buy milk");
+        assert_eq!(todos[0].tags, vec!["home".to_string()]);
+        assert_eq!(publisher.events.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn create_todo_rejects_an_empty_title_without_storing_it() {
+        let store = Arc::new(MemTodoStore::default());
+        let router = test_router(store.clone(), false);
+
+        let (status, _) = send(router, Method::POST, "/todos", r#"{"title": "   "}"#).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(store.todos.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_only_router_answers_writes_with_405() {
+        let store = Arc::new(MemTodoStore::default());
+        let id = "00000000-0000-0000-0000-000000000001";
+        let writes = [
+            (Method::POST, "/todos".to_string(), r#"{"title": "x"}"#),
+            (Method::POST, "/todos/complete".to_string(), r#"{"ids": []}"#),
+            (Method::PUT, format!("/todos/{}", id), r#"{"completed": true}"#),
+            (Method::DELETE, format!("/todos/{}", id), ""),
+            (Method::PATCH, format!("/todos/{}/title", id), r#"{"title": "x"}"#),
+            (Method::POST, format!("/todos/{}/archive", id), ""),
+            (Method::POST, format!("/todos/{}/restore", id), ""),
+            (Method::POST, format!("/todos/{}/duplicate", id), ""),
+        ];
+        for (method, uri, body) in writes {
+            let (status, body) = send(test_router(store.clone(), true), method.clone(), &uri, body).await;
+            assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED, "{} {}", method, uri);
+            assert_eq!(body["detail"], format!("Method {} is not allowed for {}", method, uri));
+        }
+        assert!(store.todos.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_only_router_still_serves_reads_and_queries() {
+        let store = Arc::new(MemTodoStore::default());
+
+        let (status, body) = send(test_router(store.clone(), true), Method::GET, "/todos", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!([]));
+
+        let (status, body) = send(test_router(store, true), Method::POST, "/todos/query", r#"{"ids": []}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn not_found_and_validation_errors_are_problem_json() {
+        let store = Arc::new(MemTodoStore::default());
+        let requests = [
+            (Method::GET, "/todos/00000000-0000-0000-0000-000000000001", "", StatusCode::NOT_FOUND),
+            (Method::POST, "/todos", r#"{"title": ""}"#, StatusCode::UNPROCESSABLE_ENTITY),
+        ];
+        for (method, uri, body, status) in requests {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let response = test_router(store.clone(), false).oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{}", uri);
+            assert_eq!(response.headers()["content-type"], error::PROBLEM_JSON);
+            let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["type"], "about:blank");
+            assert_eq!(body["title"], status.canonical_reason().unwrap());
+            assert_eq!(body["status"], status.as_u16());
+            assert!(body["detail"].is_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_requests_get_408() {
+        let router = Router::new()
+            .route("/slow", get(|| async { tokio::time::sleep(Duration::from_secs(5)).await }));
+        let router = with_limits(router, 10, Duration::from_millis(100));
+
+        let request = Request::builder().uri("/slow").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(response.headers()["content-type"], error::PROBLEM_JSON);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["detail"], "The request took too long to process");
+    }
+
+    #[test]
+    fn poll_wait_stays_below_the_request_timeout() {
+        assert_eq!(PollWaitLimit::for_timeout(Duration::from_secs(30)).0, Duration::from_secs(25));
+        assert_eq!(PollWaitLimit::for_timeout(Duration::from_secs(10)).0, Duration::from_secs(9));
+        assert_eq!(PollWaitLimit::for_timeout(Duration::from_millis(500)).0, Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn empty_poll_ends_before_a_short_request_timeout() {
+        let timeout = Duration::from_secs(2);
+        let mut state = test_state(Arc::new(MemTodoStore::default()));
+        state.poll_wait = PollWaitLimit::for_timeout(timeout);
+        let router = with_limits(build_router(state, false), 10, timeout);
+
+        let uri = "/todos/poll?since=2024-01-01T00:00:00Z&wait=25";
+        let (status, body) = send(router, Method::GET, uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_concurrency_limit_of_all_routes_get_503() {
+        let router = Router::new()
+            .route("/slow", get(|| async { tokio::time::sleep(Duration::from_millis(500)).await }))
+            .route("/fast", get(|| async {}));
+        let router = with_limits(router, 1, Duration::from_secs(5));
+
+        let slow = tokio::spawn(send(router.clone(), Method::GET, "/slow", ""));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // The limit is shared, so the slow request also blocks another route
+        let request = Request::builder().uri("/fast").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["content-type"], "application/problem+json");
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["status"], 503);
+        assert_eq!(body["detail"], "Server is overloaded, try again later");
 
+        assert_eq!(slow.await.unwrap().0, StatusCode::OK);
+        let (status, _) = send(router, Method::GET, "/fast", "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
 }
@@ -1,16 +1,69 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use chrono::{NaiveDateTime, Utc};
+use chrono::{NaiveDate, NaiveDateTime};
+use crate::recurrence::Recurrence;
 
 
 /// This module defines the data model for the todo application.
 /// It includes the `Todo` struct which represents a todo item in the database.
+/// With the camel-case feature the JSON field names are camelCase, e.g. createdAt instead of created_at.
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
 pub struct Todo {
     pub id: Uuid,
     pub title: String,
     pub completed: bool,
-    pub created_at: NaiveDateTime
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    // Set while the todo is archived, None for active todos
+    pub archived_at: Option<NaiveDateTime>,
+    // When the todo is due, a reminder is sent once it has passed, see reminders.rs
+    pub due_date: Option<NaiveDateTime>,
+    // The todo this one is a subtask of, None for top-level todos
+    pub parent_id: Option<Uuid>,
+    // Lowercased and trimmed tags, e.g. ["work", "urgent"]
+    pub tags: Vec<String>,
+    // When the todo was completed, None while it is open
+    pub completed_at: Option<NaiveDateTime>,
+    // How the todo repeats, None for a one-off todo, see recurrence.rs
+    pub recurrence: Option<Recurrence>
 }
 
+// Open top-level todo created now, tests override the fields they need with struct update syntax,
+// e.g. Todo { parent_id: Some(id), ..test_todo("subtask") }
+#[cfg(test)]
+pub(crate) fn test_todo(title: &str) -> Todo {
+    let now = chrono::Utc::now().naive_utc();
+    Todo {
+        id: Uuid::new_v4(),
+        title: title.to_string(),
+        completed: false,
+        created_at: now,
+        updated_at: now,
+        archived_at: None,
+        due_date: None,
+        parent_id: None,
+        tags: Vec::new(),
+        completed_at: None,
+        recurrence: None,
+    }
+}
+
+/// Activity of a single day, returned by the daily statistics endpoint.
+/// `created` counts the todos created that day and `completed` how many of them are completed.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct DailyStats {
+    pub day: NaiveDate,
+    pub created: i64,
+    pub completed: i64
+}
+
+/// Number of todos completed on a single day, returned by the throughput endpoint.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct DailyThroughput {
+    pub day: NaiveDate,
+    pub completed: i64
+}
@@ -0,0 +1,113 @@
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use crate::models::Todo;
+use crate::store::NewTodo;
+
+// This module defines recurring todos.
+// A todo with a recurrence gets a next occurrence when it is completed, see update_todo and complete_todos.
+// The next occurrence copies the todo with its due date moved forward by the recurrence,
+// so the chain continues when that one is completed in turn.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Recurrence {
+    Daily,
+    Weekly,
+}
+
+impl Recurrence {
+    fn interval(self) -> Duration {
+        match self {
+            Recurrence::Daily => Duration::days(1),
+            Recurrence::Weekly => Duration::weeks(1),
+        }
+    }
+
+    /// The first due date after `now` in the series of `due`
+    /// `due` is moved forward by whole intervals, so an overdue weekly todo keeps its weekday and time
+    /// and occurrences missed while it was overdue are skipped.
+    pub fn next_due_date(self, due: NaiveDateTime, now: NaiveDateTime) -> NaiveDateTime {
+        let interval = self.interval();
+        let mut next = due + interval;
+        if next <= now {
+            let missed = (now - next).num_seconds() / interval.num_seconds() + 1;
+            next += interval * missed as i32;
+        }
+        next
+    }
+}
+
+/// The next occurrence of a completed todo, None when the todo does not recur
+/// Without a due date the series starts at the completion time of the todo.
+pub fn next_occurrence(todo: &Todo, now: NaiveDateTime) -> Option<NewTodo> {
+    let recurrence = todo.recurrence?;
+    let due = todo.due_date.or(todo.completed_at).unwrap_or(now);
+    Some(NewTodo {
+        title: todo.title.clone(),
+        due_date: Some(recurrence.next_due_date(due, now)),
+        parent_id: todo.parent_id,
+        tags: todo.tags.clone(),
+        recurrence: Some(recurrence),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_todo;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 3, day).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    fn todo(recurrence: Option<Recurrence>, due_date: Option<NaiveDateTime>, completed_at: Option<NaiveDateTime>) -> Todo {
+        Todo {
+            completed: true,
+            created_at: at(1, 8),
+            updated_at: at(1, 8),
+            due_date,
+            tags: vec!["home".to_string()],
+            completed_at,
+            recurrence,
+            ..test_todo("water plants")
+        }
+    }
+
+    #[test]
+    fn daily_and_weekly_advance_by_one_interval() {
+        assert_eq!(Recurrence::Daily.next_due_date(at(10, 9), at(10, 8)), at(11, 9));
+        assert_eq!(Recurrence::Weekly.next_due_date(at(10, 9), at(10, 8)), at(17, 9));
+    }
+
+    #[test]
+    fn overdue_todos_skip_missed_occurrences() {
+        // Due on the 3rd, completed on the 12th: the 4th to the 12th were missed
+        assert_eq!(Recurrence::Daily.next_due_date(at(3, 9), at(12, 10)), at(13, 9));
+        assert_eq!(Recurrence::Daily.next_due_date(at(3, 9), at(12, 8)), at(12, 9));
+        // A weekly todo keeps its weekday and time
+        assert_eq!(Recurrence::Weekly.next_due_date(at(3, 9), at(12, 10)), at(17, 9));
+        assert_eq!(Recurrence::Weekly.next_due_date(at(3, 9), at(17, 9)), at(24, 9));
+    }
+
+    #[test]
+    fn next_occurrence_copies_the_todo_with_the_next_due_date() {
+        let next = next_occurrence(&todo(Some(Recurrence::Weekly), Some(at(10, 9)), Some(at(10, 12))), at(10, 12)).unwrap();
+        assert_eq!(next.title, "water plants");
+        assert_eq!(next.tags, ["home"]);
+        assert_eq!(next.due_date, Some(at(17, 9)));
+        assert_eq!(next.recurrence, Some(Recurrence::Weekly));
+    }
+
+    #[test]
+    fn without_a_due_date_the_series_starts_at_completion() {
+        let next = next_occurrence(&todo(Some(Recurrence::Daily), None, Some(at(10, 12))), at(10, 12)).unwrap();
+        assert_eq!(next.due_date, Some(at(11, 12)));
+    }
+
+    #[test]
+    fn one_off_todos_have_no_next_occurrence() {
+        assert!(next_occurrence(&todo(None, Some(at(10, 9)), Some(at(10, 12))), at(10, 12)).is_none());
+    }
+}
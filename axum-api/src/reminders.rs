@@ -0,0 +1,292 @@
+use serde_json::json;
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
+use crate::models::Todo;
+use crate::vars::env_var;
+
+// This module provides the background reminder task for the todo application.
+// The task periodically looks for open todos whose due_date has passed and POSTs
+// a JSON payload for each of them to the webhook in REMINDER_WEBHOOK_URL.
+// Every todo is reminded once, changing its due_date makes it eligible again.
+// Failed deliveries are retried with exponential backoff and logged, a reminder that
+// still fails is released, so the next cycle tries it again.
+
+// Number of delivery attempts per reminder and the delay before the first retry
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+// Time a webhook call may take, so a hung webhook cannot stall the reminder loop
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct ReminderConfig {
+    pub webhook_url: String,
+    pub interval: Duration,
+}
+
+impl ReminderConfig {
+    /// Read the reminder configuration from the environment
+    /// Returns None when REMINDER_WEBHOOK_URL is not set, then no reminders are sent.
+    /// REMINDER_INTERVAL_SECS defaults to checking every minute, a zero interval is ignored.
+    pub fn from_env() -> Option<Self> {
+        Self::from_vars(env_var)
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let webhook_url = var("REMINDER_WEBHOOK_URL").filter(|url| !url.is_empty())?;
+        let interval_secs = var("REMINDER_INTERVAL_SECS")
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|&secs| secs > 0)
+            .unwrap_or(60);
+
+        Some(ReminderConfig {
+            webhook_url,
+            interval: Duration::from_secs(interval_secs),
+        })
+    }
+}
+
+/// claim_due_todos
+/// This function returns the open todos that are past their due_date and were not reminded yet.
+/// It marks them as reminded in the same UPDATE SQL command, so each todo is only sent once.
+pub async fn claim_due_todos(pool: &PgPool) -> Result<Vec<Todo>, sqlx::Error> {
+    sqlx::query_as::<_, Todo>(
+        "UPDATE todos SET reminded_at = now() \
+         WHERE due_date <= now() AND reminded_at IS NULL AND NOT completed AND archived_at IS NULL \
+         RETURNING *",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// release_reminder
+/// This function clears reminded_at of a todo whose reminder could not be delivered,
+/// so claim_due_todos returns it again in the next cycle.
+pub async fn release_reminder(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE todos SET reminded_at = NULL WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// send_reminder
+/// This function POSTs the reminder payload for a todo to the webhook.
+/// The payload is {"event": "todo.due", "todo": {...}}.
+/// A connection error or a non-2xx status is retried up to MAX_ATTEMPTS times,
+/// doubling the delay after each failed attempt.
+pub async fn send_reminder(client: &reqwest::Client, url: &str, todo: &Todo) -> Result<(), reqwest::Error> {
+    let payload = json!({ "event": "todo.due", "todo": todo });
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+
+    loop {
+        let result = client
+            .post(url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match result {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                warn!("Reminder for todo {} failed (attempt {}): {}", todo.id, attempt, e);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// send_due_reminders
+/// This function claims the due todos and sends a reminder for each of them.
+/// Reminders that fail for good are released for the next cycle.
+/// It logs how many reminders were sent and every delivery that failed.
+pub async fn send_due_reminders(pool: &PgPool, client: &reqwest::Client, url: &str) -> Result<(), sqlx::Error> {
+    let todos = claim_due_todos(pool).await?;
+
+    let mut sent = 0;
+    for todo in &todos {
+        match send_reminder(client, url, todo).await {
+            Ok(()) => sent += 1,
+            Err(e) => {
+                warn!("Reminder for todo {} was not delivered, retrying next cycle: {}", todo.id, e);
+                release_reminder(pool, todo.id).await?;
+            }
+        }
+    }
+    if !todos.is_empty() {
+        info!("Sent {} of {} due todo reminders", sent, todos.len());
+    }
+    Ok(())
+}
+
+/// spawn_reminder_task
+/// This function spawns the periodic reminder task on the tokio runtime.
+/// Every cycle runs send_due_reminders, a failed cycle is logged.
+pub fn spawn_reminder_task(pool: PgPool, config: ReminderConfig) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .expect("Failed to build the webhook client");
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = send_due_reminders(&pool, &client, &config.webhook_url).await {
+                warn!("Reminder check failed: {}", e);
+            }
+        }
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_todo;
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use serde_json::Value;
+    use crate::vars::test_vars;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn no_webhook_means_no_reminders() {
+        assert!(ReminderConfig::from_vars(test_vars(&[])).is_none());
+        assert!(ReminderConfig::from_vars(test_vars(&[("REMINDER_WEBHOOK_URL", ""), ("REMINDER_INTERVAL_SECS", "5")])).is_none());
+    }
+
+    #[test]
+    fn zero_interval_falls_back_to_the_default() {
+        let config = ReminderConfig::from_vars(test_vars(&[("REMINDER_WEBHOOK_URL", "http://hook"), ("REMINDER_INTERVAL_SECS", "0")])).unwrap();
+        assert_eq!(config.webhook_url, "http://hook");
+        assert_eq!(config.interval, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn valid_interval_is_used() {
+        let config = ReminderConfig::from_vars(test_vars(&[("REMINDER_WEBHOOK_URL", "http://hook"), ("REMINDER_INTERVAL_SECS", "5")])).unwrap();
+        assert_eq!(config.interval, Duration::from_secs(5));
+    }
+
+    fn todo(title: &str) -> Todo {
+        let todo = test_todo(title);
+        Todo { due_date: Some(todo.created_at), ..todo }
+    }
+
+    // Webhook that fails the first `failures` deliveries with 500 and records the payloads of the others
+    async fn spawn_webhook(failures: usize) -> (String, Arc<Mutex<Vec<Value>>>) {
+        #[derive(Clone)]
+        struct Hook {
+            failures: Arc<Mutex<usize>>,
+            payloads: Arc<Mutex<Vec<Value>>>,
+        }
+
+        async fn receive(State(hook): State<Hook>, Json(payload): Json<Value>) -> StatusCode {
+            let mut failures = hook.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            hook.payloads.lock().unwrap().push(payload);
+            StatusCode::OK
+        }
+
+        let hook = Hook { failures: Arc::new(Mutex::new(failures)), payloads: Arc::default() };
+        let payloads = hook.payloads.clone();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let app = Router::new().route("/hook", post(receive)).with_state(hook);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, payloads)
+    }
+
+    #[tokio::test]
+    async fn reminder_posts_the_todo_to_the_webhook() {
+        let (url, payloads) = spawn_webhook(0).await;
+        let todo = todo("pay rent");
+
+        send_reminder(&reqwest::Client::new(), &url, &todo).await.unwrap();
+
+        let payloads = payloads.lock().unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["event"], "todo.due");
+        assert_eq!(payloads[0]["todo"], serde_json::to_value(&todo).unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_retried() {
+        let (url, payloads) = spawn_webhook(1).await;
+
+        send_reminder(&reqwest::Client::new(), &url, &todo("pay rent")).await.unwrap();
+
+        assert_eq!(payloads.lock().unwrap().len(), 1);
+    }
+
+    async fn insert(pool: &PgPool, due: &str, reminded: bool, completed: bool) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(&format!(
+            "INSERT INTO todos (id, title, completed, due_date, reminded_at) VALUES ($1, 'x', $2, now() + interval '{}', {})",
+            due,
+            if reminded { "now()" } else { "NULL" },
+        ))
+        .bind(id)
+        .bind(completed)
+        .execute(pool)
+        .await
+        .unwrap();
+        id
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore]
+    async fn only_open_overdue_todos_are_claimed_once(pool: PgPool) {
+        let overdue = insert(&pool, "-1 hour", false, false).await;
+        insert(&pool, "1 hour", false, false).await;
+        insert(&pool, "-1 hour", true, false).await;
+        insert(&pool, "-1 hour", false, true).await;
+
+        let claimed: Vec<Uuid> = claim_due_todos(&pool).await.unwrap().iter().map(|todo| todo.id).collect();
+        assert_eq!(claimed, vec![overdue]);
+        assert!(claim_due_todos(&pool).await.unwrap().is_empty());
+    }
+
+    async fn reminded(pool: &PgPool, id: Uuid) -> bool {
+        sqlx::query_scalar("SELECT reminded_at IS NOT NULL FROM todos WHERE id = $1")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore]
+    async fn delivered_reminders_stay_claimed(pool: PgPool) {
+        let (url, payloads) = spawn_webhook(0).await;
+        let id = insert(&pool, "-1 hour", false, false).await;
+
+        send_due_reminders(&pool, &reqwest::Client::new(), &url).await.unwrap();
+
+        assert_eq!(payloads.lock().unwrap().len(), 1);
+        assert!(reminded(&pool, id).await);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore]
+    async fn undelivered_reminders_are_released_for_the_next_cycle(pool: PgPool) {
+        let (url, payloads) = spawn_webhook(MAX_ATTEMPTS as usize).await;
+        let id = insert(&pool, "-1 hour", false, false).await;
+
+        send_due_reminders(&pool, &reqwest::Client::new(), &url).await.unwrap();
+        assert!(payloads.lock().unwrap().is_empty());
+        assert!(!reminded(&pool, id).await);
+
+        send_due_reminders(&pool, &reqwest::Client::new(), &url).await.unwrap();
+        assert_eq!(payloads.lock().unwrap().len(), 1);
+        assert!(reminded(&pool, id).await);
+    }
+}
@@ -0,0 +1,57 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::header::{CONTENT_LENGTH, CONTENT_TYPE},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+use tower_http::request_id::RequestId;
+use tracing::warn;
+use crate::error::PROBLEM_JSON;
+
+// This module adds the correlation id of a request to its error response.
+// The id comes from the X-Request-Id header sent by the client, or is generated as a UUID,
+// and is echoed in the X-Request-Id response header, see the request id layers in main.rs.
+// Every application/problem+json body also gets a request_id member, and server errors are
+// logged with it, so a client report can be matched with the server logs.
+
+/// Add the request id to the problem+json body of an error response
+/// Other responses are passed through unchanged.
+pub async fn add_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(str::to_owned);
+    let response = next.run(request).await;
+
+    let Some(request_id) = request_id else {
+        return response;
+    };
+    let is_problem = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == PROBLEM_JSON);
+    if !is_problem {
+        return response;
+    }
+
+    // Problem bodies are small JSON documents built by problem_response, so they are read whole
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let Ok(Value::Object(mut problem)) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    if parts.status.is_server_error() {
+        let detail = problem.get("detail").and_then(Value::as_str).unwrap_or("");
+        warn!("request {} failed with {}: {}", request_id, parts.status, detail);
+    }
+
+    problem.insert("request_id".to_string(), Value::String(request_id));
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(Value::Object(problem).to_string()))
+}
@@ -0,0 +1,71 @@
+use crate::store::{NewTodo, TodoStore};
+
+// This module provides the sample data loaded by `axum-api --seed` for local development.
+// Seeding inserts the SEED_TODOS through the TodoStore and exits instead of serving.
+// A sample todo whose title already exists is skipped, so seeding twice adds nothing.
+
+pub const SEED_TODOS: [&str; 5] = [
+    "Buy milk",
+    "Write the weekly report",
+    "Book a dentist appointment",
+    "Water the plants",
+    "Read the axum documentation",
+];
+
+/// Insert the sample todos that do not exist yet
+/// Returns the number of inserted todos.
+pub async fn seed(store: &dyn TodoStore) -> Result<usize, sqlx::Error> {
+    let mut inserted = 0;
+    for title in SEED_TODOS {
+        if store.find_by_title(title).await?.is_none() {
+            store
+                .create(NewTodo {
+                    title: title.to_string(),
+                    due_date: None,
+                    parent_id: None,
+                    tags: Vec::new(),
+                    recurrence: None,
+                })
+                .await?;
+            inserted += 1;
+        }
+    }
+    Ok(inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemTodoStore;
+    use crate::tests::{send, test_router};
+    use axum::http::Method;
+    use reqwest::StatusCode;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn seeding_twice_inserts_the_sample_todos_once() {
+        let store = Arc::new(MemTodoStore::default());
+
+        assert_eq!(seed(store.as_ref()).await.unwrap(), SEED_TODOS.len());
+        assert_eq!(seed(store.as_ref()).await.unwrap(), 0);
+
+        let (status, body) = send(test_router(store, false), Method::GET, "/todos", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let mut titles: Vec<&str> = body.as_array().unwrap().iter().map(|todo| todo["title"].as_str().unwrap()).collect();
+        titles.sort();
+        let mut expected = SEED_TODOS.to_vec();
+        expected.sort();
+        assert_eq!(titles, expected);
+    }
+
+    #[tokio::test]
+    async fn existing_titles_are_skipped_ignoring_case() {
+        let store = MemTodoStore::default();
+        store
+            .create(NewTodo { title: "buy MILK".into(), due_date: None, parent_id: None, tags: Vec::new(), recurrence: None })
+            .await
+            .unwrap();
+
+        assert_eq!(seed(&store).await.unwrap(), SEED_TODOS.len() - 1);
+    }
+}
@@ -0,0 +1,523 @@
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::models::Todo;
+use crate::recurrence::Recurrence;
+
+// This module defines the storage of the todo items.
+// The handlers access the todos through the TodoStore trait instead of sqlx,
+// so they can run against another store, e.g. the in-memory MemTodoStore of the tests, without a database.
+// PgTodoStore is the Postgres implementation used by the server.
+// Only the daily_stats and completion_throughput reports query the pool directly, they are
+// aggregations computed by Postgres rather than reads or writes of single todos.
+
+// A page of a list, `limit` todos starting after the first `offset` ones
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// A todo inserted by TodoStore::create
+#[derive(Debug, Clone)]
+pub struct NewTodo {
+    pub title: String,
+    pub due_date: Option<NaiveDateTime>,
+    pub parent_id: Option<Uuid>,
+    pub tags: Vec<String>,
+    pub recurrence: Option<Recurrence>,
+}
+
+// The new values of a todo written by TodoStore::update
+#[derive(Debug, Clone)]
+pub struct TodoChanges {
+    pub title: String,
+    pub completed: bool,
+    pub due_date: Option<NaiveDateTime>,
+    pub tags: Vec<String>,
+    pub recurrence: Option<Recurrence>,
+}
+
+#[async_trait]
+pub trait TodoStore: Send + Sync {
+    /// List the archived or the active todos, newest first
+    /// With a `tag` only the todos carrying that tag are listed.
+    /// Returns the todos of `page`, or all of them without a page, and the total number of todos.
+    async fn list(&self, archived: bool, tag: Option<&str>, page: Option<Page>) -> Result<(Vec<Todo>, i64), sqlx::Error>;
+
+    /// Insert a new todo
+    async fn create(&self, todo: NewTodo) -> Result<Todo, sqlx::Error>;
+
+    /// Find a todo by id
+    async fn get(&self, id: Uuid) -> Result<Option<Todo>, sqlx::Error>;
+
+    /// List the subtasks of a todo, oldest first
+    async fn subtasks(&self, parent_id: Uuid) -> Result<Vec<Todo>, sqlx::Error>;
+
+    /// Find a todo with the given title, ignoring case
+    async fn find_by_title(&self, title: &str) -> Result<Option<Todo>, sqlx::Error>;
+
+    /// Find the todos whose title normalizes to `slug`, see slugify in handlers.rs
+    async fn find_by_slug(&self, slug: &str) -> Result<Vec<Todo>, sqlx::Error>;
+
+    /// Find the todos with the given ids, in no particular order, ids that do not exist are left out
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<Todo>, sqlx::Error>;
+
+    /// List the todos updated after `since`, oldest change first
+    async fn updated_since(&self, since: NaiveDateTime) -> Result<Vec<Todo>, sqlx::Error>;
+
+    /// Overwrite the fields of a todo, returning None if it does not exist
+    /// Returns the updated todo and whether it was open before, i.e. got completed by this call.
+    async fn update(&self, id: Uuid, changes: TodoChanges) -> Result<Option<(Todo, bool)>, sqlx::Error>;
+
+    /// Change only the title of a todo, returning None if it does not exist
+    async fn set_title(&self, id: Uuid, title: &str) -> Result<Option<Todo>, sqlx::Error>;
+
+    /// Archive or restore a todo, returning None if it does not exist
+    /// Archiving an archived todo keeps its archived_at.
+    async fn set_archived(&self, id: Uuid, archived: bool) -> Result<Option<Todo>, sqlx::Error>;
+
+    /// Set the completed flag of many todos at once, ids that do not exist are skipped
+    /// Returns the updated todos and the ids of those that were open before, i.e. got completed by this call.
+    async fn complete_many(&self, ids: &[Uuid], completed: bool) -> Result<(Vec<Todo>, Vec<Uuid>), sqlx::Error>;
+
+    /// Delete a todo and its subtasks, returning whether it existed
+    async fn delete(&self, id: Uuid) -> Result<bool, sqlx::Error>;
+
+    /// Delete the completed todos last updated more than `retention_days` days ago and their subtasks
+    /// Returns the number of deleted completed todos.
+    async fn delete_completed_older_than(&self, retention_days: i32) -> Result<u64, sqlx::Error>;
+}
+
+pub struct PgTodoStore {
+    pool: PgPool,
+}
+
+impl PgTodoStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgTodoStore { pool }
+    }
+}
+
+#[async_trait]
+impl TodoStore for PgTodoStore {
+    // The tag filter uses the array contains operator, which can use the GIN index on tags
+    async fn list(&self, archived: bool, tag: Option<&str>, page: Option<Page>) -> Result<(Vec<Todo>, i64), sqlx::Error> {
+        let Some(page) = page else {
+            let todos = sqlx::query_as::<_, Todo>(
+                "SELECT * FROM todos WHERE (archived_at IS NOT NULL) = $1 \
+                 AND ($2::text IS NULL OR tags @> ARRAY[$2::text]) ORDER BY created_at DESC",
+            )
+            .bind(archived)
+            .bind(tag)
+            .fetch_all(&self.pool)
+            .await?;
+            let total = todos.len() as i64;
+            return Ok((todos, total));
+        };
+
+        let total: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM todos WHERE (archived_at IS NOT NULL) = $1 \
+             AND ($2::text IS NULL OR tags @> ARRAY[$2::text])",
+        )
+        .bind(archived)
+        .bind(tag)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let todos = sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE (archived_at IS NOT NULL) = $1 \
+             AND ($2::text IS NULL OR tags @> ARRAY[$2::text]) ORDER BY created_at DESC LIMIT $3 OFFSET $4",
+        )
+        .bind(archived)
+        .bind(tag)
+        .bind(page.limit)
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok((todos, total))
+    }
+
+    async fn create(&self, todo: NewTodo) -> Result<Todo, sqlx::Error> {
+        sqlx::query_as::<_, Todo>(
+            "Insert into todos(id, title, due_date, parent_id, tags, recurrence) \
+             values ($1, $2, $3, $4, $5, $6) returning *",
+        )
+        .bind(Uuid::new_v4())
+        .bind(todo.title)
+        .bind(todo.due_date)
+        .bind(todo.parent_id)
+        .bind(todo.tags)
+        .bind(todo.recurrence)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<Todo>, sqlx::Error> {
+        sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn subtasks(&self, parent_id: Uuid) -> Result<Vec<Todo>, sqlx::Error> {
+        sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE parent_id = $1 ORDER BY created_at ASC")
+            .bind(parent_id)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn find_by_title(&self, title: &str) -> Result<Option<Todo>, sqlx::Error> {
+        sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE LOWER(title) = LOWER($1) LIMIT 1")
+            .bind(title)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn find_by_slug(&self, slug: &str) -> Result<Vec<Todo>, sqlx::Error> {
+        sqlx::query_as::<_, Todo>(
+            r"SELECT * FROM todos WHERE regexp_replace(LOWER(TRIM(title)), '\s+', '-', 'g') = $1",
+        )
+        .bind(slug)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<Todo>, sqlx::Error> {
+        sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE id = ANY($1)")
+            .bind(ids)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn updated_since(&self, since: NaiveDateTime) -> Result<Vec<Todo>, sqlx::Error> {
+        sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE updated_at > $1 ORDER BY updated_at ASC")
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    // A changed due_date clears reminded_at, so a reminder is sent for the new due time
+    // Completing an open todo sets completed_at, reopening a completed one clears it
+    // The todo is locked first, so of two concurrent updates only one sees it open
+    async fn update(&self, id: Uuid, changes: TodoChanges) -> Result<Option<(Todo, bool)>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let Some(was_completed) = sqlx::query_scalar::<_, bool>("SELECT completed FROM todos WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let updated = sqlx::query_as::<_, Todo>(
+            "UPDATE todos SET title = $1, completed = $2, due_date = $3, tags = $4, recurrence = $6, \
+             reminded_at = CASE WHEN due_date IS DISTINCT FROM $3 THEN NULL ELSE reminded_at END, \
+             completed_at = CASE WHEN NOT $2 THEN NULL WHEN NOT completed THEN now() ELSE completed_at END, \
+             updated_at = now() WHERE id = $5 RETURNING *",
+        )
+        .bind(changes.title)
+        .bind(changes.completed)
+        .bind(changes.due_date)
+        .bind(changes.tags)
+        .bind(id)
+        .bind(changes.recurrence)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        let completed_now = !was_completed && updated.completed;
+        Ok(Some((updated, completed_now)))
+    }
+
+    async fn set_title(&self, id: Uuid, title: &str) -> Result<Option<Todo>, sqlx::Error> {
+        sqlx::query_as::<_, Todo>("UPDATE todos SET title = $1, updated_at = now() WHERE id = $2 RETURNING *")
+            .bind(title)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn set_archived(&self, id: Uuid, archived: bool) -> Result<Option<Todo>, sqlx::Error> {
+        sqlx::query_as::<_, Todo>(
+            "UPDATE todos SET archived_at = CASE WHEN $1 THEN COALESCE(archived_at, now()) END, \
+             updated_at = now() WHERE id = $2 RETURNING *",
+        )
+        .bind(archived)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // The open todos are locked first, so they are known to be completed by this call
+    // completed_at is set for the todos that get completed and cleared for the reopened ones
+    async fn complete_many(&self, ids: &[Uuid], completed: bool) -> Result<(Vec<Todo>, Vec<Uuid>), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let opened: Vec<Uuid> =
+            sqlx::query_scalar("SELECT id FROM todos WHERE id = ANY($1) AND NOT completed FOR UPDATE")
+                .bind(ids)
+                .fetch_all(&mut *tx)
+                .await?;
+
+        let updated = sqlx::query_as::<_, Todo>(
+            "UPDATE todos SET completed = $1, updated_at = now(), \
+             completed_at = CASE WHEN NOT $1 THEN NULL WHEN NOT completed THEN now() ELSE completed_at END \
+             WHERE id = ANY($2) RETURNING *",
+        )
+        .bind(completed)
+        .bind(ids)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok((updated, opened))
+    }
+
+    // The subtasks are deleted by the ON DELETE CASCADE of parent_id
+    async fn delete(&self, id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM todos WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_completed_older_than(&self, retention_days: i32) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "DELETE FROM todos WHERE completed = true AND updated_at < now() - make_interval(days => $1)",
+        )
+        .bind(retention_days)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+// In-memory store for the handler tests, it needs no database
+// It follows the semantics of PgTodoStore.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemTodoStore {
+    pub todos: std::sync::Mutex<Vec<Todo>>,
+}
+
+#[cfg(test)]
+impl MemTodoStore {
+    // Apply `change` to the todo with `id` and bump its updated_at, returning the changed todo
+    fn modify(&self, id: Uuid, change: impl FnOnce(&mut Todo)) -> Option<Todo> {
+        let mut todos = self.todos.lock().unwrap();
+        let todo = todos.iter_mut().find(|todo| todo.id == id)?;
+        change(todo);
+        todo.updated_at = chrono::Utc::now().naive_utc();
+        Some(todo.clone())
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl TodoStore for MemTodoStore {
+    async fn list(&self, archived: bool, tag: Option<&str>, page: Option<Page>) -> Result<(Vec<Todo>, i64), sqlx::Error> {
+        let mut todos: Vec<Todo> = self
+            .todos
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|todo| todo.archived_at.is_some() == archived)
+            .filter(|todo| tag.is_none_or(|tag| todo.tags.iter().any(|t| t == tag)))
+            .cloned()
+            .collect();
+        todos.sort_by_key(|todo| std::cmp::Reverse(todo.created_at));
+        let total = todos.len() as i64;
+        if let Some(page) = page {
+            todos = todos.into_iter().skip(page.offset as usize).take(page.limit as usize).collect();
+        }
+        Ok((todos, total))
+    }
+
+    async fn create(&self, todo: NewTodo) -> Result<Todo, sqlx::Error> {
+        let now = chrono::Utc::now().naive_utc();
+        let todo = Todo {
+            id: Uuid::new_v4(),
+            title: todo.title,
+            completed: false,
+            created_at: now,
+            updated_at: now,
+            archived_at: None,
+            due_date: todo.due_date,
+            parent_id: todo.parent_id,
+            tags: todo.tags,
+            completed_at: None,
+            recurrence: todo.recurrence,
+        };
+        self.todos.lock().unwrap().push(todo.clone());
+        Ok(todo)
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<Todo>, sqlx::Error> {
+        Ok(self.todos.lock().unwrap().iter().find(|todo| todo.id == id).cloned())
+    }
+
+    async fn subtasks(&self, parent_id: Uuid) -> Result<Vec<Todo>, sqlx::Error> {
+        let mut todos: Vec<Todo> = self
+            .todos
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|todo| todo.parent_id == Some(parent_id))
+            .cloned()
+            .collect();
+        todos.sort_by_key(|todo| todo.created_at);
+        Ok(todos)
+    }
+
+    async fn find_by_title(&self, title: &str) -> Result<Option<Todo>, sqlx::Error> {
+        let title = title.to_lowercase();
+        Ok(self.todos.lock().unwrap().iter().find(|todo| todo.title.to_lowercase() == title).cloned())
+    }
+
+    async fn find_by_slug(&self, slug: &str) -> Result<Vec<Todo>, sqlx::Error> {
+        let todos = self.todos.lock().unwrap();
+        Ok(todos.iter().filter(|todo| crate::handlers::slugify(&todo.title) == slug).cloned().collect())
+    }
+
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<Todo>, sqlx::Error> {
+        let todos = self.todos.lock().unwrap();
+        Ok(todos.iter().filter(|todo| ids.contains(&todo.id)).cloned().collect())
+    }
+
+    async fn updated_since(&self, since: NaiveDateTime) -> Result<Vec<Todo>, sqlx::Error> {
+        let mut todos: Vec<Todo> = self
+            .todos
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|todo| todo.updated_at > since)
+            .cloned()
+            .collect();
+        todos.sort_by_key(|todo| todo.updated_at);
+        Ok(todos)
+    }
+
+    async fn update(&self, id: Uuid, changes: TodoChanges) -> Result<Option<(Todo, bool)>, sqlx::Error> {
+        let mut completed_now = false;
+        let updated = self.modify(id, |todo| {
+            completed_now = !todo.completed && changes.completed;
+            todo.completed_at = match (todo.completed, changes.completed) {
+                (_, false) => None,
+                (false, true) => Some(chrono::Utc::now().naive_utc()),
+                (true, true) => todo.completed_at,
+            };
+            todo.title = changes.title;
+            todo.completed = changes.completed;
+            todo.due_date = changes.due_date;
+            todo.tags = changes.tags;
+            todo.recurrence = changes.recurrence;
+        });
+        Ok(updated.map(|todo| (todo, completed_now)))
+    }
+
+    async fn set_title(&self, id: Uuid, title: &str) -> Result<Option<Todo>, sqlx::Error> {
+        Ok(self.modify(id, |todo| todo.title = title.to_string()))
+    }
+
+    async fn set_archived(&self, id: Uuid, archived: bool) -> Result<Option<Todo>, sqlx::Error> {
+        let now = chrono::Utc::now().naive_utc();
+        Ok(self.modify(id, |todo| {
+            todo.archived_at = if archived { todo.archived_at.or(Some(now)) } else { None };
+        }))
+    }
+
+    async fn complete_many(&self, ids: &[Uuid], completed: bool) -> Result<(Vec<Todo>, Vec<Uuid>), sqlx::Error> {
+        let mut updated = Vec::new();
+        let mut opened = Vec::new();
+        for &id in ids {
+            let Some(todo) = self.get(id).await? else {
+                continue;
+            };
+            if !todo.completed {
+                opened.push(id);
+            }
+            let changes = TodoChanges {
+                title: todo.title,
+                completed,
+                due_date: todo.due_date,
+                tags: todo.tags,
+                recurrence: todo.recurrence,
+            };
+            updated.extend(self.update(id, changes).await?.map(|(todo, _)| todo));
+        }
+        Ok((updated, opened))
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool, sqlx::Error> {
+        let mut todos = self.todos.lock().unwrap();
+        let before = todos.len();
+        todos.retain(|todo| todo.id != id && todo.parent_id != Some(id));
+        Ok(todos.len() < before)
+    }
+
+    async fn delete_completed_older_than(&self, retention_days: i32) -> Result<u64, sqlx::Error> {
+        let cutoff = chrono::Utc::now().naive_utc() - chrono::Duration::days(retention_days.into());
+        let mut todos = self.todos.lock().unwrap();
+        let expired: Vec<Uuid> = todos
+            .iter()
+            .filter(|todo| todo.completed && todo.updated_at < cutoff)
+            .map(|todo| todo.id)
+            .collect();
+        todos.retain(|todo| !expired.contains(&todo.id) && !todo.parent_id.is_some_and(|parent| expired.contains(&parent)));
+        Ok(expired.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore]
+    async fn concurrent_updates_complete_a_todo_once(pool: PgPool) {
+        let store = PgTodoStore::new(pool);
+        let todo = store
+            .create(NewTodo { title: "x".into(), due_date: None, parent_id: None, tags: Vec::new(), recurrence: None })
+            .await
+            .unwrap();
+        let changes = TodoChanges { title: todo.title, completed: true, due_date: None, tags: Vec::new(), recurrence: None };
+
+        let (first, second) = tokio::join!(store.update(todo.id, changes.clone()), store.update(todo.id, changes));
+        let (first, second) = (first.unwrap().unwrap(), second.unwrap().unwrap());
+        assert!(first.0.completed && second.0.completed);
+        assert!(first.1 ^ second.1, "exactly one update completes the todo");
+    }
+
+    async fn insert(pool: &PgPool, completed: bool, days_ago: i32) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO todos (id, title, completed, updated_at) VALUES ($1, 'x', $2, now() - make_interval(days => $3))",
+        )
+        .bind(id)
+        .bind(completed)
+        .bind(days_ago)
+        .execute(pool)
+        .await
+        .unwrap();
+        id
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore]
+    async fn only_completed_todos_older_than_the_retention_are_deleted(pool: PgPool) {
+        let old_completed = insert(&pool, true, 31).await;
+        let recent_completed = insert(&pool, true, 29).await;
+        let old_open = insert(&pool, false, 31).await;
+
+        let store = PgTodoStore::new(pool.clone());
+        assert_eq!(store.delete_completed_older_than(30).await.unwrap(), 1);
+
+        let remaining: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM todos")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert!(!remaining.contains(&old_completed));
+        assert!(remaining.contains(&recent_completed));
+        assert!(remaining.contains(&old_open));
+    }
+}
@@ -0,0 +1,156 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use crate::error::AppError;
+use crate::models::Todo;
+
+// This module validates the fields of request payloads.
+// A Validator runs every check and collects the failures, so a payload with several
+// invalid fields is answered with one 422 listing all of them:
+// {"errors": [{"field": "title", "message": "..."}, ...]}
+
+// Names of the payload fields in error responses, in the casing of the JSON fields, see the camel-case feature
+const DUE_DATE: &str = if cfg!(feature = "camel-case") { "dueDate" } else { "due_date" };
+const PARENT_ID: &str = if cfg!(feature = "camel-case") { "parentId" } else { "parent_id" };
+
+// Longest title accepted, in characters
+pub const MAX_TITLE_LEN: usize = 200;
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator::default()
+    }
+
+    /// Record an error for `field` unless `valid` holds
+    pub fn check(&mut self, valid: bool, field: &'static str, message: impl Into<String>) -> &mut Self {
+        if !valid {
+            self.errors.push(FieldError { field, message: message.into() });
+        }
+        self
+    }
+
+    /// A title must not be empty or made only of whitespace, and at most MAX_TITLE_LEN characters long
+    pub fn title(&mut self, title: &str) -> &mut Self {
+        self.check(!title.trim().is_empty(), "title", "Title must not be empty");
+        self.check(
+            title.trim().chars().count() <= MAX_TITLE_LEN,
+            "title",
+            format!("Title must be at most {} characters", MAX_TITLE_LEN),
+        )
+    }
+
+    /// A due date, when given, must not be in the past
+    pub fn due_date(&mut self, due_date: Option<DateTime<Utc>>) -> &mut Self {
+        self.check(
+            due_date.is_none_or(|due| due > Utc::now()),
+            DUE_DATE,
+            "Due date must be in the future",
+        )
+    }
+
+    /// Every tag must contain more than whitespace
+    pub fn tags(&mut self, tags: &[String]) -> &mut Self {
+        self.check(
+            tags.iter().all(|tag| !tag.trim().is_empty()),
+            "tags",
+            "Tags must not be empty",
+        )
+    }
+
+    /// A parent, when given, must exist and must not be a subtask itself, subtasks are nested one level
+    /// `parent` is the todo found for `parent_id`, if any
+    pub fn parent(&mut self, parent_id: Option<Uuid>, parent: Option<&Todo>) -> &mut Self {
+        if parent_id.is_none() {
+            return self;
+        }
+        match parent {
+            None => self.check(false, PARENT_ID, "Parent todo does not exist"),
+            Some(parent) => self.check(parent.parent_id.is_none(), PARENT_ID, "A subtask cannot have subtasks"),
+        }
+    }
+
+    /// Return all collected errors as a 422 error, or Ok when every check passed
+    pub fn finish(&mut self) -> Result<(), AppError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::InvalidFields(std::mem::take(&mut self.errors)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_todo;
+    use chrono::Duration;
+
+    fn todo(parent_id: Option<Uuid>) -> Todo {
+        Todo { parent_id, ..test_todo("parent") }
+    }
+
+    // The fields of the errors `validator` collected, in order
+    fn failed_fields(validator: &mut Validator) -> Vec<&'static str> {
+        match validator.finish() {
+            Ok(()) => Vec::new(),
+            Err(AppError::InvalidFields(errors)) => errors.iter().map(|error| error.field).collect(),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn valid_fields_pass() {
+        let parent = todo(None);
+        let mut validator = Validator::new();
+        validator
+            .title("buy milk")
+            .due_date(Some(Utc::now() + Duration::days(1)))
+            .due_date(None)
+            .tags(&["home".to_string()])
+            .parent(Some(parent.id), Some(&parent))
+            .parent(None, None);
+        assert!(validator.finish().is_ok());
+    }
+
+    #[test]
+    fn title_must_not_be_blank_or_too_long() {
+        assert_eq!(failed_fields(Validator::new().title("")), ["title"]);
+        assert_eq!(failed_fields(Validator::new().title("   ")), ["title"]);
+        assert_eq!(failed_fields(Validator::new().title(&"x".repeat(MAX_TITLE_LEN))), Vec::<&str>::new());
+        assert_eq!(failed_fields(Validator::new().title(&"é".repeat(MAX_TITLE_LEN + 1))), ["title"]);
+    }
+
+    #[test]
+    fn due_date_tags_and_parent_are_checked() {
+        assert_eq!(failed_fields(Validator::new().due_date(Some(Utc::now() - Duration::hours(1)))), [DUE_DATE]);
+        assert_eq!(failed_fields(Validator::new().tags(&["work".to_string(), " ".to_string()])), ["tags"]);
+        assert_eq!(failed_fields(Validator::new().parent(Some(Uuid::new_v4()), None)), [PARENT_ID]);
+
+        let subtask = todo(Some(Uuid::new_v4()));
+        assert_eq!(failed_fields(Validator::new().parent(Some(subtask.id), Some(&subtask))), [PARENT_ID]);
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let mut validator = Validator::new();
+        validator
+            .title(" ")
+            .due_date(Some(Utc::now() - Duration::days(1)))
+            .tags(&["".to_string()])
+            .parent(Some(Uuid::new_v4()), None);
+        assert_eq!(failed_fields(&mut validator), ["title", DUE_DATE, "tags", PARENT_ID]);
+        // finish hands the errors over, the validator can be reused
+        assert!(validator.finish().is_ok());
+    }
+}
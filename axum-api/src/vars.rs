@@ -0,0 +1,16 @@
+// This module provides the variable lookups used by the configuration parsers.
+// A parser such as CleanupConfig::from_vars takes the lookup as a function, `env_var` in the
+// server and `test_vars` in the tests, so the tests do not change the process environment.
+
+/// Look a variable up in the process environment
+pub fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Look variables up in a fixed list, e.g. test_vars(&[("DB_HOST", "db")])
+#[cfg(test)]
+pub fn test_vars(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> + use<> {
+    let vars: std::collections::HashMap<String, String> =
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+    move |name| vars.get(name).cloned()
+}
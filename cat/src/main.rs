@@ -1,13 +1,156 @@
-use std::fs::File; 
-use std::io::{self, BufWriter, BufRead, BufReader, Write};
+use std::fs::{self, File}; 
+use std::collections::VecDeque;
+use std::io::{self, BufWriter, BufRead, BufReader, IsTerminal, Read, Write};
 use std::env;
+use std::fmt;
 use std::process;
+use std::thread;
+use std::time::Duration;
+use regex::Regex;
+use base64::{
+    alphabet,
+    engine::{general_purpose::STANDARD, DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    DecodeError, Engine,
+};
+
+/// Errors reported by cat
+/// Each variant carries the file name it refers to, InvalidOption the whole message
+/// since unknown options, bad option values and conflicting options are worded differently,
+/// the Display output follows the coreutils wording
+#[derive(Debug)]
+enum CatError {
+    MissingFile(String),
+    IsDirectory(String),
+    PermissionDenied(String),
+    InvalidOption(String),
+    InvalidBase64(DecodeError),
+    Io(String, io::Error),
+}
+
+impl CatError {
+    /// Convert an io::Error raised while reading `name` into the matching variant
+    fn from_io(name: &str, err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => CatError::MissingFile(name.to_string()),
+            io::ErrorKind::IsADirectory => CatError::IsDirectory(name.to_string()),
+            io::ErrorKind::PermissionDenied => CatError::PermissionDenied(name.to_string()),
+            _ => CatError::Io(name.to_string(), err),
+        }
+    }
+
+    /// A command line option that cat does not know
+    fn unknown_option(option: &str) -> Self {
+        CatError::InvalidOption(format!("invalid option -- '{}'", option))
+    }
+
+    /// A value that is not valid for its option, e.g. `invalid line range: '5:2'`
+    fn invalid_value(what: &str, value: &str) -> Self {
+        CatError::InvalidOption(format!("invalid {}: '{}'", what, value))
+    }
+
+    /// Exit code used by main when this error occurs
+    /// Usage errors exit with 2, file and io errors exit with 1
+    fn exit_code(&self) -> i32 {
+        match self {
+            CatError::InvalidOption(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for CatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatError::MissingFile(name) => write!(f, "{}: No such file or directory", name),
+            CatError::IsDirectory(name) => write!(f, "{}: Is a directory", name),
+            CatError::PermissionDenied(name) => write!(f, "{}: Permission denied", name),
+            CatError::InvalidOption(message) => write!(f, "{}", message),
+            CatError::InvalidBase64(err) => write!(f, "invalid base64 input: {}", err),
+            CatError::Io(name, err) => write!(f, "{}: {}", name, err),
+        }
+    }
+}
+
+/// When to print a `==> name <==` header before each input, set by -H/--headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Headers {
+    Off,
+    // only when more than one input is given, like `tail`
+    Multiple,
+    // even for a single input, set by --headers=always
+    Always,
+}
+
+/// Text encoding written by --encode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Base64,
+    Hex,
+}
+
+impl Encoding {
+    /// Line width used when --wrap is not given, 0 means the output is not wrapped
+    fn default_wrap(self) -> usize {
+        match self {
+            Encoding::Base64 => 76,
+            Encoding::Hex => 0,
+        }
+    }
+}
+
+/// Base used for the -n line numbers, set by --number-format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumberFormat {
+    Decimal,
+    Hex,
+}
 
 // config struct to hold the configuration options
 struct Config{
     show_line_numbers: bool,
+    number_format: NumberFormat,
     show_nonprinting: bool, 
-    squeeze_blank: bool
+    squeeze_blank: bool,
+    // drop lines equal to the line before them, set by -u
+    unique: bool,
+    count_lines: bool,
+    // print line, word and byte counts to stderr once all input is written
+    show_stats: bool,
+    // tab stop width used by --expand-tabs, None when tabs are left as they are
+    expand_tabs: Option<usize>,
+    // inclusive 1-based (start, end) range selected by --lines, end is None when open
+    line_range: Option<(usize, Option<usize>)>,
+    // only lines matching this pattern are printed, set by --match
+    match_pattern: Option<Regex>,
+    // highlight the matched substrings, set by --color
+    color: bool,
+    // lines longer than this many characters are cut off, set by --max-line-length
+    max_line_length: Option<usize>,
+    // only the first or last N output lines of each file are printed, set by --head and --tail
+    head: Option<usize>,
+    tail: Option<usize>,
+    // copy the input byte for byte without any line processing, set by -b/--binary
+    binary: bool,
+    // do not print the hint shown when reading from a terminal, set by --quiet
+    quiet: bool,
+    // also write the output to this file, set by --tee, appending with --tee-append
+    tee: Option<String>,
+    tee_append: bool,
+    // leave out zero-byte files entirely, set by --skip-empty-files
+    skip_empty_files: bool,
+    headers: Headers,
+    // base64-decode the concatenated input, set by --decode
+    decode: bool,
+    // decode with the URL-safe alphabet even without - or _ in the input, set by --url-safe
+    url_safe: bool,
+    // write the base64 or hex encoding of each input, set by --encode
+    encode: Option<Encoding>,
+    // line width of the --encode output, set by --wrap, 0 disables wrapping
+    wrap: Option<usize>,
+    // flush the output after every line, or every read in binary mode, set by -U/--unbuffered
+    unbuffered: bool,
+    // lines end with NUL instead of newline, in the input and the output, set by -z/--null-data
+    null_data: bool,
 }
 
 // Implementation of the Config struct
@@ -16,60 +159,609 @@ impl Config{
 
         Config {
             show_line_numbers: false, 
+            number_format: NumberFormat::Decimal,
             show_nonprinting: false,
             squeeze_blank: false,
+            unique: false,
+            count_lines: false,
+            show_stats: false,
+            expand_tabs: None,
+            line_range: None,
+            match_pattern: None,
+            color: false,
+            max_line_length: None,
+            head: None,
+            tail: None,
+            binary: false,
+            quiet: false,
+            tee: None,
+            tee_append: false,
+            skip_empty_files: false,
+            headers: Headers::Off,
+            decode: false,
+            url_safe: false,
+            encode: None,
+            wrap: None,
+            unbuffered: false,
+            null_data: false,
+
+        }
+    }
+
+    /// Whether any option is set that reads the input line by line
+    /// These options cannot be combined with --binary
+    fn processes_lines(&self) -> bool {
+        self.show_line_numbers
+            || self.show_nonprinting
+            || self.squeeze_blank
+            || self.unique
+            || self.count_lines
+            || self.show_stats
+            || self.expand_tabs.is_some()
+            || self.line_range.is_some()
+            || self.match_pattern.is_some()
+            || self.color
+            || self.max_line_length.is_some()
+            || self.head.is_some()
+            || self.tail.is_some()
+            || self.null_data
+    }
+
+    /// The byte that ends a line, NUL with -z and newline otherwise
+    fn line_terminator(&self) -> u8 {
+        if self.null_data { b'\0' } else { b'\n' }
+    }
+}
+
+/// How often concatenate_reader retries a read that fails with WouldBlock before giving up,
+/// and how long it waits before each retry
+const WOULD_BLOCK_RETRIES: u32 = 100;
+const WOULD_BLOCK_DELAY: Duration = Duration::from_millis(10);
+
+/// Line, word and byte counts of an input, as reported by --stats
+/// Words are whitespace-delimited, bytes include the line endings
+/// Lines are counted by their terminator like `wc -l`, so a last line without one adds no line
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    lines: usize,
+    words: usize,
+    bytes: usize,
+}
+
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Print a `wc` style summary to stderr, keeping stdout clean for piping
+/// Each input gets a line, followed by a total when there is more than one
+fn print_stats(file_stats: &[(String, Stats)]) {
+    let mut total = Stats::default();
+    for (name, stats) in file_stats {
+        eprintln!("{:7} {:7} {:7} {}", stats.lines, stats.words, stats.bytes, name);
+        total.add(stats);
+    }
+    if file_stats.len() > 1 {
+        eprintln!("{:7} {:7} {:7} total", total.lines, total.words, total.bytes);
+    }
+}
+
+/// Destination of the concatenated output
+/// Everything is written to `stdout` and, with --tee, to the tee file as well
+/// A failed write names the sink it failed on, e.g. `stdout: Broken pipe`
+/// When the tee file fails, writing to stdout carries on and the error is returned by flush
+struct Output<S: Write> {
+    stdout: S,
+    tee: Option<(String, File)>,
+    tee_error: Option<io::Error>,
+}
 
+impl<S: Write> Output<S> {
+    /// Open the tee file of `config`, if any, truncating it unless --tee-append is set
+    fn new(stdout: S, config: &Config) -> Result<Self, CatError> {
+        let tee = match &config.tee {
+            Some(path) => {
+                let file = File::options()
+                    .create(true)
+                    .write(true)
+                    .append(config.tee_append)
+                    .truncate(!config.tee_append)
+                    .open(path)
+                    .map_err(|e| CatError::from_io(path, e))?;
+                Some((path.clone(), file))
+            },
+            None => None,
+        };
+        Ok(Output { stdout, tee, tee_error: None })
+    }
+}
+
+/// Prefix an io::Error with the name of the sink it happened on
+fn sink_error(sink: &str, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", sink, err))
+}
+
+impl<S: Write> Write for Output<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write_all(buf).map_err(|e| sink_error("stdout", e))?;
+        if let Some((path, mut file)) = self.tee.take() {
+            match file.write_all(buf) {
+                Ok(()) => self.tee = Some((path, file)),
+                Err(e) => self.tee_error = Some(sink_error(&path, e)),
+            }
         }
+        Ok(buf.len())
     }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush().map_err(|e| sink_error("stdout", e))?;
+        if let Some((path, file)) = &mut self.tee {
+            file.flush().map_err(|e| sink_error(path, e))?;
+        }
+        match self.tee_error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A single step of the per-line output pipeline
+/// Transforms are applied in order to every line of the input,
+/// returning None drops the line so later transforms never see it
+/// `line_number` is the 1-based position of the line in the input
+trait LineTransform {
+    fn apply(&mut self, line_number: usize, line: String) -> Option<String>;
+}
+
+/// --lines: drop every line outside the selected range
+struct LineRange {
+    start: usize,
+    end: Option<usize>,
 }
 
-/// Concatenate files and print to stdout
+impl LineTransform for LineRange {
+    fn apply(&mut self, line_number: usize, line: String) -> Option<String> {
+        if line_number < self.start || self.end.is_some_and(|end| line_number > end) {
+            return None;
+        }
+        Some(line)
+    }
+}
+
+/// --match: drop every line that does not match the pattern
+struct MatchFilter {
+    pattern: Regex,
+}
+
+impl LineTransform for MatchFilter {
+    fn apply(&mut self, _line_number: usize, line: String) -> Option<String> {
+        if self.pattern.is_match(&line) {
+            Some(line)
+        } else {
+            None
+        }
+    }
+}
+
+/// --color: wrap each match of the pattern in the ANSI colour codes used by grep
+struct Highlight {
+    pattern: Regex,
+}
+
+impl LineTransform for Highlight {
+    fn apply(&mut self, _line_number: usize, line: String) -> Option<String> {
+        Some(self.pattern.replace_all(&line, "\x1b[01;31m${0}\x1b[0m").into_owned())
+    }
+}
+
+/// -s: drop a blank line if the previous line was blank too
+struct SqueezeBlank {
+    last_line_was_empty: bool,
+}
+
+impl LineTransform for SqueezeBlank {
+    fn apply(&mut self, _line_number: usize, line: String) -> Option<String> {
+        // If the line is empty and the last line was also empty, skip this line
+        if line.trim().is_empty() {
+            if self.last_line_was_empty {
+                return None;
+            }
+            self.last_line_was_empty = true;
+        } else {
+            self.last_line_was_empty = false;
+        }
+        Some(line)
+    }
+}
+
+/// -u: drop a line if it is exactly the same as the previous line, like `uniq`
+/// Only consecutive repeats are dropped, a line seen earlier in the file is printed again
+struct Unique {
+    previous: Option<String>,
+}
+
+impl LineTransform for Unique {
+    fn apply(&mut self, _line_number: usize, line: String) -> Option<String> {
+        if self.previous.as_ref() == Some(&line) {
+            return None;
+        }
+        self.previous = Some(line.clone());
+        Some(line)
+    }
+}
+
+/// --expand-tabs: replace tabs with spaces up to the next tab stop
+struct ExpandTabs {
+    width: usize,
+}
+
+impl LineTransform for ExpandTabs {
+    fn apply(&mut self, _line_number: usize, line: String) -> Option<String> {
+        Some(expand_tabs(&line, self.width))
+    }
+}
+
+/// -v: escape non-printing characters
+struct EscapeNonprinting;
+
+impl LineTransform for EscapeNonprinting {
+    fn apply(&mut self, _line_number: usize, line: String) -> Option<String> {
+        Some(escape_nonprinting(&line))
+    }
+}
+
+/// --max-line-length: cut lines longer than `max` characters and mark them with `…`
+/// Characters are counted rather than bytes, so multi-byte UTF-8 characters are never split
+struct Truncate {
+    max: usize,
+}
+
+impl LineTransform for Truncate {
+    fn apply(&mut self, _line_number: usize, line: String) -> Option<String> {
+        match line.char_indices().nth(self.max) {
+            Some((end, _)) => Some(format!("{}…", &line[..end])),
+            None => Some(line),
+        }
+    }
+}
+
+/// -n: prefix each output line with its line number
+/// Output lines are numbered consecutively, unless `original` is set,
+/// then the position of the line in the input is shown instead
+/// The number is written in decimal or hexadecimal, depending on `format`
+struct NumberLines {
+    next: usize,
+    original: bool,
+    format: NumberFormat,
+}
+
+impl LineTransform for NumberLines {
+    fn apply(&mut self, line_number: usize, line: String) -> Option<String> {
+        let number = if self.original { line_number } else { self.next };
+        self.next += 1;
+        match self.format {
+            NumberFormat::Decimal => Some(format!("{:6}  {}", number, line)),
+            NumberFormat::Hex => Some(format!("{:6x}  {}", number, line)),
+        }
+    }
+}
+
+/// Assemble the line transforms enabled in `config`
+/// The order is line range, match, squeeze, unique, expand tabs, escape, truncate, highlight,
+/// followed by --head or --tail and the -n number from `number_lines`,
+/// so that squeezed lines are never numbered, the number prefix and colour codes
+/// are never escaped and truncation counts the characters as they are displayed
+fn build_pipeline(config: &Config) -> Vec<Box<dyn LineTransform>> {
+    let mut pipeline: Vec<Box<dyn LineTransform>> = Vec::new();
+
+    if let Some((start, end)) = config.line_range {
+        pipeline.push(Box::new(LineRange { start, end }));
+    }
+    if let Some(pattern) = &config.match_pattern {
+        pipeline.push(Box::new(MatchFilter { pattern: pattern.clone() }));
+    }
+
+    if config.squeeze_blank {
+        pipeline.push(Box::new(SqueezeBlank { last_line_was_empty: false }));
+    }
+    if config.unique {
+        pipeline.push(Box::new(Unique { previous: None }));
+    }
+    if let Some(width) = config.expand_tabs {
+        pipeline.push(Box::new(ExpandTabs { width }));
+    }
+    if config.show_nonprinting {
+        pipeline.push(Box::new(EscapeNonprinting));
+    }
+    if let Some(max) = config.max_line_length {
+        pipeline.push(Box::new(Truncate { max }));
+    }
+    if let (Some(pattern), true) = (&config.match_pattern, config.color) {
+        pipeline.push(Box::new(Highlight { pattern: pattern.clone() }));
+    }
+
+    pipeline
+}
+
+/// The -n transform, run on the lines that are written, after --head and --tail
+/// A fresh one is built per input, it continues counting at `first_number`,
+/// so the output lines are numbered consecutively across all files, like `cat -n a b`
+/// With --lines or --match, -n shows the original line numbers of the selected lines in their file
+fn number_lines(config: &Config, first_number: usize) -> Option<NumberLines> {
+    config.show_line_numbers.then(|| NumberLines {
+        next: first_number,
+        original: config.line_range.is_some() || config.match_pattern.is_some(),
+        format: config.number_format,
+    })
+}
+
+/// Concatenate files and print to `output`, which is stdout and the --tee file, if any
 /// If no files are provided, read from stdin and write to stdout
 /// If the -n option is provided, show line numbers
 /// If the -s option is provided, remove repeated blank lines from output
 /// If the -v option is provided, show non-printing characters in the output
 /// If the -h option is provided, show help message
+/// If the -b option is provided, copy the file byte for byte
 /// If an invalid option is provided, print an error message and exit
-/// If a file does not exist, print an error message and exit
-fn concatenate_file(filename: &str, config: &Config) -> io::Result<()>{
+/// If a file cannot be opened or read, the error is returned and main continues with the next file
+/// A filename of `-` reads stdin
+/// `header` is written once the file is opened, so missing files get no header
+/// `line_number` is the -n number of the next output line, it is advanced past the lines of the file
+/// Returns the line, word and byte counts of the file
+fn concatenate_file<W: Write>(
+    filename: &str,
+    config: &Config,
+    output: &mut W,
+    header: Option<&str>,
+    line_number: &mut usize,
+) -> Result<Stats, CatError>{
 
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    let mut stdout = BufWriter::new(io::stdout());
+    let mut input: Box<dyn Read> = if filename == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(filename).map_err(|e| CatError::from_io(filename, e))?)
+    };
 
-    let mut line_number = 1;
-    let mut last_line_was_empty = false;
+    if let Some(header) = header {
+        writeln!(output, "{}", header).map_err(|e| CatError::from_io(filename, e))?;
+    }
 
-    for line in reader.lines(){
-        let mut line = line?;
+    // In binary mode the file is copied as is, so NUL bytes and invalid UTF-8 pass through
+    if config.binary {
+        copy_bytes(&mut input, output, config.unbuffered).map_err(|e| CatError::from_io(filename, e))?;
+        return Ok(Stats::default());
+    }
 
-        // If the -s option is provided, remove repeated blank lines
-        // If the line is empty and the last line was also empty, skip this line
-        if config.squeeze_blank && line.trim().is_empty(){
-            if last_line_was_empty {
+    concatenate_reader(BufReader::new(input), filename, config, output, line_number)
+        .map_err(|e| CatError::from_io(filename, e))
+}
+
+/// Write the contents of an already opened reader to `output`
+/// `name` is the name reported for the input, `-` is used for stdin
+/// If the -c option is provided, only the line count is printed as `<count> <name>`
+/// A read error ends the input early, the lines read so far are still written
+/// and flushed before the error is returned
+/// `line_number` is the -n number of the next output line, every line that is written
+/// advances it, also when the input ends with an error, lines dropped by --head or --tail do not
+/// Returns the line, word and byte counts of the input
+fn concatenate_reader<R: BufRead, W: Write>(
+    mut reader: R,
+    name: &str,
+    config: &Config,
+    output: &mut W,
+    line_number: &mut usize,
+) -> io::Result<Stats>{
+
+    let mut output = BufWriter::new(output);
+    let mut pipeline = build_pipeline(config);
+    let mut number = number_lines(config, *line_number);
+    let mut stats = Stats::default();
+    // 1-based number of the current input line, for the transforms
+    let mut index = 0;
+    let mut buf = Vec::new();
+    let terminator = config.line_terminator();
+    // --head counts the lines written so far, --tail keeps the last lines until the end
+    let mut written = 0;
+    let mut last_lines = VecDeque::new();
+    let mut read_error = None;
+    let mut would_block_retries = 0;
+
+    // Number the line if -n is set and write it with its terminator
+    // `index` is the position of the line in the input, shown by -n with --lines or --match
+    let mut write_line = |output: &mut BufWriter<&mut W>, index: usize, line: String| -> io::Result<()> {
+        let line = match &mut number {
+            Some(number) => number.apply(index, line).expect("numbering never drops a line"),
+            None => line,
+        };
+        *line_number += 1;
+        write!(output, "{}{}", line, terminator as char)?;
+        // With -U the line is passed on right away instead of waiting for the buffer to fill
+        if config.unbuffered {
+            output.flush()?;
+        }
+        Ok(())
+    };
+
+    loop {
+        if read_error.is_some() {
+            break;
+        }
+
+        // With --head, stop reading once enough lines are written,
+        // unless --stats needs to see the whole input
+        if config.head.is_some_and(|head| written >= head) && !config.show_stats {
+            break;
+        }
+
+        // read_until keeps the line ending, so the byte count stays exact
+        // It retries Interrupted reads itself, WouldBlock reads, as seen on some network filesystems,
+        // are retried here up to WOULD_BLOCK_RETRIES times in a row before the error is returned
+        // Data read before the error stays in buf, so the retry completes the same line
+        let bytes = match reader.read_until(terminator, &mut buf) {
+            Ok(bytes) => {
+                would_block_retries = 0;
+                bytes
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && would_block_retries < WOULD_BLOCK_RETRIES => {
+                would_block_retries += 1;
+                thread::sleep(WOULD_BLOCK_DELAY);
                 continue;
-            }
-            last_line_was_empty = true;   
-        } else {
-            last_line_was_empty = false;
+            },
+            // Any other error ends the input, a partly read line is still written
+            Err(e) => {
+                read_error = Some(e);
+                0
+            },
+        };
+        if bytes == 0 && buf.is_empty() {
+            break;
         }
+        // Take the whole line, including any part read before a retried error
+        // Like read_line, a line that is not valid UTF-8 ends the input with an error
+        let text = match String::from_utf8(std::mem::take(&mut buf)) {
+            Ok(text) => text,
+            Err(_) => {
+                read_error = Some(io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"));
+                continue;
+            },
+        };
+        let bytes = text.len();
+        let terminated = text.ends_with(terminator as char);
+        let line = text.strip_suffix(terminator as char).unwrap_or(&text);
+        // A CRLF line ending is only stripped as a whole, NUL-terminated lines keep their \r
+        let line = if config.null_data { line } else { line.strip_suffix('\r').unwrap_or(line) };
+
+        // Like `wc -l`, only terminated lines are counted, a last line without a newline is not
+        index += 1;
+        stats.lines += usize::from(terminated);
+        stats.words += line.split_whitespace().count();
+        stats.bytes += bytes;
 
-        // If the -v option is provided, escape non-printing characters
-        if config.show_nonprinting {
-            line = escape_nonprinting(&line);
+        // If the -c option is provided, skip the normal write path
+        // and only count the lines of the input
+        if config.count_lines {
+            continue;
         }
 
-        // If the -n option is provided, show line numbers
-        if config.show_line_numbers{
-            writeln!(&mut stdout, "{:6}  {}", line_number, line)?;
-            line_number += 1;
+        // Run the line through each transform in order,
+        // a transform returning None drops the line from the output
+        if let Some(line) = pipeline.iter_mut().try_fold(line.to_string(), |line, transform| transform.apply(index, line)) {
+            if let Some(tail) = config.tail {
+                // Keep only the last `tail` lines, they are written once the input ends
+                if last_lines.len() == tail {
+                    last_lines.pop_front();
+                }
+                if tail > 0 {
+                    last_lines.push_back((index, line));
+                }
+            } else if config.head.is_none_or(|head| written < head) {
+                write_line(&mut output, index, line)?;
+                written += 1;
+            }
+        }
+    }
+
+    for (index, line) in last_lines {
+        write_line(&mut output, index, line)?;
+    }
+
+    // Like `wc`, an input that failed to read gets no count and is left out of the total
+    if config.count_lines && read_error.is_none() {
+        writeln!(&mut output, "{} {}", stats.lines, name)?;
+    }
+
+    output.flush()?;
+    match read_error {
+        Some(e) => Err(e),
+        None => Ok(stats),
+    }
+}
+
+/// Write stdin, given as `input`, to `output` when no files are given
+/// With any option that processes lines the input goes through concatenate_reader like a file named `-`,
+/// otherwise it is copied byte for byte
+/// Returns the line, word and byte counts of the input, which are only counted in line mode
+fn concatenate_stdin<R: BufRead, W: Write>(mut input: R, config: &Config, output: &mut W) -> io::Result<Stats> {
+    if config.processes_lines() {
+        return concatenate_reader(input, "-", config, output, &mut 1);
+    }
+    copy_bytes(&mut input, output, config.unbuffered)?;
+    Ok(Stats::default())
+}
+
+/// Copy `input` to `output` byte for byte
+/// With `unbuffered` every chunk is flushed as soon as it is read,
+/// so data without a trailing newline is not held back by the line-buffered stdout
+fn copy_bytes<R: Read + ?Sized, W: Write + ?Sized>(input: &mut R, output: &mut W, unbuffered: bool) -> io::Result<u64> {
+    if !unbuffered {
+        return io::copy(input, output);
+    }
+
+    let mut buf = [0u8; 8192];
+    let mut copied = 0;
+    loop {
+        let bytes = match input.read(&mut buf) {
+            Ok(0) => return Ok(copied),
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        output.write_all(&buf[..bytes])?;
+        output.flush()?;
+        copied += bytes as u64;
+    }
+}
+
+/// Replace each tab with enough spaces to reach the next multiple of `width`
+/// The current column is tracked per line, so tabs after text only fill
+/// the remainder of their tab stop, like the `expand` utility
+fn expand_tabs(s: &str, width: usize) -> String {
+    let mut result = String::new();
+    let mut column = 0;
+    for c in s.chars(){
+        if c == '\t' {
+            let spaces = width - (column % width);
+            result.push_str(&" ".repeat(spaces));
+            column += spaces;
         } else {
-            writeln!(&mut stdout, "{}", line)?;
+            result.push(c);
+            column += 1;
         }
     }
+    result
+}
 
-    stdout.flush()?;
-    Ok(())
+/// Parse a `--lines` range of the form `A:B`, `A:` or `:B`
+/// Both bounds are 1-based and inclusive, a missing start means the first line
+/// and a missing end means the last line
+/// Returns None if the range is malformed or empty
+fn parse_line_range(range: &str) -> Option<(usize, Option<usize>)> {
+    let (start, end) = range.split_once(':')?;
+    let start = if start.is_empty() { 1 } else { start.parse::<usize>().ok()? };
+    let end = if end.is_empty() { None } else { Some(end.parse::<usize>().ok()?) };
+
+    if start == 0 || end.is_some_and(|end| end < start) {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// Parse the value of `--number-format`, decimal or hex
+fn parse_number_format(value: &str) -> Result<NumberFormat, CatError> {
+    match value {
+        "decimal" => Ok(NumberFormat::Decimal),
+        "hex" => Ok(NumberFormat::Hex),
+        _ => Err(CatError::InvalidOption(format!("invalid number format: '{}' (expected decimal or hex)", value))),
+    }
+}
+
+/// Compile the pattern of `--match`, an invalid pattern is an invalid option value
+fn parse_match_pattern(pattern: &str) -> Result<Regex, CatError> {
+    Regex::new(pattern)
+        .map_err(|e| CatError::InvalidOption(format!("invalid pattern '{}': {}", pattern, e)))
 }
 
 fn escape_nonprinting(s: &str) -> String {
@@ -81,7 +773,8 @@ fn escape_nonprinting(s: &str) -> String {
             match c {
                 // convert tab to ^I representation of tab character
                 '\t' => result.push_str("^I"),
-                '\n' => {}, // ignore newline
+                // a newline inside a line only occurs with -z, where it is data and kept as is
+                '\n' => result.push('\n'),
                 // convert to ascii characters 
                 _ => result.push_str(&format!("^{}", (c as u8 + 64) as char)),
 
@@ -98,6 +791,184 @@ fn escape_nonprinting(s: &str) -> String {
     result
 }
 
+/// Read all inputs into one buffer, for --decode
+/// Without filenames stdin is read, a `-` filename reads stdin too
+fn read_inputs(filenames: &[String]) -> Result<Vec<u8>, CatError> {
+    let stdin = ["-".to_string()];
+    let filenames = if filenames.is_empty() { &stdin[..] } else { filenames };
+
+    let mut input = Vec::new();
+    for filename in filenames {
+        let result = if filename == "-" {
+            io::stdin().read_to_end(&mut input)
+        } else {
+            File::open(filename).and_then(|mut file| file.read_to_end(&mut input))
+        };
+        result.map_err(|e| CatError::from_io(filename, e))?;
+    }
+    Ok(input)
+}
+
+/// Decode base64 text, ignoring whitespace such as the line breaks of wrapped base64
+/// The URL-safe alphabet is used with `url_safe` or when the text contains - or _,
+/// the = padding is optional for both alphabets
+fn decode_base64(input: &[u8], url_safe: bool) -> Result<Vec<u8>, CatError> {
+    let text: Vec<u8> = input.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+    let alphabet = if url_safe || text.iter().any(|&b| b == b'-' || b == b'_') {
+        &alphabet::URL_SAFE
+    } else {
+        &alphabet::STANDARD
+    };
+
+    let config = GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+    GeneralPurpose::new(alphabet, config)
+        .decode(&text)
+        .map_err(CatError::InvalidBase64)
+}
+
+/// Encode bytes as base64 or lowercase hex, for --encode
+/// The text is split into lines of `wrap` characters, each ending with a newline,
+/// a `wrap` of 0 writes a single line; empty input gives empty output
+fn encode_bytes(bytes: &[u8], encoding: Encoding, wrap: usize) -> String {
+    let text = match encoding {
+        Encoding::Base64 => STANDARD.encode(bytes),
+        Encoding::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+    };
+    if text.is_empty() {
+        return text;
+    }
+
+    // Both encodings are ASCII, so the text can be split at any byte
+    let width = if wrap == 0 { text.len() } else { wrap };
+    let mut wrapped = String::with_capacity(text.len() + text.len() / width + 1);
+    for line in text.as_bytes().chunks(width) {
+        wrapped.push_str(std::str::from_utf8(line).expect("encoded text is ASCII"));
+        wrapped.push('\n');
+    }
+    wrapped
+}
+
+/// Read the list of filenames given to --files-from or --files0-from
+/// The filenames are separated by `separator`, a newline or a NUL byte,
+/// empty entries are skipped
+/// A `-` path reads the list from `stdin`
+fn read_file_list<R: Read>(path: &str, separator: u8, mut stdin: R) -> Result<Vec<String>, CatError> {
+    let mut list = Vec::new();
+    let result = if path == "-" {
+        stdin.read_to_end(&mut list)
+    } else {
+        File::open(path).and_then(|mut file| file.read_to_end(&mut list))
+    };
+    result.map_err(|e| CatError::from_io(path, e))?;
+
+    Ok(list
+        .split(|&b| b == separator)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect())
+}
+
+/// Get the value of an option given as `--name=value` or `--name value`
+/// For the second form the value is the next argument, `i` is advanced past it
+/// A missing value is returned as an empty string
+fn option_value(args: &[String], i: &mut usize, name: &str) -> String {
+    match args[*i].strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+        Some(value) => value.to_string(),
+        None => {
+            *i += 1;
+            args.get(*i).cloned().unwrap_or_default()
+        }
+    }
+}
+
+/// Get the tab stop width of `--expand-tabs`, given as `--expand-tabs=N` or `--expand-tabs N`
+/// The width is optional, so the next argument is only taken when it is a number,
+/// otherwise it stays a filename and the default width of 8 is used
+fn expand_tabs_width(args: &[String], i: &mut usize) -> Result<usize, CatError> {
+    let next_is_number = args.get(*i + 1).is_some_and(|next| !next.is_empty() && next.bytes().all(|b| b.is_ascii_digit()));
+    if args[*i] == "--expand-tabs" && !next_is_number {
+        return Ok(8);
+    }
+
+    let value = option_value(args, i, "--expand-tabs");
+    match value.parse::<usize>() {
+        Ok(width) if width > 0 => Ok(width),
+        _ => Err(CatError::invalid_value("tab size", &value)),
+    }
+}
+
+/// Insert the options from CAT_OPTS right after the program name
+/// The value is split on whitespace, e.g. CAT_OPTS="-n --expand-tabs=4",
+/// and parsed like command line arguments, so the command line can still add to them
+/// An option with a value given on the command line overrides the one from CAT_OPTS,
+/// since it is parsed later; flags like -n have no negated form, so they cannot be turned off again
+fn with_env_options(mut args: Vec<String>, env_options: Option<String>) -> Vec<String> {
+    if let Some(options) = env_options {
+        let position = 1.min(args.len());
+        args.splice(position..position, options.split_whitespace().map(String::from));
+    }
+    args
+}
+
+/// Check that the options of `config` can be combined
+/// Returns the first conflict found as an invalid option
+fn check_options(config: &Config) -> Result<(), CatError> {
+    let conflict = |message: &str| Err(CatError::InvalidOption(message.to_string()));
+
+    // tabs can either be expanded or escaped as ^I, not both
+    if config.expand_tabs.is_some() && config.show_nonprinting {
+        return conflict("--expand-tabs cannot be combined with -v");
+    }
+
+    // a file can be previewed from the start or from the end, not both
+    if config.head.is_some() && config.tail.is_some() {
+        return conflict("--head cannot be combined with --tail");
+    }
+
+    // binary mode guarantees an unmodified copy, so no line processing or headers may be enabled
+    if config.binary && (config.processes_lines() || config.headers != Headers::Off) {
+        return conflict("--binary cannot be combined with options that process lines or print headers");
+    }
+
+    // decoding writes raw bytes, so the output may not be changed by any other option
+    if config.decode && (config.binary || config.processes_lines() || config.headers != Headers::Off) {
+        return conflict("--decode cannot be combined with options that process lines or print headers");
+    }
+
+    // the encoding covers the raw bytes of each input, so no line processing may be enabled
+    if config.encode.is_some() && (config.decode || config.binary || config.processes_lines() || config.headers != Headers::Off) {
+        return conflict("--encode cannot be combined with --decode or options that process lines or print headers");
+    }
+
+    Ok(())
+}
+
+/// The hint printed when cat reads stdin without any files, if it is shown at all
+/// Without files cat waits for input, so the user is told when they are typing it in a terminal,
+/// piped or redirected stdin and --quiet never show the hint
+fn stdin_hint(is_terminal: bool, quiet: bool) -> Option<&'static str> {
+    (is_terminal && !quiet).then_some("reading from stdin; press Ctrl-D to end")
+}
+
+/// Print the stdin_hint to stderr, if any
+fn print_stdin_hint(program: &str, config: &Config) {
+    if let Some(hint) = stdin_hint(io::stdin().is_terminal(), config.quiet) {
+        eprintln!("{}: {}", program, hint);
+    }
+}
+
+/// Print a usage error and exit with its exit code, 2 for an invalid option
+fn exit_with(program: &str, err: CatError) -> ! {
+    eprintln!("{}: {}", program, err);
+    process::exit(err.exit_code())
+}
+
+/// Like exit_with, followed by the usage information
+fn exit_with_usage(program: &str, err: CatError) -> ! {
+    eprintln!("{}: {}", program, err);
+    print_usage(program);
+    process::exit(err.exit_code())
+}
 
 /// Print usage information
 /// This function is called when the user requests help or provides an invalid option
@@ -108,20 +979,97 @@ fn print_usage(program: &str){
     // or when no files are provided
     eprintln!("HowTo: {} [options] [file]...", program);
     eprintln!("Options:");
+    eprintln!(" -b      Copy the input byte for byte, without any line processing");
+    eprintln!(" -c      Only print the line count of each file (newlines, like wc -l)");
     eprintln!(" -h      Show this help message");
+    eprintln!(" -H      Print a ==> name <== header before each file (--headers=always for one file too)");
     eprintln!(" -n      Show line numbers");
+    eprintln!(" --number-format F  Write -n line numbers as decimal (default) or hex");
     eprintln!(" -s      Remove repeated blank lines from output");
+    eprintln!(" -u      Remove consecutive duplicate lines from output");
+    eprintln!(" -U      Flush the output after every line (--unbuffered), e.g. when reading from a pipe");
     eprintln!(" -v      Show non-printing characters in the output");
+    eprintln!(" -z      Lines end with NUL instead of newline, in the input and the output (--null-data)");
+    eprintln!(" --expand-tabs [N]  Convert tabs to spaces with tab stops every N columns (default 8)");
+    eprintln!(" --lines A:B        Only print lines A through B of each file (A: and :B are open ranges)");
+    eprintln!(" --match PATTERN    Only print lines matching the regular expression PATTERN");
+    eprintln!(" --color            Highlight the text matched by --match");
+    eprintln!(" --max-line-length N  Cut lines longer than N characters, marking them with …");
+    eprintln!(" --head N           Only print the first N lines of each file");
+    eprintln!(" --tail N           Only print the last N lines of each file");
+    eprintln!(" --stats            Print line, word and byte counts to stderr after the output, like wc");
+    eprintln!(" --quiet            Do not print a hint when reading from a terminal");
+    eprintln!(" --skip-empty-files Silently skip files that are empty");
+    eprintln!(" --tee FILE         Also write the output to FILE (--tee-append appends to it)");
+    eprintln!(" --files-from FILE  Also concatenate the files listed in FILE, one per line (- reads stdin)");
+    eprintln!(" --files0-from FILE Like --files-from, but the filenames in FILE are NUL-separated");
+    eprintln!(" --decode           Base64-decode the concatenated input and write the raw bytes");
+    eprintln!(" --url-safe         Decode with the URL-safe alphabet (detected from - or _ otherwise)");
+    eprintln!(" --encode E         Write the base64 or hex encoding of each file");
+    eprintln!(" --wrap N           Wrap the --encode output after N characters (0 disables, default 76 for base64)");
 }
 
 
+/// Concatenate the files in order to `output`, see concatenate_file
+/// A file that cannot be read is reported on stderr and skipped, the following files are still written
+/// Returns the exit code, 0 or the code of the last failure, and the stats of every file that was read
+fn concatenate_files<W: Write>(
+    program: &str,
+    filenames: &[String],
+    config: &Config,
+    output: &mut W,
+) -> (i32, Vec<(String, Stats)>) {
+    let mut exit_code = 0;
+    let mut total = Stats::default();
+    let mut file_stats = Vec::new();
+    let mut file_count = 0;
+    let show_headers = config.headers == Headers::Always
+        || (config.headers == Headers::Multiple && filenames.len() > 1);
+    let mut first_header = true;
+    // -n keeps counting from one file to the next, also when the same file is given twice
+    let mut line_number = 1;
+    for filename in filenames {
+        // Empty files are skipped before they are opened, so they add nothing to the output or the stats
+        if config.skip_empty_files && fs::metadata(filename).is_ok_and(|m| m.is_file() && m.len() == 0) {
+            continue;
+        }
+
+        // Like `tail`, headers after the first one are separated by a blank line
+        let header = show_headers.then(|| {
+            let name = if filename == "-" { "standard input" } else { filename };
+            format!("{}==> {} <==", if first_header { "" } else { "\n" }, name)
+        });
+
+        file_count += 1;
+        match concatenate_file(filename, config, output, header.as_deref(), &mut line_number) {
+            Ok(stats) => {
+                first_header = false;
+                total.add(&stats);
+                file_stats.push((filename.clone(), stats));
+            },
+            Err(err) => {
+                eprintln!("{}: {}", program, err);
+                exit_code = err.exit_code();
+            }
+        }
+    }
+
+    // Like `wc -l`, print a total when counting more than one file
+    if config.count_lines && file_count > 1 {
+        let _ = writeln!(output, "{} total", total.lines);
+    }
+
+    (exit_code, file_stats)
+}
+
 /// Main function
 /// Parses command line arguments, sets configuration options,
 /// and calls the `concatenate_file` function for each file provided
 /// If an invalid option is provided, prints an error message and exits
-
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+/// Errors are reported as `CatError` and mapped to the process exit code
+/// Default options can be set in the CAT_OPTS environment variable, see `with_env_options`
+fn main() {
+    let args = with_env_options(env::args().collect(), env::var("CAT_OPTS").ok());
     let program = args[0].clone();
     let mut config = Config::new();
     let mut filenames = Vec::new();
@@ -132,23 +1080,132 @@ fn main() -> io::Result<()> {
         // Check if the argument is an option
         // If it is, set the corresponding config option
         match args[i].as_str() {
+            // If the argument is -c or --count, set count_lines to true
             // If the argument is -n, set show_line_numbers to true
             // If the argument is -s, set squeeze_blank to true
+            // If the argument is -u, set unique to true
+            // If the argument is -U or --unbuffered, set unbuffered to true
+            // If the argument is -z or --null-data, set null_data to true
             // If the argument is -v, set show_nonprinting to true
             // If the argument is -h, print usage and exit
+            // If the argument is -b or --binary, set binary to true
+            "-b" | "--binary" => config.binary = true,
+            "-c" | "--count" => config.count_lines = true,
             "-n" => config.show_line_numbers = true,
             "-s" => config.squeeze_blank = true,
+            "-u" => config.unique = true,
+            "-U" | "--unbuffered" => config.unbuffered = true,
+            "-z" | "--null-data" => config.null_data = true,
+            "-H" | "--headers" => config.headers = Headers::Multiple,
+            "--headers=always" => config.headers = Headers::Always,
             "-v" => config.show_nonprinting = true,
             "-h" => {
                 print_usage(&program);
                 process::exit(0);
             },
+            // If the argument is --expand-tabs, use the default tab stop width
+            // If the argument is --expand-tabs=N or --expand-tabs N, use N as the tab stop width
+            arg if arg == "--expand-tabs" || arg.starts_with("--expand-tabs=") => {
+                match expand_tabs_width(&args, &mut i) {
+                    Ok(width) => config.expand_tabs = Some(width),
+                    Err(err) => exit_with(&program, err),
+                }
+            },
+            // If the argument is --lines A:B or --lines=A:B, only print that range of lines
+            arg if arg == "--lines" || arg.starts_with("--lines=") => {
+                let range = option_value(&args, &mut i, "--lines");
+                match parse_line_range(&range) {
+                    Some(line_range) => config.line_range = Some(line_range),
+                    None => exit_with(&program, CatError::invalid_value("line range", &range)),
+                }
+            },
+            // If the argument is --match PATTERN or --match=PATTERN, only print matching lines
+            // An invalid pattern is reported before any file is read
+            arg if arg == "--match" || arg.starts_with("--match=") => {
+                let pattern = option_value(&args, &mut i, "--match");
+                match parse_match_pattern(&pattern) {
+                    Ok(regex) => config.match_pattern = Some(regex),
+                    Err(err) => exit_with(&program, err),
+                }
+            },
+            // If the argument is --files-from FILE or --files-from=FILE,
+            // add the files listed in FILE in the order they are listed
+            // --files0-from reads a NUL-separated list instead, as written by `find -print0`
+            arg if arg == "--files-from" || arg.starts_with("--files-from=")
+                || arg == "--files0-from" || arg.starts_with("--files0-from=") => {
+                let (name, separator) = if arg.starts_with("--files0-from") {
+                    ("--files0-from", b'\0')
+                } else {
+                    ("--files-from", b'\n')
+                };
+                let path = option_value(&args, &mut i, name);
+                match read_file_list(&path, separator, io::stdin()) {
+                    Ok(list) => filenames.extend(list),
+                    Err(err) => exit_with(&program, err),
+                }
+            },
+            // If the argument is --max-line-length N or --max-line-length=N, truncate long lines
+            arg if arg == "--max-line-length" || arg.starts_with("--max-line-length=") => {
+                let value = option_value(&args, &mut i, "--max-line-length");
+                match value.parse::<usize>() {
+                    Ok(max) if max > 0 => config.max_line_length = Some(max),
+                    _ => exit_with(&program, CatError::invalid_value("line length", &value)),
+                }
+            },
+            // If the argument is --head N or --tail N (or the --head=N form), limit the lines per file
+            arg if arg == "--head" || arg.starts_with("--head=") || arg == "--tail" || arg.starts_with("--tail=") => {
+                let name = if arg.starts_with("--head") { "--head" } else { "--tail" };
+                let value = option_value(&args, &mut i, name);
+                match value.parse::<usize>() {
+                    Ok(count) if name == "--head" => config.head = Some(count),
+                    Ok(count) => config.tail = Some(count),
+                    Err(_) => exit_with(&program, CatError::invalid_value("number of lines", &value)),
+                }
+            },
+            // If the argument is --number-format F or --number-format=F, choose the base of -n
+            arg if arg == "--number-format" || arg.starts_with("--number-format=") => {
+                let value = option_value(&args, &mut i, "--number-format");
+                match parse_number_format(&value) {
+                    Ok(format) => config.number_format = format,
+                    Err(err) => exit_with(&program, err),
+                }
+            },
+            "--color" => config.color = true,
+            "--quiet" => config.quiet = true,
+            // If the argument is --tee FILE or --tee=FILE, copy the output to FILE
+            arg if arg == "--tee" || arg.starts_with("--tee=") => {
+                config.tee = Some(option_value(&args, &mut i, "--tee"));
+            },
+            "--tee-append" => config.tee_append = true,
+            "--skip-empty-files" => config.skip_empty_files = true,
+            "--stats" => config.show_stats = true,
+            "--decode" => config.decode = true,
+            "--url-safe" => config.url_safe = true,
+            // If the argument is --encode base64|hex or --encode=base64|hex, encode each input
+            arg if arg == "--encode" || arg.starts_with("--encode=") => {
+                let value = option_value(&args, &mut i, "--encode");
+                config.encode = match value.as_str() {
+                    "base64" => Some(Encoding::Base64),
+                    "hex" => Some(Encoding::Hex),
+                    _ => exit_with(
+                        &program,
+                        CatError::InvalidOption(format!("invalid encoding: '{}' (expected base64 or hex)", value)),
+                    ),
+                };
+            },
+            // If the argument is --wrap N or --wrap=N, wrap the --encode output after N characters
+            arg if arg == "--wrap" || arg.starts_with("--wrap=") => {
+                let value = option_value(&args, &mut i, "--wrap");
+                match value.parse::<usize>() {
+                    Ok(wrap) => config.wrap = Some(wrap),
+                    Err(_) => exit_with(&program, CatError::invalid_value("wrap width", &value)),
+                }
+            },
             // If the argument starts with a dash, but is not a valid option
             // print an error message and exit
-            arg if arg.starts_with("-") => {
-                eprintln!("{}: invalid option -- '{}' ", program, arg);
-                print_usage(&program);
-                process::exit(1)
+            // A lone dash is a filename that stands for stdin
+            arg if arg.starts_with("-") && arg != "-" => {
+                exit_with_usage(&program, CatError::unknown_option(arg))
             },
             // Otherwise, treat it as a filename
             // and add it to the list of filenames
@@ -157,29 +1214,931 @@ fn main() -> io::Result<()> {
         i += 1;
     }
 
+    if let Err(err) = check_options(&config) {
+        exit_with_usage(&program, err);
+    }
+
+    // Open the --tee file once, so every input is written to it
+    let mut output = match Output::new(io::stdout(), &config) {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!("{}: {}", program, err);
+            process::exit(err.exit_code());
+        }
+    };
+
+    // The base64 text may be split across the inputs, so they are decoded together
+    if config.decode {
+        if filenames.is_empty() {
+            print_stdin_hint(&program, &config);
+        }
+
+        let result = read_inputs(&filenames)
+            .and_then(|input| decode_base64(&input, config.url_safe))
+            .and_then(|bytes| {
+                output.write_all(&bytes)
+                    .and_then(|_| output.flush())
+                    .map_err(|e| CatError::Io("stdout".to_string(), e))
+            });
+        if let Err(err) = result {
+            eprintln!("{}: {}", program, err);
+            process::exit(err.exit_code());
+        }
+        return;
+    }
+
+    // Each input is encoded on its own, a file that cannot be read is reported and skipped
+    if let Some(encoding) = config.encode {
+        if filenames.is_empty() {
+            print_stdin_hint(&program, &config);
+            filenames.push("-".to_string());
+        }
+
+        let wrap = config.wrap.unwrap_or(encoding.default_wrap());
+        let mut exit_code = 0;
+        for filename in &filenames {
+            let result = read_inputs(std::slice::from_ref(filename)).and_then(|bytes| {
+                output.write_all(encode_bytes(&bytes, encoding, wrap).as_bytes())
+                    .map_err(|e| CatError::Io("stdout".to_string(), e))
+            });
+            if let Err(err) = result {
+                eprintln!("{}: {}", program, err);
+                exit_code = err.exit_code();
+            }
+        }
+        if let Err(e) = output.flush() {
+            eprintln!("{}: {}", program, e);
+            exit_code = 1;
+        }
+        process::exit(exit_code);
+    }
+
     // check filename(s) is provided
     if filenames.is_empty(){
+        print_stdin_hint(&program, &config);
+
         // If no files are provided, read from stdin
         // and write to stdout
-        if let Err(e) = io::copy(&mut io::stdin(), &mut io::stdout()) {
-            // If an error occurs while reading from stdin, print the error and exit
-            eprintln!("{}: stdin: {}", program, e);
-            process::exit(1);
+        match concatenate_stdin(io::stdin().lock(), &config, &mut output).and_then(|stats| output.flush().map(|_| stats)) {
+            Ok(stats) => {
+                if config.show_stats {
+                    print_stats(&[("-".to_string(), stats)]);
+                }
+            },
+            Err(e) => {
+                // If an error occurs while reading from stdin, print the error and exit
+                let err = CatError::Io("stdin".to_string(), e);
+                eprintln!("{}: {}", program, err);
+                process::exit(err.exit_code());
+            }
         }
-        return Ok(());
+        return;
     }
 
-    let mut exit_code = 0;
-    for filename in filenames {
-        if concatenate_file(&filename, &config).is_err() {
-            eprintln!("{}: {}: No such file or directory", program, filename);
-            exit_code = 1;
-        }
+    let (mut exit_code, file_stats) = concatenate_files(&program, &filenames, &config, &mut output);
+
+    // A failed --tee file only shows up once everything is written to stdout
+    if let Err(e) = output.flush() {
+        eprintln!("{}: {}", program, e);
+        exit_code = 1;
+    }
+
+    if config.show_stats {
+        print_stats(&file_stats);
     }
 
     if exit_code != 0{
         process::exit(exit_code);
     }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
 
-    Ok(())
-}
\ No newline at end of file
+    /// Run `input` through concatenate_reader under the name `name` and return the output
+    fn cat_str(config: &Config, name: &str, input: &str) -> String {
+        let mut output = Vec::new();
+        concatenate_reader(io::Cursor::new(input), name, config, &mut output, &mut 1).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Write `contents` to a file in the temp directory, unique per test process and `name`
+    fn temp_file(name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = env::temp_dir().join(format!("cat-test-{}-{}", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn count_prints_the_line_count_of_a_single_file() {
+        let path = temp_file("count", "one\ntwo\nthree\n");
+        let filename = path.to_str().unwrap();
+        let mut config = Config::new();
+        config.count_lines = true;
+
+        let mut output = Vec::new();
+        let stats = concatenate_file(filename, &config, &mut output, None, &mut 1).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), format!("3 {}\n", filename));
+        assert_eq!(stats.lines, 3);
+    }
+
+    #[test]
+    fn count_prints_one_line_per_file() {
+        let mut config = Config::new();
+        config.count_lines = true;
+
+        let mut output = Vec::new();
+        let mut total = Stats::default();
+        for (name, input) in [("a", "1\n2\n"), ("b", "1\n2\n3\n")] {
+            let stats = concatenate_reader(io::Cursor::new(input), name, &config, &mut output, &mut 1).unwrap();
+            total.add(&stats);
+        }
+
+        assert_eq!(String::from_utf8(output).unwrap(), "2 a\n3 b\n");
+        assert_eq!(total.lines, 5);
+    }
+
+    #[test]
+    fn count_names_stdin_with_a_dash() {
+        let mut config = Config::new();
+        config.count_lines = true;
+        assert_eq!(cat_str(&config, "-", "x\n"), "1 -\n");
+        assert_eq!(cat_str(&config, "-", ""), "0 -\n");
+    }
+
+    #[test]
+    fn expand_tabs_fills_to_the_next_tab_stop() {
+        assert_eq!(expand_tabs("\tx", 8), "        x");
+        assert_eq!(expand_tabs("ab\tx", 8), "ab      x");
+        assert_eq!(expand_tabs("abcdefg\tx", 8), "abcdefg x");
+        assert_eq!(expand_tabs("abcdefgh\tx", 8), "abcdefgh        x");
+        assert_eq!(expand_tabs("a\t\tb", 4), "a       b");
+        assert_eq!(expand_tabs("a\tb", 1), "a b");
+        assert_eq!(expand_tabs("no tabs", 8), "no tabs");
+    }
+
+    #[test]
+    fn expand_tabs_restarts_the_column_on_every_line() {
+        let mut config = Config::new();
+        config.expand_tabs = Some(4);
+        assert_eq!(cat_str(&config, "-", "abc\tx\n\ty\n"), "abc x\n    y\n");
+    }
+
+    #[test]
+    fn expand_tabs_width_reads_both_forms() {
+        let args: Vec<String> = ["cat", "--expand-tabs=2", "--expand-tabs", "4", "--expand-tabs", "a.txt", "--expand-tabs"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let mut i = 1;
+        assert_eq!(expand_tabs_width(&args, &mut i).unwrap(), 2);
+        assert_eq!(i, 1);
+
+        i = 2;
+        assert_eq!(expand_tabs_width(&args, &mut i).unwrap(), 4);
+        assert_eq!(i, 3);
+
+        // A following filename is left alone
+        i = 4;
+        assert_eq!(expand_tabs_width(&args, &mut i).unwrap(), 8);
+        assert_eq!(i, 4);
+
+        i = 6;
+        assert_eq!(expand_tabs_width(&args, &mut i).unwrap(), 8);
+        assert_eq!(i, 6);
+    }
+
+    #[test]
+    fn expand_tabs_width_rejects_zero_and_non_numbers() {
+        for arg in ["--expand-tabs=0", "--expand-tabs=x", "--expand-tabs="] {
+            let args = vec!["cat".to_string(), arg.to_string()];
+            let err = expand_tabs_width(&args, &mut 1).unwrap_err();
+            assert!(err.to_string().starts_with("invalid tab size: "));
+        }
+        let args: Vec<String> = ["cat", "--expand-tabs", "0"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(expand_tabs_width(&args, &mut 1).unwrap_err().to_string(), "invalid tab size: '0'");
+    }
+
+    #[test]
+    fn parse_line_range_accepts_bounded_and_open_ranges() {
+        assert_eq!(parse_line_range("2:4"), Some((2, Some(4))));
+        assert_eq!(parse_line_range("3:3"), Some((3, Some(3))));
+        assert_eq!(parse_line_range(":5"), Some((1, Some(5))));
+        assert_eq!(parse_line_range("7:"), Some((7, None)));
+        assert_eq!(parse_line_range(":"), Some((1, None)));
+    }
+
+    #[test]
+    fn parse_line_range_rejects_malformed_and_empty_ranges() {
+        assert_eq!(parse_line_range("5"), None);
+        assert_eq!(parse_line_range("0:3"), None);
+        assert_eq!(parse_line_range("4:2"), None);
+        assert_eq!(parse_line_range("a:b"), None);
+        assert_eq!(parse_line_range("-1:3"), None);
+        assert_eq!(parse_line_range(""), None);
+    }
+
+    #[test]
+    fn line_range_selects_the_inclusive_range() {
+        let input = "1\n2\n3\n4\n5\n";
+        let mut config = Config::new();
+
+        config.line_range = parse_line_range("2:4");
+        assert_eq!(cat_str(&config, "-", input), "2\n3\n4\n");
+        config.line_range = parse_line_range(":2");
+        assert_eq!(cat_str(&config, "-", input), "1\n2\n");
+        config.line_range = parse_line_range("4:");
+        assert_eq!(cat_str(&config, "-", input), "4\n5\n");
+        config.line_range = parse_line_range("9:");
+        assert_eq!(cat_str(&config, "-", input), "");
+    }
+
+    #[test]
+    fn match_prints_only_matching_lines() {
+        let mut config = Config::new();
+        config.match_pattern = Some(parse_match_pattern("^err").unwrap());
+        assert_eq!(cat_str(&config, "-", "error 1\nok\nerr 2\nan error\n"), "error 1\nerr 2\n");
+        assert_eq!(cat_str(&config, "-", "ok\nfine\n"), "");
+    }
+
+    #[test]
+    fn match_with_color_highlights_every_match() {
+        let mut config = Config::new();
+        config.match_pattern = Some(parse_match_pattern("o").unwrap());
+        config.color = true;
+        assert_eq!(cat_str(&config, "-", "foo\nbar\n"), "f\x1b[01;31mo\x1b[0m\x1b[01;31mo\x1b[0m\n");
+    }
+
+    #[test]
+    fn invalid_match_pattern_is_an_invalid_option() {
+        let err = parse_match_pattern("(unclosed").unwrap_err();
+        assert!(matches!(err, CatError::InvalidOption(_)));
+        assert!(err.to_string().starts_with("invalid pattern '(unclosed': "));
+        assert_eq!(err.exit_code(), 2);
+    }
+
+    #[test]
+    fn option_value_reads_both_forms() {
+        let args: Vec<String> = ["cat", "--match=a=b", "--match", "x", "--match"].iter().map(|s| s.to_string()).collect();
+
+        let mut i = 1;
+        assert_eq!(option_value(&args, &mut i, "--match"), "a=b");
+        assert_eq!(i, 1);
+
+        i = 2;
+        assert_eq!(option_value(&args, &mut i, "--match"), "x");
+        assert_eq!(i, 3);
+
+        i = 4;
+        assert_eq!(option_value(&args, &mut i, "--match"), "");
+        assert_eq!(i, 5);
+    }
+
+    #[test]
+    fn decode_base64_ignores_whitespace_and_padding() {
+        assert_eq!(decode_base64(b"aGVsbG8gd29ybGQ=", false).unwrap(), b"hello world");
+        assert_eq!(decode_base64(b"aGVsbG8g\nd29ybGQ=\n", false).unwrap(), b"hello world");
+        assert_eq!(decode_base64(b"aGVsbG8gd29ybGQ", false).unwrap(), b"hello world");
+        assert_eq!(decode_base64(b"", false).unwrap(), b"");
+    }
+
+    #[test]
+    fn decode_base64_picks_the_url_safe_alphabet() {
+        let bytes = [0xfb, 0xff, 0xbf];
+        assert_eq!(decode_base64(b"-_-_", false).unwrap(), bytes);
+        assert_eq!(decode_base64(b"-_-_", true).unwrap(), bytes);
+        assert_eq!(decode_base64(b"+/+/", false).unwrap(), bytes);
+        assert!(decode_base64(b"+/+/", true).is_err());
+    }
+
+    #[test]
+    fn decode_base64_round_trips_the_encoding() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let encoded = encode_bytes(&bytes, Encoding::Base64, 76);
+        assert_eq!(decode_base64(encoded.as_bytes(), false).unwrap(), bytes);
+    }
+
+    #[test]
+    fn invalid_base64_is_reported() {
+        let err = decode_base64(b"a", false).unwrap_err();
+        assert!(matches!(err, CatError::InvalidBase64(_)));
+        assert_eq!(err.exit_code(), 1);
+    }
+
+    #[test]
+    fn encode_bytes_writes_base64_and_lowercase_hex() {
+        assert_eq!(encode_bytes(b"hello", Encoding::Base64, 0), "aGVsbG8=\n");
+        assert_eq!(encode_bytes(&[0x00, 0xab, 0xff], Encoding::Hex, 0), "00abff\n");
+        assert_eq!(encode_bytes(b"", Encoding::Base64, 76), "");
+        assert_eq!(encode_bytes(b"", Encoding::Hex, 0), "");
+    }
+
+    #[test]
+    fn encode_bytes_wraps_lines_at_the_given_width() {
+        assert_eq!(encode_bytes(b"hello world", Encoding::Base64, 8), "aGVsbG8g\nd29ybGQ=\n");
+        assert_eq!(encode_bytes(b"abcd", Encoding::Hex, 4), "6162\n6364\n");
+        assert_eq!(encode_bytes(b"abc", Encoding::Hex, 4), "6162\n63\n");
+    }
+
+    #[test]
+    fn max_line_length_cuts_only_longer_lines() {
+        let mut config = Config::new();
+        config.max_line_length = Some(3);
+        assert_eq!(cat_str(&config, "-", "ab\nabc\nabcd\n"), "ab\nabc\nabc…\n");
+        // Characters are counted, not bytes
+        assert_eq!(cat_str(&config, "-", "äöü\näöüß\n"), "äöü\näöü…\n");
+
+        // The number prefix does not count towards the length
+        config.show_line_numbers = true;
+        assert_eq!(cat_str(&config, "-", "abcd\n"), "     1  abc…\n");
+    }
+
+    #[test]
+    fn unique_drops_only_consecutive_duplicates() {
+        let mut config = Config::new();
+        config.unique = true;
+        assert_eq!(cat_str(&config, "-", "a\na\na\nb\na\n\n\n"), "a\nb\na\n\n");
+    }
+
+    /// A writer that records the data written and how much of it had been flushed at each flush
+    #[derive(Default)]
+    struct FlushRecorder {
+        data: Vec<u8>,
+        flushed: Vec<usize>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed.push(self.data.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn unbuffered_copy_flushes_after_every_read() {
+        // Each read of a chained reader returns at most the rest of one part
+        let mut input = io::Cursor::new("one").chain(io::Cursor::new("two"));
+        let mut output = FlushRecorder::default();
+        copy_bytes(&mut input, &mut output, true).unwrap();
+        assert_eq!(output.data, b"onetwo");
+        assert_eq!(output.flushed, [3, 6]);
+
+        let mut input = io::Cursor::new("one").chain(io::Cursor::new("two"));
+        let mut output = FlushRecorder::default();
+        copy_bytes(&mut input, &mut output, false).unwrap();
+        assert_eq!(output.data, b"onetwo");
+        assert!(output.flushed.is_empty());
+    }
+
+    #[test]
+    fn unbuffered_flushes_after_every_line() {
+        let mut config = Config::new();
+        config.unbuffered = true;
+        config.show_line_numbers = true;
+
+        let mut output = FlushRecorder::default();
+        concatenate_reader(io::Cursor::new("a\nb\n"), "-", &config, &mut output, &mut 1).unwrap();
+
+        // The last flush comes from the end of the input
+        assert_eq!(String::from_utf8(output.data).unwrap(), "     1  a\n     2  b\n");
+        assert_eq!(output.flushed, [10, 20, 20]);
+
+        config.unbuffered = false;
+        let mut output = FlushRecorder::default();
+        concatenate_reader(io::Cursor::new("a\nb\n"), "-", &config, &mut output, &mut 1).unwrap();
+        assert_eq!(output.flushed, [20]);
+    }
+
+    #[test]
+    fn number_format_pads_decimal_and_hex_to_six_columns() {
+        let input = "a\nb\n";
+        let mut config = Config::new();
+        config.show_line_numbers = true;
+
+        let mut decimal = Vec::new();
+        concatenate_reader(io::Cursor::new(input), "-", &config, &mut decimal, &mut 15).unwrap();
+        config.number_format = NumberFormat::Hex;
+        let mut hex = Vec::new();
+        concatenate_reader(io::Cursor::new(input), "-", &config, &mut hex, &mut 15).unwrap();
+
+        assert_eq!(String::from_utf8(decimal).unwrap(), "    15  a\n    16  b\n");
+        assert_eq!(String::from_utf8(hex).unwrap(), "     f  a\n    10  b\n");
+    }
+
+    #[test]
+    fn parse_number_format_accepts_decimal_and_hex_only() {
+        assert_eq!(parse_number_format("decimal").unwrap(), NumberFormat::Decimal);
+        assert_eq!(parse_number_format("hex").unwrap(), NumberFormat::Hex);
+        for value in ["HEX", "octal", ""] {
+            let err = parse_number_format(value).unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid number format: '{}' (expected decimal or hex)", value));
+            assert_eq!(err.exit_code(), 2);
+        }
+    }
+
+    #[test]
+    fn stats_count_lines_words_and_bytes_like_wc() {
+        let mut config = Config::new();
+        config.show_stats = true;
+        let input = "the quick brown fox\n  jumps over\tthe\n\nlazy dog\n";
+
+        let mut output = Vec::new();
+        let stats = concatenate_reader(io::Cursor::new(input), "-", &config, &mut output, &mut 1).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+        assert_eq!((stats.lines, stats.words, stats.bytes), (4, 9, input.len()));
+    }
+
+    #[test]
+    fn stats_do_not_count_a_last_line_without_newline() {
+        let mut config = Config::new();
+        config.show_stats = true;
+        let input = "one two\nthree";
+
+        let mut output = Vec::new();
+        let stats = concatenate_reader(io::Cursor::new(input), "-", &config, &mut output, &mut 1).unwrap();
+
+        assert_eq!((stats.lines, stats.words, stats.bytes), (1, 3, 13));
+        assert_eq!(String::from_utf8(output).unwrap(), "one two\nthree\n");
+
+        config.show_stats = false;
+        config.count_lines = true;
+        assert_eq!(cat_str(&config, "-", input), "1 -\n");
+    }
+
+    #[test]
+    fn stats_add_up_across_files() {
+        let mut total = Stats::default();
+        total.add(&Stats { lines: 2, words: 5, bytes: 20 });
+        total.add(&Stats { lines: 1, words: 0, bytes: 1 });
+        assert_eq!((total.lines, total.words, total.bytes), (3, 5, 21));
+    }
+
+    #[test]
+    fn squeeze_runs_before_escape_and_number() {
+        let mut config = Config::new();
+        config.squeeze_blank = true;
+        config.show_nonprinting = true;
+        config.show_line_numbers = true;
+        // Squeezed lines are not numbered and the number prefix is never escaped
+        assert_eq!(
+            cat_str(&config, "-", "a\tb\n\n\n\n\x01c\n"),
+            "     1  a^Ib\n     2  \n     3  ^Ac\n"
+        );
+    }
+
+    #[test]
+    fn match_and_color_run_before_number() {
+        let mut config = Config::new();
+        config.match_pattern = Some(parse_match_pattern("[0-9]").unwrap());
+        config.color = true;
+        config.show_line_numbers = true;
+        // The kept lines show their original numbers, and the digits of the prefix are not highlighted
+        assert_eq!(
+            cat_str(&config, "-", "a\nb2\nc\n3d\n"),
+            "     2  b\x1b[01;31m2\x1b[0m\n     4  \x1b[01;31m3\x1b[0md\n"
+        );
+    }
+
+    #[test]
+    fn binary_copies_nul_and_invalid_utf8_byte_for_byte() {
+        let bytes = b"a\0b\xff\xfe\r\nno newline\x80";
+        let path = temp_file("binary", bytes);
+        let mut config = Config::new();
+        config.binary = true;
+
+        let mut output = Vec::new();
+        concatenate_file(path.to_str().unwrap(), &config, &mut output, None, &mut 1).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(output, bytes);
+    }
+
+    #[test]
+    fn stdin_applies_the_line_options() {
+        let input = "a\nb\nc\n";
+        let mut config = Config::new();
+
+        let mut output = Vec::new();
+        concatenate_stdin(io::Cursor::new(input), &config, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+
+        config.match_pattern = Some(parse_match_pattern("b").unwrap());
+        let mut output = Vec::new();
+        concatenate_stdin(io::Cursor::new(input), &config, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "b\n");
+
+        config.match_pattern = None;
+        config.show_line_numbers = true;
+        let mut output = Vec::new();
+        concatenate_stdin(io::Cursor::new(input), &config, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "     1  a\n     2  b\n     3  c\n");
+    }
+
+    #[test]
+    fn head_on_a_short_file_prints_every_line() {
+        let mut config = Config::new();
+        config.head = Some(5);
+        assert_eq!(cat_str(&config, "-", "a\nb\n"), "a\nb\n");
+        config.head = Some(1);
+        assert_eq!(cat_str(&config, "-", "a\nb\n"), "a\n");
+
+        let mut output = Vec::new();
+        concatenate_stdin(io::Cursor::new("a\nb\n"), &config, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "a\n");
+    }
+
+    #[test]
+    fn tail_numbers_only_the_written_lines() {
+        let mut config = Config::new();
+        config.tail = Some(2);
+        config.show_line_numbers = true;
+        assert_eq!(cat_str(&config, "-", "a\nb\nc\nd\n"), "     1  c\n     2  d\n");
+
+        // With --match the original line numbers are shown
+        config.match_pattern = Some(parse_match_pattern("[a-c]").unwrap());
+        assert_eq!(cat_str(&config, "-", "a\nb\nc\nd\n"), "     2  b\n     3  c\n");
+    }
+
+    #[test]
+    fn head_with_stats_keeps_the_numbers_continuous_across_files() {
+        let mut config = Config::new();
+        config.head = Some(1);
+        config.show_line_numbers = true;
+        config.show_stats = true;
+
+        let mut output = Vec::new();
+        let mut line_number = 1;
+        let mut total = Stats::default();
+        for input in ["a\nb\nc\n", "d\ne\n"] {
+            let stats = concatenate_reader(io::Cursor::new(input), "-", &config, &mut output, &mut line_number).unwrap();
+            total.add(&stats);
+        }
+
+        // Every line is still counted, only the written ones are numbered
+        assert_eq!(String::from_utf8(output).unwrap(), "     1  a\n     2  d\n");
+        assert_eq!(line_number, 3);
+        assert_eq!(total.lines, 5);
+    }
+
+    /// Run concatenate_files over `filenames` and return the exit code and the output
+    fn cat_files(config: &Config, filenames: &[String]) -> (i32, String) {
+        let mut output = Vec::new();
+        let (exit_code, _) = concatenate_files("cat", filenames, config, &mut output);
+        (exit_code, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn files_from_outputs_the_listed_files_in_order() {
+        let paths: Vec<PathBuf> = ["one", "two", "three"]
+            .iter()
+            .map(|name| temp_file(&format!("listed-{}", name), format!("{}\n", name)))
+            .collect();
+        let list = format!(
+            "{}\n\n{}\n{}\n",
+            paths[2].display(),
+            paths[0].display(),
+            paths[1].display()
+        );
+        let list_path = temp_file("files-from", &list);
+
+        let filenames = read_file_list(list_path.to_str().unwrap(), b'\n', io::empty()).unwrap();
+        let (exit_code, output) = cat_files(&Config::new(), &filenames);
+        for path in paths.iter().chain([&list_path]) {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(filenames.len(), 3);
+        assert_eq!(exit_code, 0);
+        assert_eq!(output, "three\none\ntwo\n");
+    }
+
+    #[test]
+    fn files0_from_reads_nul_separated_names_with_spaces() {
+        let spaced = temp_file("listed with space", "spaced\n");
+        let plain = temp_file("listed-plain", "plain\n");
+        let list = format!("{}\0\0{}\0", spaced.display(), plain.display());
+        let list_path = temp_file("files0-from", &list);
+
+        let filenames = read_file_list(list_path.to_str().unwrap(), b'\0', io::empty()).unwrap();
+        let (exit_code, output) = cat_files(&Config::new(), &filenames);
+        for path in [&spaced, &plain, &list_path] {
+            fs::remove_file(path).unwrap();
+        }
+
+        // The empty entry between the two NULs is skipped
+        assert_eq!(filenames, [spaced.to_str().unwrap(), plain.to_str().unwrap()]);
+        assert_eq!(exit_code, 0);
+        assert_eq!(output, "spaced\nplain\n");
+    }
+
+    #[test]
+    fn file_list_dash_reads_stdin() {
+        let stdin = io::Cursor::new("a b\0\0c\nd\0");
+        assert_eq!(read_file_list("-", b'\0', stdin).unwrap(), ["a b", "c\nd"]);
+        assert_eq!(read_file_list("-", b'\n', io::Cursor::new("x\n\ny")).unwrap(), ["x", "y"]);
+    }
+
+    #[test]
+    fn missing_file_list_is_reported() {
+        let err = read_file_list("no-such-list", b'\0', io::empty()).unwrap_err();
+        assert!(matches!(err, CatError::MissingFile(name) if name == "no-such-list"));
+    }
+
+    #[test]
+    fn headers_name_each_file_in_order() {
+        let first = temp_file("header-first", "one\n");
+        let second = temp_file("header-second", "two\n");
+        let filenames = [first.to_str().unwrap().to_string(), second.to_str().unwrap().to_string()];
+        let mut config = Config::new();
+        config.headers = Headers::Multiple;
+
+        let (exit_code, output) = cat_files(&config, &filenames);
+        let (_, single) = cat_files(&config, &filenames[..1]);
+        config.headers = Headers::Always;
+        let (_, always) = cat_files(&config, &filenames[..1]);
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+
+        assert_eq!(exit_code, 0);
+        assert_eq!(
+            output,
+            format!("==> {} <==\none\n\n==> {} <==\ntwo\n", filenames[0], filenames[1])
+        );
+        assert_eq!(single, "one\n");
+        assert_eq!(always, format!("==> {} <==\none\n", filenames[0]));
+    }
+
+    #[test]
+    fn conflicting_options_are_rejected() {
+        let mut config = Config::new();
+        assert!(check_options(&config).is_ok());
+
+        config.binary = true;
+        config.headers = Headers::Multiple;
+        let err = check_options(&config).unwrap_err();
+        assert_eq!(err.to_string(), "--binary cannot be combined with options that process lines or print headers");
+        assert_eq!(err.exit_code(), 2);
+
+        config.headers = Headers::Off;
+        assert!(check_options(&config).is_ok());
+        config.show_line_numbers = true;
+        assert!(check_options(&config).is_err());
+
+        let mut config = Config::new();
+        config.head = Some(1);
+        config.tail = Some(1);
+        assert_eq!(check_options(&config).unwrap_err().to_string(), "--head cannot be combined with --tail");
+    }
+
+    #[test]
+    fn null_data_splits_and_terminates_records_on_nul() {
+        let mut config = Config::new();
+        config.null_data = true;
+        config.show_line_numbers = true;
+        assert_eq!(cat_str(&config, "-", "a\0b\nc\0"), "     1  a\0     2  b\nc\0");
+
+        config.show_line_numbers = false;
+        config.squeeze_blank = true;
+        assert_eq!(cat_str(&config, "-", "a\0\0\0b"), "a\0\0b\0");
+    }
+
+    #[test]
+    fn null_data_keeps_newlines_when_escaping() {
+        let mut config = Config::new();
+        config.null_data = true;
+        config.show_nonprinting = true;
+        assert_eq!(cat_str(&config, "-", "a\nb\n"), "a\nb\n\0");
+        assert_eq!(cat_str(&config, "-", "a\tb\0"), "a^Ib\0");
+    }
+
+    /// A reader that fails with `errors` before returning `data`
+    /// With `endless` the last error is repeated forever instead
+    struct FlakyReader {
+        errors: Vec<io::ErrorKind>,
+        data: io::Cursor<&'static str>,
+        endless: bool,
+    }
+
+    impl Read for FlakyReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.errors.first().copied() {
+                Some(kind) if self.endless => Err(io::Error::from(kind)),
+                Some(_) => Err(io::Error::from(self.errors.remove(0))),
+                None => self.data.read(buf),
+            }
+        }
+    }
+
+    #[test]
+    fn interrupted_and_would_block_reads_are_retried() {
+        let reader = FlakyReader {
+            errors: vec![io::ErrorKind::Interrupted, io::ErrorKind::WouldBlock],
+            data: io::Cursor::new("one\ntwo\n"),
+            endless: false,
+        };
+
+        let mut output = Vec::new();
+        let stats = concatenate_reader(BufReader::new(reader), "-", &Config::new(), &mut output, &mut 1).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "one\ntwo\n");
+        assert_eq!(stats.lines, 2);
+    }
+
+    #[test]
+    fn a_reader_that_never_becomes_ready_fails() {
+        let reader = FlakyReader {
+            errors: vec![io::ErrorKind::WouldBlock],
+            data: io::Cursor::new(""),
+            endless: true,
+        };
+
+        let mut output = Vec::new();
+        let err = concatenate_reader(BufReader::new(reader), "-", &Config::new(), &mut output, &mut 1).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn env_options_come_before_the_command_line() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            with_env_options(args(&["cat", "a.txt"]), Some("-n".to_string())),
+            args(&["cat", "-n", "a.txt"])
+        );
+        // The command line value of --expand-tabs is parsed last, so it wins
+        assert_eq!(
+            with_env_options(args(&["cat", "--expand-tabs=2", "a.txt"]), Some(" -s\t--expand-tabs=4 ".to_string())),
+            args(&["cat", "-s", "--expand-tabs=4", "--expand-tabs=2", "a.txt"])
+        );
+        assert_eq!(with_env_options(args(&["cat", "a.txt"]), None), args(&["cat", "a.txt"]));
+        assert_eq!(with_env_options(args(&["cat"]), Some(String::new())), args(&["cat"]));
+    }
+
+    #[test]
+    fn stdin_hint_is_only_shown_on_a_terminal_without_quiet() {
+        assert_eq!(stdin_hint(true, false), Some("reading from stdin; press Ctrl-D to end"));
+        assert_eq!(stdin_hint(true, true), None);
+        assert_eq!(stdin_hint(false, false), None);
+        assert_eq!(stdin_hint(false, true), None);
+    }
+
+    #[test]
+    fn tee_file_receives_the_same_bytes_as_stdout() {
+        let path = temp_file("tee", "old contents\n");
+        let input = temp_file("tee-input", "one\ntwo\n");
+        let mut config = Config::new();
+        config.tee = Some(path.to_str().unwrap().to_string());
+        config.show_line_numbers = true;
+
+        let mut output = Output::new(Vec::new(), &config).unwrap();
+        concatenate_files("cat", &[input.to_str().unwrap().to_string()], &config, &mut output);
+        output.flush().unwrap();
+        let tee = fs::read(&path).unwrap();
+
+        // --tee-append keeps what is already in the file
+        config.tee_append = true;
+        let mut appended = Output::new(Vec::new(), &config).unwrap();
+        appended.write_all(b"three\n").unwrap();
+        appended.flush().unwrap();
+        let tee_appended = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&input).unwrap();
+
+        assert_eq!(output.stdout, b"     1  one\n     2  two\n");
+        assert_eq!(tee, output.stdout);
+        assert_eq!(tee_appended, b"     1  one\n     2  two\nthree\n");
+    }
+
+    #[test]
+    fn tee_file_that_cannot_be_opened_is_reported() {
+        let mut config = Config::new();
+        let path = env::temp_dir().join(format!("cat-test-{}-no-such-dir", process::id())).join("tee");
+        config.tee = Some(path.to_str().unwrap().to_string());
+
+        let err = Output::new(Vec::new(), &config).err().unwrap();
+        assert!(matches!(&err, CatError::MissingFile(name) if name == path.to_str().unwrap()));
+        assert_eq!(err.exit_code(), 1);
+    }
+
+    #[test]
+    fn skip_empty_files_leaves_out_their_headers_and_numbers() {
+        let empty = temp_file("skip-empty", "");
+        let first = temp_file("skip-first", "one\n");
+        let second = temp_file("skip-second", "two\n");
+        let filenames: Vec<String> = [&empty, &first, &empty, &second]
+            .iter()
+            .map(|path| path.to_str().unwrap().to_string())
+            .collect();
+        let mut config = Config::new();
+        config.skip_empty_files = true;
+        config.headers = Headers::Multiple;
+        config.show_line_numbers = true;
+
+        let (exit_code, output) = cat_files(&config, &filenames);
+        config.skip_empty_files = false;
+        let (_, unskipped) = cat_files(&config, &filenames);
+        for path in [&empty, &first, &second] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(exit_code, 0);
+        assert_eq!(
+            output,
+            format!("==> {} <==\n     1  one\n\n==> {} <==\n     2  two\n", filenames[1], filenames[3])
+        );
+        assert!(unskipped.starts_with(&format!("==> {} <==\n\n==> {} <==\n", filenames[0], filenames[1])));
+    }
+
+    #[test]
+    fn read_errors_skip_the_file_and_continue_with_the_next() {
+        let first = temp_file("error-first", "one\n");
+        let broken = temp_file("error-broken", b"two\n\xff\nlost\n");
+        let last = temp_file("error-last", "three\n");
+        let filenames: Vec<String> = [
+            first.to_str().unwrap(),
+            "no-such-file",
+            env::temp_dir().to_str().unwrap(),
+            broken.to_str().unwrap(),
+            last.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+
+        let (exit_code, output) = cat_files(&Config::new(), &filenames);
+        for path in [&first, &broken, &last] {
+            fs::remove_file(path).unwrap();
+        }
+
+        // The lines of the broken file before the invalid UTF-8 are still written
+        assert_eq!(exit_code, 1);
+        assert_eq!(output, "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn cat_error_display_follows_coreutils_wording() {
+        assert_eq!(CatError::MissingFile("a.txt".into()).to_string(), "a.txt: No such file or directory");
+        assert_eq!(CatError::IsDirectory("src".into()).to_string(), "src: Is a directory");
+        assert_eq!(CatError::PermissionDenied("secret".into()).to_string(), "secret: Permission denied");
+        assert_eq!(CatError::unknown_option("-Q").to_string(), "invalid option -- '-Q'");
+        assert_eq!(CatError::invalid_value("line range", "5:2").to_string(), "invalid line range: '5:2'");
+        let err = base64::engine::general_purpose::STANDARD.decode("a").unwrap_err();
+        assert_eq!(CatError::InvalidBase64(err).to_string(), "invalid base64 input: Invalid input length: 1");
+        let err = io::Error::new(io::ErrorKind::BrokenPipe, "Broken pipe");
+        assert_eq!(CatError::Io("stdout".into(), err).to_string(), "stdout: Broken pipe");
+    }
+
+    #[test]
+    fn invalid_options_exit_with_2_and_other_errors_with_1() {
+        assert_eq!(CatError::unknown_option("-Q").exit_code(), 2);
+        assert_eq!(CatError::invalid_value("wrap width", "x").exit_code(), 2);
+        assert_eq!(CatError::InvalidOption("--head cannot be combined with --tail".into()).exit_code(), 2);
+        assert_eq!(CatError::MissingFile("a".into()).exit_code(), 1);
+        assert_eq!(CatError::IsDirectory("a".into()).exit_code(), 1);
+        assert_eq!(CatError::PermissionDenied("a".into()).exit_code(), 1);
+        let err = base64::engine::general_purpose::STANDARD.decode("a").unwrap_err();
+        assert_eq!(CatError::InvalidBase64(err).exit_code(), 1);
+        assert_eq!(CatError::Io("stdout".into(), io::Error::other("x")).exit_code(), 1);
+    }
+
+    #[test]
+    fn io_errors_map_to_their_variant() {
+        let from = |kind| CatError::from_io("f", io::Error::from(kind));
+        assert!(matches!(from(io::ErrorKind::NotFound), CatError::MissingFile(name) if name == "f"));
+        assert!(matches!(from(io::ErrorKind::IsADirectory), CatError::IsDirectory(_)));
+        assert!(matches!(from(io::ErrorKind::PermissionDenied), CatError::PermissionDenied(_)));
+        assert!(matches!(from(io::ErrorKind::UnexpectedEof), CatError::Io(..)));
+    }
+
+    #[test]
+    fn same_file_twice_is_output_twice_with_continuous_numbers() {
+        let path = temp_file("twice", "one\ntwo\n");
+        let filename = path.to_str().unwrap();
+        let mut config = Config::new();
+        config.show_line_numbers = true;
+
+        let mut output = Vec::new();
+        let mut line_number = 1;
+        for _ in 0..2 {
+            concatenate_file(filename, &config, &mut output, None, &mut line_number).unwrap();
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "     1  one\n     2  two\n     3  one\n     4  two\n"
+        );
+        assert_eq!(line_number, 5);
+    }
+}
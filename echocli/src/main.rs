@@ -1,40 +1,146 @@
 use std::env; 
+use std::io::{self, Write};
+use std::process;
 
 
 // struct EchoOptions holds the options for the echo command
-// It contains flags for -e (escaped characters)
+// It contains flags for -e/-E (escaped characters), -n (no trailing newline), --posix,
+// -h/--help and --version and the --prefix/--suffix strings that wrap the output
+// --upper and --lower case-fold the output and cannot be combined
+// --json prints the output as a quoted JSON string
+// --ascii-only replaces non-ASCII characters with a placeholder, ? unless --ascii-only=STR is given
 // It is used to parse command line arguments and determine how to format the output
 #[derive(Debug, Default)]
 struct EchoOptions {
     escaped_args: bool,
+    no_newline: bool,
+    posix: bool,
+    show_whitespace: bool,
+    upper: bool,
+    lower: bool,
+    json: bool,
+    show_help: bool,
+    show_version: bool,
+    prefix: String,
+    suffix: String,
+    // Set by --ascii-only, None leaves non-ASCII characters as they are
+    ascii_placeholder: Option<String>,
 }
 
 // This function parses command line arguments to extract options for the echo command
 // It processes flags like -e, and returns an EchoOptions struct
 // It modifies the args vector in place, removing the flags as they are processed
-// It stops processing flags when it encounters a non-flag argument or an unrecognized flag
+// It stops processing flags when it encounters a non-flag argument or an unrecognized flag,
+// and never resumes, so flags after the first text argument are printed as text:
+// `echocli foo -e bar` prints "foo -e bar" and `echocli -e foo -n` prints "foo -n" with escapes
+// A `--` argument is consumed and ends flag processing, so everything after it is literal text
+// With --posix, or the ECHO_POSIX environment variable set to 1, escapes are interpreted
+// by default (the xpg_echo behavior) and -E turns them off again.
+// Without it, escapes are only interpreted with -e.
 // Example usage:
 // echocli.exe -e "Hello, World!\nThis is a test."
+// echocli.exe -e -- -n
+// echocli.exe --prefix '[INFO] ' hi
 
 fn parse_options(args: &mut Vec<String>) -> EchoOptions {
     // Initialize default options 
-    let mut options = EchoOptions::default();
+    // ECHO_POSIX=1 turns on the posix behavior without passing --posix
+    let mut options = EchoOptions {
+        posix: posix_from_env(env::var("ECHO_POSIX").ok().as_deref()),
+        ..Default::default()
+    };
+    // Set by -e/-E, None means the default for the current mode is used
+    let mut escapes: Option<bool> = None;
     
     while !args.is_empty() && args[0].starts_with('-') && args[0] != "-" {
         match args[0].as_str() {
 
             // more flags can be added here
+            "--" => {
+                args.remove(0); // Remove the end-of-options marker
+                break;
+            },
             "-e" => {
-                options.escaped_args = true;
+                escapes = Some(true);
                 args.remove(0); // Remove the -e argument
             },
+            "-E" => {
+                escapes = Some(false);
+                args.remove(0); // Remove the -E argument
+            },
+            "-n" => {
+                options.no_newline = true;
+                args.remove(0); // Remove the -n argument
+            },
+            "--posix" => {
+                options.posix = true;
+                args.remove(0);
+            },
+            "--show-whitespace" => {
+                options.show_whitespace = true;
+                args.remove(0);
+            },
+            "--upper" => {
+                options.upper = true;
+                args.remove(0);
+            },
+            "--lower" => {
+                options.lower = true;
+                args.remove(0);
+            },
+            "--json" => {
+                options.json = true;
+                args.remove(0);
+            },
+            // help and version short-circuit the output in main
+            "-h" | "--help" => {
+                options.show_help = true;
+                args.remove(0);
+            },
+            "--version" => {
+                options.show_version = true;
+                args.remove(0);
+            },
+            // --prefix and --suffix take the next argument as their value,
+            // the --prefix=STR form is accepted as well
+            "--prefix" | "--suffix" => {
+                let flag = args.remove(0);
+                let value = if args.is_empty() { String::new() } else { args.remove(0) };
+                if flag == "--prefix" {
+                    options.prefix = value;
+                } else {
+                    options.suffix = value;
+                }
+            },
+            arg if arg.starts_with("--prefix=") => {
+                options.prefix = args.remove(0)["--prefix=".len()..].to_string();
+            },
+            arg if arg.starts_with("--suffix=") => {
+                options.suffix = args.remove(0)["--suffix=".len()..].to_string();
+            },
+            // --ascii-only uses ? as the placeholder, --ascii-only=STR uses STR
+            "--ascii-only" => {
+                options.ascii_placeholder = Some("?".to_string());
+                args.remove(0);
+            },
+            arg if arg.starts_with("--ascii-only=") => {
+                options.ascii_placeholder = Some(args.remove(0)["--ascii-only=".len()..].to_string());
+            },
             _ => break, // If it's not a recognized flag, stop processing flags
         }
     }
 
+    // In posix mode escapes are interpreted unless -E was given
+    options.escaped_args = escapes.unwrap_or(options.posix);
     options
 }
 
+// This function reads the value of the ECHO_POSIX environment variable
+// 1 and true turn on the posix behavior, anything else or no value leaves it off
+fn posix_from_env(value: Option<&str>) -> bool {
+    matches!(value, Some("1" | "true"))
+}
+
 // This function handles escape sequences in a string
 // It processes common escape sequences like \n, \t, \r, and 
 // also handles quotes and slashes.
@@ -83,6 +189,134 @@ fn handle_escapes(s: &str) -> String {
     result
 }
 
+// This function makes whitespace in the output visible for debugging
+// Spaces are rendered as `·`, tabs as `→`, and every line ends with `$`
+// It is only applied when --show-whitespace is given
+// Example usage: echocli.exe -e --show-whitespace "a b\tc"
+// prints "a·b→c$"
+fn show_whitespace(s: &str) -> String {
+    let mut result = String::new();
+
+    for c in s.chars() {
+        match c {
+            ' ' => result.push('·'),
+            '\t' => result.push('→'),
+            '\n' => result.push_str("$\n"),
+            _ => result.push(c),
+        }
+    }
+    result.push('$');
+
+    result
+}
+
+// This function replaces every non-ASCII character with the placeholder
+// It works on chars, so a multi-byte character like é or 😀 becomes a single placeholder
+// It is only applied when --ascii-only is given
+// Example usage: echocli.exe --ascii-only "café 😀"
+// prints "caf? ?"
+fn ascii_only(s: &str, placeholder: &str) -> String {
+    let mut result = String::new();
+
+    for c in s.chars() {
+        if c.is_ascii() {
+            result.push(c);
+        } else {
+            result.push_str(placeholder);
+        }
+    }
+
+    result
+}
+
+// The usage summary printed by -h/--help
+// It lists every flag recognized by parse_options
+const USAGE: &str = r#"Usage: echocli [options] [string]...
+Print the given strings separated by spaces, followed by a newline.
+
+Options:
+  -e          Interpret backslash escapes (\n, \t, \r, \v, \\, \', \")
+  -E          Do not interpret backslash escapes
+  -n          Do not print the trailing newline
+  --posix     Interpret backslash escapes by default (also set by ECHO_POSIX=1)
+  --show-whitespace  Show spaces as ·, tabs as → and line ends as $
+  --upper            Convert the output to uppercase
+  --lower            Convert the output to lowercase
+  --json             Print the output as an escaped JSON string (ignores -e)
+  --prefix STR       Print STR before the output
+  --suffix STR       Print STR after the output, before the newline (if any)
+  --ascii-only[=STR] Replace non-ASCII characters with STR (default ?)
+  -h, --help  Show this help message and exit
+  --version   Show the version and exit
+  --          Treat all following arguments as text
+"#;
+
+// This function builds the text printed for the remaining arguments
+// It applies, in order, escapes, case-folding, --ascii-only, --json and --show-whitespace,
+// then wraps the result in the prefix and suffix
+// The trailing newline is not included, print_output adds it
+fn format_output(options: &EchoOptions, args: &[String]) -> String {
+    let output = if !args.is_empty() {
+        let joined = args.join(" ");
+        // JSON output escapes the text itself, so shell escapes are left as typed
+        if options.escaped_args && !options.json {
+            handle_escapes(&joined)
+        } else {
+            joined
+        }
+    } else {
+        String::new() // If no arguments, return an empty string
+    };
+
+    // Case-fold after escapes are handled, using the full Unicode mappings
+    // e.g. ß becomes SS with --upper and É becomes é with --lower
+    let output = if options.upper {
+        output.to_uppercase()
+    } else if options.lower {
+        output.to_lowercase()
+    } else {
+        output
+    };
+
+    // Replace non-ASCII characters after escapes and case-folding, so the result is plain ASCII
+    // The prefix and suffix are sanitized as well, they end up in the same output
+    let (output, prefix, suffix) = match &options.ascii_placeholder {
+        Some(placeholder) => (
+            ascii_only(&output, placeholder),
+            ascii_only(&options.prefix, placeholder),
+            ascii_only(&options.suffix, placeholder),
+        ),
+        None => (output, options.prefix.clone(), options.suffix.clone()),
+    };
+
+    // Quote the text and escape quotes, backslashes and control characters for JSON
+    let output = if options.json {
+        serde_json::to_string(&output).expect("a string always serializes to JSON")
+    } else {
+        output
+    };
+
+    // Visualize whitespace after escapes are handled, so \t shows up as a tab
+    let output = if options.show_whitespace {
+        show_whitespace(&output)
+    } else {
+        output
+    };
+
+    // The prefix and suffix wrap the final output
+    format!("{}{}{}", prefix, output, suffix)
+}
+
+// This function writes the output line for the remaining arguments to `out`
+// The newline comes after the suffix and is left out with -n
+fn print_output(out: &mut impl Write, options: &EchoOptions, args: &[String]) -> io::Result<()> {
+    out.write_all(format_output(options, args).as_bytes())?;
+    if !options.no_newline {
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+}
+
 fn main() {
 
     // Collect command line arguments, skipping the first one (the program name)
@@ -95,17 +329,195 @@ fn main() {
     let mut args: Vec<String> = env::args().skip(1).collect();
     let options = parse_options(&mut args);
 
-    let output = if !args.is_empty() {
-        let joined = args.join(" ");
-        if options.escaped_args {
-            handle_escapes(&joined)
-        } else {
-            joined
+    // --help and --version print their text instead of echoing the arguments
+    if options.show_help {
+        print!("{}", USAGE);
+        return;
+    }
+    if options.show_version {
+        println!("echocli {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+
+    if options.upper && options.lower {
+        eprintln!("echocli: --upper and --lower cannot be used together");
+        process::exit(1);
+    }
+
+    if let Err(e) = print_output(&mut io::stdout().lock(), &options, &args) {
+        eprintln!("echocli: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parse `args` like the command line and return the options and the remaining text
+    fn parse(args: &[&str]) -> (EchoOptions, Vec<String>) {
+        let mut args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        let options = parse_options(&mut args);
+        (options, args)
+    }
+
+    // The line echocli prints for `args`, without the newline
+    fn echo(args: &[&str]) -> String {
+        let (options, rest) = parse(args);
+        format_output(&options, &rest)
+    }
+
+    #[test]
+    fn help_and_version_are_recognized() {
+        let (options, rest) = parse(&["--help"]);
+        assert!(options.show_help);
+        assert!(rest.is_empty());
+
+        let (options, _) = parse(&["-e", "-h", "text"]);
+        assert!(options.show_help);
+
+        let (options, _) = parse(&["--version"]);
+        assert!(options.show_version);
+        assert!(!options.show_help);
+    }
+
+    #[test]
+    fn help_after_the_terminator_is_text() {
+        let (options, rest) = parse(&["--", "--help"]);
+        assert!(!options.show_help);
+        assert_eq!(rest, ["--help"]);
+
+        let (options, rest) = parse(&["hi", "--version"]);
+        assert!(!options.show_version);
+        assert_eq!(rest, ["hi", "--version"]);
+    }
+
+    #[test]
+    fn usage_lists_every_flag() {
+        for flag in [
+            "-e", "-E", "-n", "--posix", "--show-whitespace", "--upper", "--lower", "--json",
+            "--prefix", "--suffix", "--ascii-only", "-h, --help", "--version", "--  ",
+        ] {
+            assert!(USAGE.contains(flag), "usage does not mention {}", flag);
         }
-    } else {
-        String::new() // If no arguments, return an empty string
-    };
+        assert!(USAGE.starts_with("Usage: echocli"));
+    }
 
-    println!("{}", output);
-    
-}
\ No newline at end of file
+    #[test]
+    fn terminator_makes_dash_arguments_literal() {
+        let (options, rest) = parse(&["-e", "--", "-n"]);
+        assert!(options.escaped_args);
+        assert_eq!(rest, ["-n"]);
+        assert_eq!(echo(&["-e", "--", "-n"]), "-n");
+        assert_eq!(echo(&["--", "-e", "--", "x"]), "-e -- x");
+        assert_eq!(echo(&["--"]), "");
+    }
+
+    #[test]
+    fn escapes_apply_after_the_terminator() {
+        assert_eq!(echo(&["-e", "--", "-a\\tb"]), "-a\tb");
+        assert_eq!(echo(&["-E", "--", "-a\\tb"]), "-a\\tb");
+    }
+
+    #[test]
+    fn posix_interprets_escapes_without_e() {
+        assert_eq!(echo(&["--posix", "a\\tb"]), "a\tb");
+        assert_eq!(echo(&["--posix", "-E", "a\\tb"]), "a\\tb");
+        assert_eq!(echo(&["--posix", "-E", "-e", "a\\tb"]), "a\tb");
+        assert_eq!(echo(&["--posix", "-n", "--", "-e"]), "-e");
+    }
+
+    #[test]
+    fn without_posix_escapes_need_e() {
+        let (options, _) = parse(&["a\\tb"]);
+        assert!(!options.posix);
+        assert_eq!(echo(&["a\\tb"]), "a\\tb");
+        assert_eq!(echo(&["-e", "a\\tb"]), "a\tb");
+    }
+
+    #[test]
+    fn echo_posix_accepts_1_and_true() {
+        assert!(posix_from_env(Some("1")));
+        assert!(posix_from_env(Some("true")));
+        assert!(!posix_from_env(Some("0")));
+        assert!(!posix_from_env(Some("")));
+        assert!(!posix_from_env(None));
+    }
+
+    // The bytes print_output writes for `args`, including the newline
+    fn echo_line(args: &[&str]) -> String {
+        let (options, rest) = parse(args);
+        let mut out = Vec::new();
+        print_output(&mut out, &options, &rest).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn flags_after_the_first_text_argument_are_text() {
+        assert_eq!(echo(&["foo", "-e", "bar"]), "foo -e bar");
+        assert_eq!(echo(&["foo", "-e", "a\\tb"]), "foo -e a\\tb");
+
+        let (options, rest) = parse(&["-e", "foo", "-n"]);
+        assert!(options.escaped_args);
+        assert!(!options.no_newline);
+        assert_eq!(rest, ["foo", "-n"]);
+        assert_eq!(echo_line(&["-e", "foo\\t", "-n"]), "foo\t -n\n");
+    }
+
+    #[test]
+    fn no_newline_drops_only_the_trailing_newline() {
+        assert_eq!(echo_line(&["hi"]), "hi\n");
+        assert_eq!(echo_line(&["-n", "hi"]), "hi");
+        assert_eq!(echo_line(&["-n"]), "");
+        assert_eq!(echo_line(&["-n", "-e", "a\\nb"]), "a\nb");
+        assert_eq!(echo_line(&["--", "-n", "hi"]), "-n hi\n");
+    }
+
+    #[test]
+    fn prefix_and_suffix_go_inside_the_newline() {
+        assert_eq!(echo_line(&["--prefix", "[INFO] ", "hi"]), "[INFO] hi\n");
+        assert_eq!(echo_line(&["--prefix", "<", "--suffix=>", "hi"]), "<hi>\n");
+        assert_eq!(echo_line(&["-n", "--prefix", "<", "--suffix", ">", "hi"]), "<hi>");
+        assert_eq!(echo_line(&["--prefix=<", "-n", "--suffix=>", "a", "b"]), "<a b>");
+        assert_eq!(echo_line(&["-n", "--suffix", ";"]), ";");
+    }
+
+    #[test]
+    fn show_whitespace_marks_spaces_tabs_and_line_ends() {
+        assert_eq!(show_whitespace("a b\tc"), "a·b→c$");
+        assert_eq!(show_whitespace("a \nb\t"), "a·$\nb→$");
+        assert_eq!(show_whitespace(""), "$");
+        assert_eq!(echo(&["-e", "--show-whitespace", "a b\\tc\\n"]), "a·b→c$\n$");
+        assert_eq!(echo(&["--show-whitespace", "a\\tb"]), "a\\tb$");
+    }
+
+    #[test]
+    fn upper_and_lower_use_unicode_case_folding() {
+        assert_eq!(echo(&["--upper", "straße", "école"]), "STRASSE ÉCOLE");
+        assert_eq!(echo(&["--lower", "ÉCOLE", "Straße"]), "école straße");
+        // Case-folding runs after the escapes
+        assert_eq!(echo(&["-e", "--upper", "a\\tb"]), "A\tB");
+        assert_eq!(echo(&["--upper", "--prefix", "x", "y"]), "xY");
+    }
+
+    #[test]
+    fn json_prints_a_valid_escaped_string() {
+        let output = echo(&["--json", "say \"hi\"\n", "back\\slash"]);
+        assert_eq!(output, r#""say \"hi\"\n back\\slash""#);
+        let parsed: String = serde_json::from_str(&output).unwrap();
+        assert_eq!(parsed, "say \"hi\"\n back\\slash");
+
+        // -e is ignored, the backslash sequence stays in the text
+        assert_eq!(echo(&["-e", "--json", "a\\tb"]), r#""a\\tb""#);
+        assert_eq!(echo(&["--json", "\u{1}"]), r#""\u0001""#);
+    }
+
+    #[test]
+    fn ascii_only_replaces_each_non_ascii_character() {
+        assert_eq!(ascii_only("café 😀", "?"), "caf? ?");
+        assert_eq!(echo(&["--ascii-only", "café", "😀!"]), "caf? ?!");
+        assert_eq!(echo(&["--ascii-only=_", "naïve"]), "na_ve");
+        assert_eq!(echo(&["--ascii-only=", "é-e"]), "-e");
+        assert_eq!(echo(&["--ascii-only", "--prefix", "» ", "plain"]), "? plain");
+    }
+}
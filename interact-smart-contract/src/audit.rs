@@ -0,0 +1,92 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use ethers::types::H256;
+use serde::Serialize;
+
+// This module keeps a record of the sent transactions in the audit log at AUDIT_LOG_PATH.
+// Every transaction is appended as one JSON object per line (JSON Lines), reads are not recorded.
+// The transaction is already sent when its record is written, so a failed write only prints a warning.
+
+#[derive(Debug, Serialize)]
+pub struct AuditRecord<'a> {
+    pub hash: H256,
+    pub function: &'a str,
+    pub args: &'a [String],
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    // pending, confirmed, reverted, dropped or unknown
+    pub status: &'a str,
+}
+
+/// Append a record to the audit log as a single line
+/// The file is opened in append mode and the line is written at once,
+/// so records of concurrent runs do not overwrite or interleave with each other.
+pub fn append(path: &Path, record: &AuditRecord) -> io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(&line)
+}
+
+/// Record a sent transaction in the audit log, if one is configured
+/// A failure to write the log is printed as a warning and otherwise ignored.
+pub fn record(path: Option<&str>, hash: H256, function: &str, args: &[String], status: &str) {
+    let Some(path) = path else {
+        return;
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let record = AuditRecord { hash, function, args, timestamp, status };
+    if let Err(err) = append(Path::new(path), &record) {
+        eprintln!("Warning: failed to write audit log {}: {}", path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::{env, fs};
+
+    #[test]
+    fn records_are_appended_as_json_lines() {
+        let path = env::temp_dir().join(format!("audit-test-{}.jsonl", std::process::id()));
+        let args = ["42".to_string()];
+        let records = [
+            AuditRecord { hash: H256::repeat_byte(1), function: "set", args: &args, timestamp: 1700000000, status: "pending" },
+            AuditRecord { hash: H256::repeat_byte(2), function: "set", args: &[], timestamp: 1700000060, status: "reverted" },
+        ];
+        for record in &records {
+            append(&path, record).unwrap();
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let lines: Vec<Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(
+            lines,
+            [
+                json!({"hash": H256::repeat_byte(1), "function": "set", "args": ["42"], "timestamp": 1700000000, "status": "pending"}),
+                json!({"hash": H256::repeat_byte(2), "function": "set", "args": [], "timestamp": 1700000060, "status": "reverted"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn record_fills_in_the_timestamp() {
+        let path = env::temp_dir().join(format!("audit-record-test-{}.jsonl", std::process::id()));
+        record(path.to_str(), H256::zero(), "set", &["1".to_string()], "confirmed");
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let line: Value = serde_json::from_str(contents.trim_end()).unwrap();
+        assert_eq!(line["status"], "confirmed");
+        assert!(line["timestamp"].as_u64().unwrap() > 1700000000);
+    }
+}
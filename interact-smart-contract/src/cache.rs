@@ -0,0 +1,93 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use ethers::types::Bytes;
+
+// This module caches the results of read-only calls for --cache-ttl.
+// A result is keyed by the call data, e.g. the encoded get(), and served without an RPC request
+// until it is older than the TTL. The cache lives as long as the process, so it matters for
+// repeated reads in repl and watch-value. A TTL of 0 disables it.
+
+pub struct ReadCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<Bytes, (Instant, Bytes)>>,
+}
+
+impl ReadCache {
+    pub fn new(ttl: Duration) -> Self {
+        ReadCache { ttl, entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// The cached result of a call, None if there is none or it has expired
+    pub fn get(&self, data: &Bytes) -> Option<Bytes> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        let entries = self.entries.lock().unwrap();
+        let (stored_at, output) = entries.get(data)?;
+        (stored_at.elapsed() < self.ttl).then(|| output.clone())
+    }
+
+    /// Store the result of a call, replacing an older one
+    pub fn insert(&self, data: Bytes, output: Bytes) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.entries.lock().unwrap().insert(data, (Instant::now(), output));
+    }
+
+    /// Forget all results, e.g. after a transaction may have changed the state
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn bytes(value: u8) -> Bytes {
+        Bytes::from(vec![value])
+    }
+
+    #[test]
+    fn results_are_served_until_the_ttl_expires() {
+        let cache = ReadCache::new(Duration::from_millis(50));
+        assert_eq!(cache.get(&bytes(1)), None);
+
+        cache.insert(bytes(1), bytes(42));
+        assert_eq!(cache.get(&bytes(1)), Some(bytes(42)));
+        assert_eq!(cache.get(&bytes(2)), None);
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get(&bytes(1)), None);
+    }
+
+    #[test]
+    fn insert_replaces_and_restarts_the_ttl() {
+        let cache = ReadCache::new(Duration::from_secs(60));
+        cache.insert(bytes(1), bytes(1));
+        cache.insert(bytes(1), bytes(2));
+        assert_eq!(cache.get(&bytes(1)), Some(bytes(2)));
+    }
+
+    #[test]
+    fn clear_forgets_every_result() {
+        let cache = ReadCache::new(Duration::from_secs(60));
+        cache.insert(bytes(1), bytes(1));
+        cache.insert(bytes(2), bytes(2));
+        cache.clear();
+        assert_eq!(cache.get(&bytes(1)), None);
+        assert_eq!(cache.get(&bytes(2)), None);
+    }
+
+    #[test]
+    fn zero_ttl_disables_the_cache() {
+        let cache = ReadCache::new(Duration::ZERO);
+        cache.insert(bytes(1), bytes(42));
+        assert_eq!(cache.get(&bytes(1)), None);
+    }
+}
@@ -0,0 +1,113 @@
+use ethers::{
+    abi::{Param, ParamType, Token},
+    types::{Address, I256, U256},
+};
+
+// This module converts command line strings into ABI tokens
+// The expected Solidity type of each argument is taken from the contract ABI,
+// so `call set 42` becomes a uint256 token and `call balanceOf 0x...` an address token.
+// Supported types are uint, int, address, bool and string.
+
+// Convert a single string into the token for the given Solidity type
+// Unsigned integers accept decimal or 0x-prefixed hex values
+pub fn coerce_arg(kind: &ParamType, value: &str) -> Result<Token, String> {
+    match kind {
+        ParamType::Uint(_) => {
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => U256::from_str_radix(hex, 16),
+                None => U256::from_dec_str(value).map_err(|e| e.into()),
+            };
+            parsed
+                .map(Token::Uint)
+                .map_err(|_| format!("invalid uint value: {}", value))
+        }
+        ParamType::Int(_) => I256::from_dec_str(value)
+            .map(|v| Token::Int(v.into_raw()))
+            .map_err(|_| format!("invalid int value: {}", value)),
+        ParamType::Address => value
+            .parse::<Address>()
+            .map(Token::Address)
+            .map_err(|_| format!("invalid address value: {}", value)),
+        ParamType::Bool => match value {
+            "true" => Ok(Token::Bool(true)),
+            "false" => Ok(Token::Bool(false)),
+            _ => Err(format!("invalid bool value: {}", value)),
+        },
+        ParamType::String => Ok(Token::String(value.to_string())),
+        _ => Err(format!("unsupported argument type: {}", kind)),
+    }
+}
+
+// Convert all arguments of a function call, checking the argument count first
+pub fn coerce_args(params: &[Param], values: &[String]) -> Result<Vec<Token>, String> {
+    if params.len() != values.len() {
+        return Err(format!(
+            "expected {} argument(s), got {}",
+            params.len(),
+            values.len()
+        ));
+    }
+
+    params
+        .iter()
+        .zip(values)
+        .map(|(param, value)| coerce_arg(&param.kind, value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(name: &str, kind: ParamType) -> Param {
+        Param { name: name.to_string(), kind, internal_type: None }
+    }
+
+    #[test]
+    fn uint_accepts_decimal_and_hex() {
+        assert_eq!(coerce_arg(&ParamType::Uint(256), "42"), Ok(Token::Uint(U256::from(42))));
+        assert_eq!(coerce_arg(&ParamType::Uint(256), "0x2a"), Ok(Token::Uint(U256::from(42))));
+        assert!(coerce_arg(&ParamType::Uint(256), "-1").is_err());
+        assert_eq!(coerce_arg(&ParamType::Uint(8), "abc"), Err("invalid uint value: abc".to_string()));
+    }
+
+    #[test]
+    fn int_accepts_negative_values() {
+        assert_eq!(
+            coerce_arg(&ParamType::Int(256), "-5"),
+            Ok(Token::Int(I256::from(-5).into_raw()))
+        );
+        assert!(coerce_arg(&ParamType::Int(256), "0x5").is_err());
+    }
+
+    #[test]
+    fn address_bool_and_string() {
+        let address = "0x5FbDB2315678afecb367f032d93F642f64180aa3";
+        assert_eq!(coerce_arg(&ParamType::Address, address), Ok(Token::Address(address.parse().unwrap())));
+        assert_eq!(coerce_arg(&ParamType::Address, "0x123"), Err("invalid address value: 0x123".to_string()));
+        assert_eq!(coerce_arg(&ParamType::Bool, "true"), Ok(Token::Bool(true)));
+        assert_eq!(coerce_arg(&ParamType::Bool, "false"), Ok(Token::Bool(false)));
+        assert!(coerce_arg(&ParamType::Bool, "1").is_err());
+        assert_eq!(coerce_arg(&ParamType::String, "hi there"), Ok(Token::String("hi there".to_string())));
+    }
+
+    #[test]
+    fn unsupported_types_are_rejected() {
+        assert_eq!(
+            coerce_arg(&ParamType::Bytes, "0x00"),
+            Err("unsupported argument type: bytes".to_string())
+        );
+    }
+
+    #[test]
+    fn coerce_args_checks_the_count_and_keeps_the_order() {
+        let params = [param("to", ParamType::Address), param("amount", ParamType::Uint(256))];
+        let values = ["0x5FbDB2315678afecb367f032d93F642f64180aa3".to_string(), "7".to_string()];
+        let tokens = coerce_args(&params, &values).unwrap();
+        assert_eq!(tokens[1], Token::Uint(U256::from(7)));
+        assert!(matches!(tokens[0], Token::Address(_)));
+
+        assert_eq!(coerce_args(&params, &values[..1]), Err("expected 2 argument(s), got 1".to_string()));
+        assert_eq!(coerce_args(&[], &[]), Ok(Vec::new()));
+    }
+}
@@ -0,0 +1,65 @@
+use std::env;
+use ethers::types::H256;
+
+// This module links sent transactions to a block explorer, so they can be opened in a browser.
+// The explorer is chosen by the chain id of the RPC endpoint, EXPLORER_URL overrides it,
+// e.g. for a chain that is not listed below or a self-hosted explorer.
+
+// Base URLs of the explorers of common chains, by chain id
+const EXPLORERS: &[(u64, &str)] = &[
+    (1, "https://etherscan.io"),
+    (11155111, "https://sepolia.etherscan.io"),
+    (17000, "https://holesky.etherscan.io"),
+    (10, "https://optimistic.etherscan.io"),
+    (42161, "https://arbiscan.io"),
+    (8453, "https://basescan.org"),
+    (56, "https://bscscan.com"),
+    (137, "https://polygonscan.com"),
+    (80002, "https://amoy.polygonscan.com"),
+];
+
+/// The explorer page of a transaction, None on a chain without a known explorer
+/// EXPLORER_URL is used as the base URL instead of the built-in one when it is set.
+pub fn explorer_tx_url(chain_id: u64, hash: H256) -> Option<String> {
+    tx_url(env::var("EXPLORER_URL").ok(), chain_id, hash)
+}
+
+// The explorer page of a transaction, with `explorer_url` in place of EXPLORER_URL
+fn tx_url(explorer_url: Option<String>, chain_id: u64, hash: H256) -> Option<String> {
+    let base = match explorer_url {
+        Some(url) if !url.is_empty() => url,
+        _ => EXPLORERS
+            .iter()
+            .find(|(id, _)| *id == chain_id)
+            .map(|(_, url)| url.to_string())?,
+    };
+    Some(format!("{}/tx/{:?}", base.trim_end_matches('/'), hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_chains_link_to_their_explorer() {
+        let hash = H256::repeat_byte(0xab);
+        assert_eq!(tx_url(None, 1, hash), Some(format!("https://etherscan.io/tx/{:?}", hash)));
+        assert_eq!(tx_url(None, 11155111, hash), Some(format!("https://sepolia.etherscan.io/tx/{:?}", hash)));
+        assert_eq!(tx_url(None, 137, hash), Some(format!("https://polygonscan.com/tx/{:?}", hash)));
+        assert!(tx_url(None, 1, hash).unwrap().ends_with("/tx/0xabababababababababababababababababababababababababababababababab"));
+    }
+
+    #[test]
+    fn unknown_chains_have_no_link() {
+        assert_eq!(tx_url(None, 31337, H256::zero()), None);
+        assert_eq!(tx_url(Some(String::new()), 31337, H256::zero()), None);
+    }
+
+    #[test]
+    fn explorer_url_overrides_the_built_in_explorer() {
+        let hash = H256::zero();
+        let url = Some("https://explorer.example.org/".to_string());
+        assert_eq!(tx_url(url.clone(), 1, hash), Some(format!("https://explorer.example.org/tx/{:?}", hash)));
+        assert_eq!(tx_url(url, 31337, hash), Some(format!("https://explorer.example.org/tx/{:?}", hash)));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+use async_trait::async_trait;
+use ethers::{
+    abi::Token,
+    providers::{JsonRpcClient, JsonRpcError, MockError, ProviderError},
+    types::{transaction::eip2718::TypedTransaction, Bytes, Transaction, TransactionReceipt, H256, U256, U64},
+    utils::{keccak256, rlp::Rlp},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use crate::SIMPLESTORAGE_ABI;
+
+// This module provides an in-memory stand-in for an Ethereum node used by --local
+// It answers the JSON-RPC methods the tool needs and emulates the SimpleStorage contract,
+// so set and get work end-to-end without an RPC endpoint or a funded key.
+// Sent transactions are mined right away in block 0 and can be looked up with their receipt.
+// State lives only as long as the process.
+
+// Chain id reported by the local node, the same one used by anvil and hardhat
+pub const LOCAL_CHAIN_ID: u64 = 31337;
+
+// Well-known first development key of anvil and hardhat, never use it on a real network
+pub const DEV_PRIVATE_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+// Address the emulated SimpleStorage contract is "deployed" at
+pub const LOCAL_CONTRACT_ADDRESS: &str = "0x5FbDB2315678afecb367f032d93F642f64180aa3";
+
+#[derive(Debug, Default, Clone)]
+pub struct LocalNode {
+    state: Arc<Mutex<LocalState>>,
+}
+
+#[derive(Debug, Default)]
+struct LocalState {
+    stored_value: U256,
+    nonce: u64,
+    // Sent transactions and their receipts by hash
+    transactions: HashMap<H256, (Transaction, TransactionReceipt)>,
+}
+
+impl LocalNode {
+    // Run a call against the emulated contract and return the ABI encoded result
+    // Calls to set are only simulated, they check the argument but leave the state unchanged
+    fn call(&self, data: &[u8]) -> Result<Bytes, ProviderError> {
+        let get = SIMPLESTORAGE_ABI.function("get").map_err(custom_error)?;
+        let set = SIMPLESTORAGE_ABI.function("set").map_err(custom_error)?;
+        let selector = data.get(..4);
+
+        if selector == Some(&get.short_signature()[..]) {
+            let value = self.state.lock().unwrap().stored_value;
+            return Ok(ethers::abi::encode(&[Token::Uint(value)]).into());
+        }
+        if selector == Some(&set.short_signature()[..]) {
+            set.decode_input(&data[4..]).map_err(|_| revert("invalid set argument"))?;
+            return Ok(Bytes::default());
+        }
+        Err(revert("function selector was not recognized"))
+    }
+
+    // Decode a signed transaction, apply it to the emulated contract and return its hash
+    fn send_raw_transaction(&self, raw: &Bytes) -> Result<Value, ProviderError> {
+        let (tx, signature) = TypedTransaction::decode_signed(&Rlp::new(raw)).map_err(custom_error)?;
+        let data = tx.data().cloned().unwrap_or_default();
+
+        let set = SIMPLESTORAGE_ABI.function("set").map_err(custom_error)?;
+        if data.get(..4) != Some(&set.short_signature()[..]) {
+            return Err(revert("function selector was not recognized"));
+        }
+        let tokens = set.decode_input(&data[4..]).map_err(|_| revert("invalid set argument"))?;
+        let value = tokens
+            .first()
+            .and_then(|token| token.clone().into_uint())
+            .ok_or_else(|| revert("invalid set argument"))?;
+
+        // Record the mined transaction and a successful receipt, so its confirmation can be awaited
+        let hash = H256::from(keccak256(raw));
+        let from = signature.recover(tx.sighash()).map_err(custom_error)?;
+        let block_number = Some(U64::zero());
+        let transaction = Transaction {
+            hash,
+            nonce: tx.nonce().cloned().unwrap_or_default(),
+            block_hash: Some(H256::zero()),
+            block_number,
+            transaction_index: Some(U64::zero()),
+            from,
+            to: tx.to_addr().cloned(),
+            value: tx.value().cloned().unwrap_or_default(),
+            gas: tx.gas().cloned().unwrap_or_default(),
+            input: data,
+            v: signature.v.into(),
+            r: signature.r,
+            s: signature.s,
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            transaction_hash: hash,
+            block_hash: Some(H256::zero()),
+            block_number,
+            from,
+            to: tx.to_addr().cloned(),
+            status: Some(U64::one()),
+            ..Default::default()
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.stored_value = value;
+        state.nonce += 1;
+        state.transactions.insert(hash, (transaction, receipt));
+        Ok(json!(hash))
+    }
+}
+
+// Build the error a node returns when a call reverts with `reason`
+// The revert data is encoded as Error(string), like a Solidity require message,
+// so it can be decoded the same way as a revert from a real node
+fn revert(reason: &str) -> ProviderError {
+    let mut data = vec![0x08, 0xc3, 0x79, 0xa0];
+    data.extend(ethers::abi::encode(&[Token::String(reason.to_string())]));
+    MockError::JsonRpcError(JsonRpcError {
+        code: 3,
+        message: format!("execution reverted: {}", reason),
+        data: Some(json!(Bytes::from(data))),
+    })
+    .into()
+}
+
+fn custom_error<E: std::fmt::Display>(err: E) -> ProviderError {
+    ProviderError::CustomError(err.to_string())
+}
+
+#[async_trait]
+impl JsonRpcClient for LocalNode {
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = serde_json::to_value(params)?;
+
+        let result = match method {
+            "eth_chainId" => json!(format!("{:#x}", LOCAL_CHAIN_ID)),
+            "eth_blockNumber" => json!("0x0"),
+            "eth_gasPrice" => json!("0x1"),
+            "eth_estimateGas" => json!("0x100000"),
+            // The emulated contract emits no events
+            "eth_getLogs" => json!([]),
+            "eth_getTransactionCount" => json!(format!("{:#x}", self.state.lock().unwrap().nonce)),
+            "eth_call" => {
+                // Newer clients send the calldata as "input", older ones as "data"
+                let tx = &params[0];
+                let data: Bytes = serde_json::from_value(
+                    tx.get("input").or_else(|| tx.get("data")).cloned().unwrap_or(json!("0x")),
+                )?;
+                json!(self.call(&data)?)
+            }
+            "eth_getTransactionByHash" | "eth_getTransactionReceipt" => {
+                let hash: H256 = serde_json::from_value(params[0].clone())?;
+                let state = self.state.lock().unwrap();
+                match state.transactions.get(&hash) {
+                    Some((tx, _)) if method == "eth_getTransactionByHash" => json!(tx),
+                    Some((_, receipt)) => json!(receipt),
+                    None => Value::Null,
+                }
+            }
+            "eth_sendRawTransaction" => {
+                let raw: Bytes = serde_json::from_value(params[0].clone())?;
+                self.send_raw_transaction(&raw)?
+            }
+            _ => return Err(ProviderError::UnsupportedRPC),
+        };
+
+        Ok(serde_json::from_value(result)?)
+    }
+}
@@ -1,21 +1,58 @@
+mod audit;
+mod cache;
+mod coerce;
+mod explorer;
+mod local;
+
 use std::{
     sync::Arc,
     env,
+    fmt,
+    fs,
+    future::Future,
+    io::{self, Write},
+    time::Duration,
 };
 use dotenv::dotenv;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use serde_json::{json, Value};
 use ethers::{
-    contract::abigen, middleware::SignerMiddleware, providers::{
-        Http, Middleware, Provider
+    abi::{Abi, RawLog, StateMutability},
+    abi::Detokenize,
+    contract::{abigen, ContractError, EthError}, middleware::{signer::SignerMiddlewareError, MiddlewareError, SignerMiddleware}, providers::{
+        Http, JsonRpcClient, Middleware, Provider, ProviderError, DEFAULT_LOCAL_POLL_INTERVAL
     }, signers::{LocalWallet, Signer}, types::{
+        transaction::eip2718::TypedTransaction,
         Address, 
-        U256
-    }
+        BlockNumber,
+        Bytes,
+        Filter,
+        TransactionRequest,
+        U256,
+    },
+    utils::{format_units, parse_units, ParseUnits},
 };
+use cache::ReadCache;
+use coerce::coerce_args;
+use explorer::explorer_tx_url;
+use local::{LocalNode, DEV_PRIVATE_KEY, LOCAL_CONTRACT_ADDRESS};
+
+// Seconds an RPC request may take when RPC_TIMEOUT_SECS is not set
+const DEFAULT_RPC_TIMEOUT_SECS: u64 = 30;
+
+// Seconds between two attempts of a failed read when --retry-delay is not given
+const DEFAULT_RETRY_DELAY_SECS: u64 = 1;
+
+// Seconds between two polls of watch-value when --interval is not given
+const DEFAULT_WATCH_INTERVAL_SECS: u64 = 5;
+
+// Largest block range queried with a single eth_getLogs request by `logs`
+const LOGS_CHUNK_SIZE: u64 = 2000;
 
 // Generate the contract bindings for SimpleStorage
 // abigen! is a macro that generates Rust bindings for the contract ABI
 // Ensure you have the ABI file in the correct location
-// .\abi\SimpleStorage.json
+// ./abi/SimpleStorage.json
 // The SimpleStorage contract is expected to have the following functions:
 // - set(uint256) to set a value
 // - get() to retrieve the stored value
@@ -23,10 +60,597 @@ use ethers::{
 // to be deserialized and serialized using serde
 abigen!(
     SimpleStorage,
-    ".\\abi\\SimpleStorage.json",
+    "./abi/SimpleStorage.json",
     event_derives(serde::Deserialize, serde::Serialize)
 );
 
+// How often a read-only call is retried, set by --retries and --retry-delay
+// Only failures on the way to the RPC endpoint are retried, e.g. a timeout or a dropped connection,
+// an answer of the node such as a revert is final. Sends are never retried.
+#[derive(Debug, Default, Clone, Copy)]
+struct ReadRetry {
+    retries: u32,
+    delay: Duration,
+}
+
+// Run a read, repeating it up to `retry.retries` more times while it fails with a transient error
+async fn retry_read<T, E, Fut>(
+    retry: ReadRetry,
+    is_transient: impl Fn(&E) -> bool,
+    mut read: impl FnMut() -> Fut,
+) -> Result<T, E>
+where
+    E: fmt::Display,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        match read().await {
+            Err(err) if attempt < retry.retries && is_transient(&err) => {
+                attempt += 1;
+                eprintln!("Read failed: {}, retrying in {:?} ({}/{})", err, retry.delay, attempt, retry.retries);
+                tokio::time::sleep(retry.delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Whether a failed call may succeed when it is sent again
+// An error response of the node, e.g. a revert, is final, anything else failed on the way to the node,
+// e.g. a timeout, a dropped connection or a 503 page instead of a JSON-RPC answer
+fn is_transient<M: Middleware>(err: &M::Error) -> bool {
+    !err.is_error_response()
+}
+
+// Run a read-only call against the latest block, retrying transient failures
+async fn call_with_retry<M: Middleware>(client: &M, tx: &TypedTransaction, retry: ReadRetry) -> Result<Bytes, M::Error> {
+    retry_read(retry, is_transient::<M>, || client.call(tx, None)).await
+}
+
+// Run a read-only call, serving it from the cache while an earlier result is fresh, see cache.rs
+async fn cached_call<M: Middleware>(
+    client: &M,
+    tx: &TypedTransaction,
+    retry: ReadRetry,
+    cache: &ReadCache,
+) -> Result<Bytes, M::Error> {
+    let data = tx.data().cloned().unwrap_or_default();
+    if let Some(output) = cache.get(&data) {
+        return Ok(output);
+    }
+    let output = call_with_retry(client, tx, retry).await?;
+    cache.insert(data, output.clone());
+    Ok(output)
+}
+
+// Call a view or pure function by name and print the decoded result
+// The function and its argument types are looked up in the abigen-generated ABI,
+// so any read-only function of the contract can be called, e.g. `call get`
+// This is a read-only operation and does not require gas
+async fn call_function<M: Middleware + 'static>(
+    contract: &SimpleStorage<M>,
+    args: &[String],
+    retry: ReadRetry,
+    cache: &ReadCache,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (name, values) = args.split_first().ok_or("usage: call <function> [args...]")?;
+    let function = contract.abi().function(name)?;
+    if !matches!(function.state_mutability, StateMutability::View | StateMutability::Pure) {
+        return Err(format!("{} changes state, use send instead", name).into());
+    }
+
+    // Encode the call, run it against the latest block and decode the returned values
+    let tokens = coerce_args(&function.inputs, values)?;
+    let data = function.encode_input(&tokens)?;
+    let tx: TypedTransaction = TransactionRequest::new().to(contract.address()).data(data).into();
+    let output = cached_call(contract.client().as_ref(), &tx, retry, cache).await?;
+    for token in function.decode_output(&output)? {
+        println!("{}: {}", name, token);
+    }
+
+    Ok(())
+}
+
+// Build one eth_call request per call data, all against the latest block
+// The id of a request is the index of its call, so the responses of a batch can be matched to the calls
+fn batch_requests(to: Address, calls: &[Bytes]) -> Vec<Value> {
+    calls
+        .iter()
+        .enumerate()
+        .map(|(id, data)| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "eth_call",
+                "params": [{ "to": to, "data": data }, "latest"],
+            })
+        })
+        .collect()
+}
+
+// Send the eth_calls as a single JSON-RPC batch request and return the output or the error of each call
+// ethers' Http provider sends one request per call, so the batch is posted directly to the endpoint
+// The responses of a batch may come in any order and are matched to the calls by their id
+async fn batch_eth_call(
+    endpoint: &RpcEndpoint,
+    to: Address,
+    calls: &[Bytes],
+) -> Result<Vec<Result<Bytes, String>>, Box<dyn std::error::Error>> {
+    let body = serde_json::to_vec(&batch_requests(to, calls))?;
+    let response = endpoint
+        .client
+        .post(endpoint.url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    let response: Value = serde_json::from_slice(&response.bytes().await?)?;
+
+    // An endpoint without batch support answers with a single error object instead of an array
+    let Value::Array(responses) = response else {
+        let message = response["error"]["message"].as_str().unwrap_or("unexpected response");
+        return Err(format!("the RPC endpoint does not support batch requests: {}", message).into());
+    };
+
+    let mut outputs: Vec<Result<Bytes, String>> = vec![Err("no response".to_string()); calls.len()];
+    for response in responses {
+        let Some(output) = response["id"].as_u64().and_then(|id| outputs.get_mut(id as usize)) else {
+            continue;
+        };
+        *output = match response.get("error") {
+            Some(error) => Err(error["message"].as_str().unwrap_or("unknown error").to_string()),
+            None => serde_json::from_value(response["result"].clone()).map_err(|e| e.to_string()),
+        };
+    }
+    Ok(outputs)
+}
+
+// Read several view functions without arguments and print each result, e.g. `multiget get get`
+// With an RPC endpoint all eth_calls go out in one JSON-RPC batch, so they take a single round trip
+// The local node runs in-process, so its calls are simply made one by one
+// A failed call is printed with its error and does not stop the others
+// A batch that fails on the way to the endpoint is retried as a whole, errors of single calls are final
+async fn multi_get<M: Middleware + 'static>(
+    contract: &SimpleStorage<M>,
+    names: &[String],
+    endpoint: Option<&RpcEndpoint>,
+    retry: ReadRetry,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if names.is_empty() {
+        return Err("usage: multiget <function> [function...]".into());
+    }
+
+    let mut functions = Vec::new();
+    let mut calls = Vec::new();
+    for name in names {
+        let function = contract.abi().function(name)?;
+        if !matches!(function.state_mutability, StateMutability::View | StateMutability::Pure) {
+            return Err(format!("{} changes state, use send instead", name).into());
+        }
+        if !function.inputs.is_empty() {
+            return Err(format!("{} takes arguments, use call instead", name).into());
+        }
+        calls.push(Bytes::from(function.encode_input(&[])?));
+        functions.push(function);
+    }
+
+    let outputs = match endpoint {
+        Some(endpoint) => {
+            // Only transport failures are retried, a malformed or failed batch response would fail again
+            let read = || batch_eth_call(endpoint, contract.address(), &calls);
+            retry_read(retry, |err| err.is::<reqwest::Error>(), read).await?
+        }
+        None => {
+            let mut outputs = Vec::new();
+            for data in &calls {
+                let tx: TypedTransaction = TransactionRequest::new().to(contract.address()).data(data.clone()).into();
+                let output = call_with_retry(contract.client().as_ref(), &tx, retry).await;
+                outputs.push(output.map_err(|e| e.to_string()));
+            }
+            outputs
+        }
+    };
+
+    for (function, output) in functions.iter().zip(outputs) {
+        match output.and_then(|output| function.decode_output(&output).map_err(|e| e.to_string())) {
+            Ok(tokens) => {
+                for token in tokens {
+                    println!("{}: {}", function.name, token);
+                }
+            }
+            Err(e) => println!("{}: error: {}", function.name, e),
+        }
+    }
+
+    Ok(())
+}
+
+// Split the block range from..=to into inclusive ranges of at most `chunk_size` blocks
+// e.g. 0..=4500 with a chunk size of 2000 becomes 0..=1999, 2000..=3999 and 4000..=4500
+// RPC providers limit the block range of a single eth_getLogs request, so large ranges are queried in chunks
+fn block_chunks(from: u64, to: u64, chunk_size: u64) -> Vec<(u64, u64)> {
+    let mut chunks = Vec::new();
+    let mut start = from;
+    while start <= to {
+        let end = start.saturating_add(chunk_size.max(1) - 1).min(to);
+        chunks.push((start, end));
+        if end == u64::MAX {
+            break;
+        }
+        start = end + 1;
+    }
+    chunks
+}
+
+// Print the logs the contract emitted in a block range, e.g. `logs --from-block 100 --to-block 200`
+// Logs of an event in the ABI are decoded into its parameters, other logs are printed as raw topics and data
+// Without --to-block the range ends at the latest block
+async fn print_logs<M: Middleware + 'static>(
+    contract: &SimpleStorage<M>,
+    from_block: u64,
+    to_block: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = contract.client();
+    let to_block = match to_block {
+        Some(block) => block,
+        None => client.get_block_number().await?.as_u64(),
+    };
+    if from_block > to_block {
+        return Err(format!("--from-block {} is after --to-block {}", from_block, to_block).into());
+    }
+
+    let mut count = 0;
+    for (start, end) in block_chunks(from_block, to_block, LOGS_CHUNK_SIZE) {
+        let filter = Filter::new().address(contract.address()).from_block(start).to_block(end);
+        for log in client.get_logs(&filter).await? {
+            let block = log.block_number.map(|block| block.to_string()).unwrap_or_default();
+            let event = contract
+                .abi()
+                .events()
+                .find(|event| log.topics.first() == Some(&event.signature()));
+            let raw = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+            match event.map(|event| (event, event.parse_log(raw))) {
+                Some((event, Ok(parsed))) => {
+                    let params: Vec<String> = parsed
+                        .params
+                        .iter()
+                        .map(|param| format!("{}={}", param.name, param.value))
+                        .collect();
+                    println!("block {}: {}({})", block, event.name, params.join(", "));
+                }
+                _ => println!("block {}: topics {:?} data {}", block, log.topics, log.data),
+            }
+            count += 1;
+        }
+    }
+    println!("{} log(s) in blocks {} to {}", count, from_block, to_block);
+
+    Ok(())
+}
+
+// Parse a polling interval like 5s, 500ms or 2m, a plain number is taken as seconds
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("invalid interval: {}", value))?;
+    let interval = match unit {
+        "ms" => Duration::from_millis(number),
+        "s" => Duration::from_secs(number),
+        "m" => Duration::from_secs(number.checked_mul(60).ok_or_else(|| format!("interval too large: {}", value))?),
+        _ => return Err(format!("invalid interval: {} (use ms, s or m)", value)),
+    };
+    if interval.is_zero() {
+        return Err("the interval must be greater than zero".to_string());
+    }
+    Ok(interval)
+}
+
+// Whether a polled value differs from the previous one, the first value always does
+fn value_changed(last: Option<U256>, current: U256) -> bool {
+    last != Some(current)
+}
+
+// Poll get() every `interval` and print the stored value when it changes, e.g. `watch-value --interval 5s`
+// With verbose set the value is printed on every tick
+// A failed call is reported and polling continues, Ctrl-C stops watching
+async fn watch_value<M: Middleware + 'static>(
+    contract: &SimpleStorage<M>,
+    interval: Duration,
+    verbose: bool,
+    decimals: Option<u32>,
+    cache: &ReadCache,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let get = contract.get();
+    let read_value = || async {
+        let output = cached_call(contract.client().as_ref(), &get.tx, ReadRetry::default(), cache).await?;
+        Ok::<_, Box<dyn std::error::Error>>(U256::from_tokens(get.function.decode_output(&output)?)?)
+    };
+    let mut ticks = tokio::time::interval(interval);
+    let mut last = None;
+    println!("Watching the stored value every {:?}, press Ctrl-C to stop", interval);
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                println!("Stopped watching");
+                return Ok(());
+            }
+            _ = ticks.tick() => {
+                match read_value().await {
+                    Ok(value) => {
+                        if value_changed(last, value) || verbose {
+                            let shown = match decimals {
+                                Some(decimals) => format_value(value, decimals)?,
+                                None => value.to_string(),
+                            };
+                            println!("Stored value: {}", shown);
+                        }
+                        last = Some(value);
+                    }
+                    Err(err) => eprintln!("Failed to read the stored value: {}", err),
+                }
+            }
+        }
+    }
+}
+
+// A call listed in a batch file, `line` is its 1-based line number in the file
+#[derive(Debug, Clone, PartialEq)]
+struct BatchCall {
+    line: usize,
+    function: String,
+    args: Vec<String>,
+}
+
+// Parse a batch file with one call per line, the function name followed by its arguments
+// e.g. `set 42`; blank lines and lines starting with # are skipped
+fn parse_batch(contents: &str) -> Vec<BatchCall> {
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let mut words = line.split_whitespace().map(String::from);
+            let function = words.next()?;
+            Some(BatchCall { line: index + 1, function, args: words.collect() })
+        })
+        .collect()
+}
+
+// Send the calls of a batch file one after the other, waiting for each to be confirmed
+// A failed call is reported and the batch continues, unless stop_on_error is set
+// A summary of the succeeded, failed and skipped calls is printed at the end
+async fn run_batch<M: Middleware + 'static>(
+    contract: &SimpleStorage<M>,
+    path: &str,
+    overrides: TxOverrides,
+    stop_on_error: bool,
+    chain_id: u64,
+    audit_log: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Every call needs its own nonce, a fixed one would make all but the first fail
+    if overrides.nonce.is_some() {
+        return Err("--nonce cannot be used with batch".into());
+    }
+
+    let calls = parse_batch(&fs::read_to_string(path)?);
+    let (mut succeeded, mut failed) = (0, 0);
+    for (index, call) in calls.iter().enumerate() {
+        println!("[{}/{}] {} {}", index + 1, calls.len(), call.function, call.args.join(" "));
+        let mut args = vec![call.function.clone()];
+        args.extend(call.args.iter().cloned());
+
+        match send_function(contract, &args, overrides, true, chain_id, audit_log).await {
+            Ok(()) => succeeded += 1,
+            Err(err) => {
+                eprintln!("{}:{}: {}", path, call.line, err);
+                failed += 1;
+                if stop_on_error {
+                    break;
+                }
+            }
+        }
+    }
+
+    let skipped = calls.len() - succeeded - failed;
+    println!("Batch finished: {} succeeded, {} failed, {} skipped", succeeded, failed, skipped);
+    if failed > 0 {
+        return Err(format!("{} of {} calls failed", failed, calls.len()).into());
+    }
+    Ok(())
+}
+
+// Manual values for a transaction, set with --nonce and --gas-limit
+// Values left as None are filled in automatically by ethers when sending
+// value is the amount of wei sent along with the call, set with --value in ether
+// With check_pending set, sending is refused while the wallet has pending transactions
+// unless replace is set too, then the oldest pending transaction is replaced
+// With simulate set, the transaction is run with eth_call first and not sent if it reverts,
+// unless force is set too
+// With dry_run set, the transaction is checked, filled in and simulated, but printed instead of sent
+#[derive(Debug, Default, Clone, Copy)]
+struct TxOverrides {
+    nonce: Option<U256>,
+    gas_limit: Option<U256>,
+    value: Option<U256>,
+    check_pending: bool,
+    replace: bool,
+    simulate: bool,
+    force: bool,
+    dry_run: bool,
+}
+
+// Number of transactions sent by the wallet that are still in the mempool
+// This is the difference between the nonce including pending transactions
+// and the nonce of the latest block
+fn nonce_gap(pending: U256, confirmed: U256) -> U256 {
+    pending.saturating_sub(confirmed)
+}
+
+// Look up the pending and confirmed nonces of `address` and return them with their gap
+async fn pending_nonce_gap<M: Middleware + 'static>(
+    client: &M,
+    address: Address,
+) -> Result<(U256, U256), Box<dyn std::error::Error>> {
+    let pending = client
+        .get_transaction_count(address, Some(BlockNumber::Pending.into()))
+        .await?;
+    let confirmed = client
+        .get_transaction_count(address, Some(BlockNumber::Latest.into()))
+        .await?;
+    Ok((confirmed, nonce_gap(pending, confirmed)))
+}
+
+// Build the transaction request for a contract call, applying the overrides
+// e.g. to resend a stuck transaction with the same nonce and a higher gas limit
+fn build_transaction(to: Address, data: Bytes, overrides: TxOverrides) -> TransactionRequest {
+    let mut tx = TransactionRequest::new().to(to).data(data);
+    if let Some(nonce) = overrides.nonce {
+        tx = tx.nonce(nonce);
+    }
+    if let Some(gas_limit) = overrides.gas_limit {
+        tx = tx.gas(gas_limit);
+    }
+    if let Some(value) = overrides.value {
+        tx = tx.value(value);
+    }
+    tx
+}
+
+// Describe why the node rejected a simulated transaction
+// A revert with an Error(string) reason is decoded to its message, e.g. a require() message,
+// other error responses are shown with the node's message.
+// Returns None when the node did not answer with an error response, e.g. on a connection error.
+fn revert_reason<M: Middleware>(err: &M::Error) -> Option<String> {
+    let response = err.as_error_response()?;
+    let reason = response.as_revert_data().and_then(|data| String::decode_with_selector(&data));
+    Some(reason.unwrap_or_else(|| response.message.clone()))
+}
+
+// Send a state-changing function by name as a transaction and print its hash
+// with a link to the block explorer of `chain_id`, if one is known
+// The arguments are coerced to the types in the ABI, e.g. `send set 42`
+// With confirm set, wait until the transaction is mined and fail if it reverted
+async fn send_function<M: Middleware + 'static>(
+    contract: &SimpleStorage<M>,
+    args: &[String],
+    overrides: TxOverrides,
+    confirm: bool,
+    chain_id: u64,
+    audit_log: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (name, values) = args.split_first().ok_or("usage: send <function> [args...]")?;
+    let function = contract.abi().function(name)?;
+
+    // Only payable functions accept ether, anything else would revert
+    if let Some(value) = overrides.value.filter(|value| !value.is_zero())
+        && function.state_mutability != StateMutability::Payable
+    {
+        return Err(format!("{} is not payable, cannot send {} wei with --value", name, value).into());
+    }
+
+    let client = contract.client();
+    let mut overrides = overrides;
+
+    // Refuse to send while earlier transactions are pending, to avoid accidental double-spends
+    // With --replace the new transaction takes the nonce of the oldest pending one instead
+    if overrides.check_pending {
+        let sender = client.default_sender().ok_or("no wallet configured to send from")?;
+        let (confirmed, gap) = pending_nonce_gap(client.as_ref(), sender).await?;
+        if !gap.is_zero() {
+            if !overrides.replace {
+                return Err(format!(
+                    "wallet {:?} has {} pending transaction(s), use --replace to replace the oldest one",
+                    sender, gap
+                )
+                .into());
+            }
+            overrides.nonce = overrides.nonce.or(Some(confirmed));
+        }
+    }
+
+    // Encode the call and send it, the wallet signs the transaction
+    let tokens = coerce_args(&function.inputs, values)?;
+    let data = function.encode_input(&tokens)?;
+    let tx: TypedTransaction = build_transaction(contract.address(), data.into(), overrides).into();
+
+    // Run the transaction with eth_call first, so a revert is caught before any gas is spent
+    // A dry run is always simulated, it is what would happen if the transaction was sent
+    // Only a revert can be forced, an error reaching the node is returned as it is
+    if overrides.simulate || overrides.dry_run {
+        match client.call(&tx, None).await {
+            Ok(_) => println!("Simulation succeeded"),
+            Err(err) => {
+                let reason = revert_reason::<M>(&err).ok_or(err)?;
+                if !overrides.force {
+                    return Err(format!("simulation reverted: {}, use --force to send anyway", reason).into());
+                }
+                println!("Simulation reverted: {}, sending anyway", reason);
+            }
+        }
+    }
+
+    // Stop short of broadcasting on a dry run, after the nonce, gas and fees are filled in
+    // as they would be for the real transaction
+    if overrides.dry_run {
+        let mut tx = tx;
+        client.fill_transaction(&mut tx, None).await?;
+        print_dry_run(name, values, &tx);
+        return Ok(());
+    }
+
+    let pending = client.send_transaction(tx, None).await?;
+    let tx_hash = pending.tx_hash();
+    println!("Transaction hash: {:?}", tx_hash);
+    if let Some(url) = explorer_tx_url(chain_id, tx_hash) {
+        println!("Explorer: {}", url);
+    }
+
+    // Without confirmation the transaction is recorded as pending, otherwise with the outcome of its receipt
+    if !confirm {
+        audit::record(audit_log, tx_hash, name, values, "pending");
+        return Ok(());
+    }
+
+    let receipt = pending.await;
+    let status = match &receipt {
+        Ok(Some(receipt)) if receipt.status == Some(0.into()) => "reverted",
+        Ok(Some(_)) => "confirmed",
+        Ok(None) => "dropped",
+        Err(_) => "unknown",
+    };
+    audit::record(audit_log, tx_hash, name, values, status);
+
+    let receipt = receipt?.ok_or_else(|| format!("transaction {:?} was dropped", tx_hash))?;
+    let block = receipt.block_number.unwrap_or_default();
+    if receipt.status == Some(0.into()) {
+        return Err(format!("transaction {:?} reverted in block {}", tx_hash, block).into());
+    }
+    println!("Confirmed in block {}", block);
+
+    Ok(())
+}
+
+// Print the transaction a dry run would have sent
+fn print_dry_run(name: &str, values: &[String], tx: &TypedTransaction) {
+    let unset = || "-".to_string();
+    println!("Dry run, not sent: {} {}", name, values.join(" "));
+    println!("  from: {}", tx.from().map(|from| format!("{:?}", from)).unwrap_or_else(unset));
+    println!("  to: {}", tx.to_addr().map(|to| format!("{:?}", to)).unwrap_or_else(unset));
+    println!("  nonce: {}", tx.nonce().map(U256::to_string).unwrap_or_else(unset));
+    println!("  gas limit: {}", tx.gas().map(U256::to_string).unwrap_or_else(unset));
+    println!("  gas price: {}", tx.gas_price().map(|price| format!("{} wei", price)).unwrap_or_else(unset));
+    println!("  value: {} wei", tx.value().copied().unwrap_or_default());
+    println!("  data: {}", tx.data().cloned().unwrap_or_default());
+}
+
 #[tokio::main]
 // Box<dyn std::error::Error> is used to allow for any error type to be returned
 // This is useful for handling different types of errors that may occur
@@ -35,16 +659,272 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>{
     // load from .env 
     dotenv().ok();
 
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // --local runs against an in-memory node with a deterministic dev wallet,
+    // so no RPC endpoint, private key or deployed contract is needed
+    if take_flag(&mut args, "--local") {
+        return run_local(LocalNode::default(), &args).await;
+    }
+
     // Set up the provider and wallet
     let rpc_url = env::var("ALCHEMY_RPC_URL").expect("ALCHEMY_RPC_URL not set");
-    let provider = Provider::<Http>::try_from(rpc_url)?;
+    let rpc_timeout = env::var("RPC_TIMEOUT_SECS")
+        .ok()
+        .map(|value| value.parse::<u64>())
+        .transpose()?
+        .unwrap_or(DEFAULT_RPC_TIMEOUT_SECS);
+    let endpoint = RpcEndpoint::new(&rpc_url, Duration::from_secs(rpc_timeout))?;
+    let provider = endpoint.provider();
+
+    // Load the wallet from the keystore or the private key in the environment
+    let wallet = load_wallet()?;
+
+    // The contract address is the address of the deployed contract on the Ethereum network
+    let contract_address: Address = env::var("CONTRACT_ADDRESS").expect("CONTRACT_ADDRESS not set"). parse()?;
+
+    run(provider, Some(&endpoint), wallet, contract_address, &args)
+        .await
+        .map_err(|err| describe_timeout(err, rpc_timeout))
+}
+
+// Run the command line `args` against `node` with the dev wallet and the emulated contract, see --local
+async fn run_local(node: LocalNode, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let provider = Provider::new(node).interval(DEFAULT_LOCAL_POLL_INTERVAL);
+    let wallet = DEV_PRIVATE_KEY.parse::<LocalWallet>()?;
+    let contract_address: Address = LOCAL_CONTRACT_ADDRESS.parse()?;
+    run(provider, None, wallet, contract_address, args).await
+}
+
+// Load the wallet that signs the transactions
+// With KEYSTORE_PATH set, the key is decrypted from that encrypted JSON keystore with KEYSTORE_PASSWORD,
+// so the private key never has to be stored in plain text
+// Otherwise the raw private key is read from PRIVATE_KEY
+fn load_wallet() -> Result<LocalWallet, Box<dyn std::error::Error>> {
+    wallet_from(|name| env::var(name).ok())
+}
+
+// Load the wallet from the variables returned by `var`, see load_wallet
+fn wallet_from(var: impl Fn(&str) -> Option<String>) -> Result<LocalWallet, Box<dyn std::error::Error>> {
+    if let Some(path) = var("KEYSTORE_PATH") {
+        let password = var("KEYSTORE_PASSWORD").ok_or("KEYSTORE_PASSWORD not set")?;
+        return LocalWallet::decrypt_keystore(&path, password)
+            .map_err(|e| format!("failed to decrypt keystore {}: {}", path, e).into());
+    }
+
+    let private_key = var("PRIVATE_KEY").ok_or("PRIVATE_KEY or KEYSTORE_PATH not set")?;
+    Ok(private_key.parse::<LocalWallet>()?)
+}
+
+// The HTTP RPC endpoint, shared by the provider and the JSON-RPC batches of multiget
+// Every request is aborted after the timeout, so a hung endpoint fails with a
+// "operation timed out" error instead of blocking forever
+// The client keeps idle connections open and reuses them for the following requests
+#[derive(Clone)]
+struct RpcEndpoint {
+    client: reqwest::Client,
+    url: reqwest::Url,
+}
+
+impl RpcEndpoint {
+    fn new(rpc_url: &str, timeout: Duration) -> Result<Self, Box<dyn std::error::Error>> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .connect_timeout(timeout)
+            .pool_idle_timeout(Duration::from_secs(90))
+            .build()?;
+        let url: reqwest::Url = rpc_url.parse()?;
+        Ok(RpcEndpoint { client, url })
+    }
+
+    // Build the HTTP provider for the endpoint
+    fn provider(&self) -> Provider<Http> {
+        Provider::new(Http::new_with_client(self.url.clone(), self.client.clone()))
+    }
+}
+
+// Client used with the HTTP provider, see run
+type HttpClient = SignerMiddleware<Provider<Http>, LocalWallet>;
+
+// Find the reqwest error of a failed RPC request, through the provider, signer and contract errors
+fn reqwest_error<'a>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a reqwest::Error> {
+    let provider_error: Option<&ProviderError> = if let Some(err) = err.downcast_ref::<ProviderError>() {
+        Some(err)
+    } else if let Some(err) = err.downcast_ref::<SignerMiddlewareError<Provider<Http>, LocalWallet>>() {
+        err.as_inner()
+    } else if let Some(ContractError::MiddlewareError { e }) = err.downcast_ref::<ContractError<HttpClient>>() {
+        e.as_inner()
+    } else {
+        None
+    };
+
+    match provider_error {
+        Some(ProviderError::HTTPError(e)) => Some(e),
+        _ => err.downcast_ref::<reqwest::Error>(),
+    }
+}
+
+// Replace the error of a timed out RPC request with a message naming the timeout
+// The reqwest error is searched in the source chain, other errors are returned as they are
+fn describe_timeout(err: Box<dyn std::error::Error>, timeout_secs: u64) -> Box<dyn std::error::Error> {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err.as_ref());
+    while let Some(current) = source {
+        if reqwest_error(current).is_some_and(|e| e.is_timeout()) {
+            return format!(
+                "RPC request timed out after {}s, check ALCHEMY_RPC_URL or raise RPC_TIMEOUT_SECS",
+                timeout_secs
+            )
+            .into();
+        }
+        source = current.source();
+    }
+    err
+}
+
+// Remove a boolean flag from the arguments, returning whether it was present
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+// Remove an option and its value from the arguments, e.g. `--decimals 18`
+// Returns an error if the option is given without a value
+fn take_value(args: &mut Vec<String>, option: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|arg| arg == option) {
+        Some(index) if index + 1 < args.len() => {
+            let value = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(value))
+        }
+        Some(_) => Err(format!("{} requires a value", option)),
+        None => Ok(None),
+    }
+}
+
+// Convert an amount of ether to wei, e.g. 0.5 becomes 500000000000000000
+// Amounts with more than 18 decimals or a sign are rejected
+fn parse_ether(amount: &str) -> Result<U256, Box<dyn std::error::Error>> {
+    // parse_units would silently cut off the digits below one wei
+    if amount.split_once('.').is_some_and(|(_, fraction)| fraction.len() > 18) {
+        return Err(format!("invalid value {}: at most 18 decimals are allowed", amount).into());
+    }
+    match parse_units(amount, "ether").map_err(|e| format!("invalid value {}: {}", amount, e))? {
+        ParseUnits::U256(wei) => Ok(wei),
+        ParseUnits::I256(_) => Err(format!("invalid value {}: must not be negative", amount).into()),
+    }
+}
+
+// Format a raw contract value with the given number of decimals
+// e.g. 1000000000000000000 with 18 decimals becomes 1.0
+// Trailing zeros of the fraction are dropped, keeping at least one digit
+fn format_value(value: U256, decimals: u32) -> Result<String, Box<dyn std::error::Error>> {
+    let formatted = format_units(value, decimals)?;
+    let mut formatted = formatted.trim_end_matches('0').to_string();
+    if formatted.ends_with('.') {
+        formatted.push('0');
+    }
+    Ok(formatted)
+}
+
+// Compare the chain id reported by the provider with EXPECTED_CHAIN_ID, if it is set
+// This stops a wallet meant for a testnet from sending to mainnet because of a wrong RPC URL
+fn check_chain_id(expected: Option<u64>, actual: u64) -> Result<(), String> {
+    match expected {
+        Some(expected) if expected != actual => Err(format!(
+            "chain id mismatch: EXPECTED_CHAIN_ID is {} but the RPC endpoint is on chain {}",
+            expected, actual
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Commands of the command line, any other first word of a REPL line is taken as a function name
+const COMMANDS: &[&str] = &["call", "multiget", "send", "watch-value", "batch", "logs"];
+
+// Turn a line typed at the REPL prompt into the arguments of a command
+// Besides the commands of the command line, a function of the contract can be typed directly,
+// e.g. `get` is called as `call get` and `set 42` is sent as `send set 42`
+fn repl_command(line: &str, abi: &Abi) -> Vec<String> {
+    let mut args: Vec<String> = line.split_whitespace().map(String::from).collect();
+    let Some(first) = args.first() else {
+        return args;
+    };
+    if COMMANDS.contains(&first.as_str()) {
+        return args;
+    }
+    if let Ok(function) = abi.function(first) {
+        let command = match function.state_mutability {
+            StateMutability::View | StateMutability::Pure => "call",
+            _ => "send",
+        };
+        args.insert(0, command.to_string());
+    }
+    args
+}
+
+// Read commands from the prompt and run them against the same connection until exit or Ctrl-D
+// A failed command is reported and the prompt continues
+// `options` are appended to every command, options typed on the line take precedence
+async fn repl<M: Middleware + 'static>(
+    contract: &SimpleStorage<M>,
+    endpoint: Option<&RpcEndpoint>,
+    chain_id: u64,
+    cache: &ReadCache,
+    options: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            println!();
+            return Ok(());
+        };
+        let line = line.trim();
+        match line {
+            "" => continue,
+            "exit" | "quit" => return Ok(()),
+            _ => {}
+        }
+
+        let mut args = repl_command(line, contract.abi());
+        if !COMMANDS.contains(&args[0].as_str()) {
+            eprintln!("Error: unknown command or function {}", args[0]);
+            continue;
+        }
+        args.extend(options.iter().cloned());
+        if let Err(err) = run_command(contract, endpoint, chain_id, cache, &args).await {
+            eprintln!("Error: {}", err);
+        }
+    }
+}
+
+// Connect the wallet to the provider and run the requested command against the contract
+// The provider is either the HTTP provider for the configured RPC endpoint or the local node
+// `endpoint` is the RPC endpoint of the HTTP provider, None for the local node
+async fn run<P: JsonRpcClient + 'static>(
+    provider: Provider<P>,
+    endpoint: Option<&RpcEndpoint>,
+    wallet: LocalWallet,
+    contract_address: Address,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
     // Get the chain ID from the provider
     // This is useful for signing transactions correctly
     // and ensuring the wallet is configured for the correct network
     let chain_id = provider.get_chainid().await?.as_u64();
 
-    // Load the private key from environment variable
-    let private_key = env::var("PRIVATE_KEY").expect("PRIVATE_KEY not set");
+    // Abort before anything is sent if the provider is on an unexpected network
+    let expected_chain_id = env::var("EXPECTED_CHAIN_ID")
+        .ok()
+        .map(|value| value.parse::<u64>())
+        .transpose()?;
+    check_chain_id(expected_chain_id, chain_id)?;
 
     // Create a LocalWallet from the private key
     // and wrap it in a SignerMiddleware to allow signing transactions
@@ -52,40 +932,696 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>{
     // The SignerMiddleware is used to add signing capabilities to the provider
     // The wallet is then configured with the chain ID to ensure it signs transactions correctly
     // The provider is used to interact with the Ethereum network
-    let wallet = private_key.parse::<LocalWallet>()?.with_chain_id(chain_id);
+    let wallet = wallet.with_chain_id(chain_id);
     let client = SignerMiddleware::new(provider, wallet);
     let client = Arc::new(client);
 
-    // The contract address is the address of the deployed contract on the Ethereum network
-    let contract_address: Address = env::var("CONTRACT_ADDRESS").expect("CONTRACT_ADDRESS not set"). parse()?;
     println!("Interacting with contract at: {:?}", contract_address);   
 
     // Create an instance of the SimpleStorage contract
     // The contract instance allows us to call functions on the contract
     let contract = SimpleStorage::new(contract_address, client);
 
+    // --cache-ttl serves repeated reads of the same call from memory for that many seconds,
+    // --no-cache turns it off again, see cache.rs
+    let mut args = args.to_vec();
+    let cache_ttl = take_value(&mut args, "--cache-ttl")?
+        .map(|value| value.parse::<u64>())
+        .transpose()?
+        .unwrap_or(0);
+    let cache_ttl = if take_flag(&mut args, "--no-cache") { 0 } else { cache_ttl };
+    let cache = ReadCache::new(Duration::from_secs(cache_ttl));
+
+    // repl keeps the connection and reads the commands from the prompt, one per line
+    // The options given with repl apply to every command, e.g. `repl --dry-run`
+    if args.first().map(String::as_str) == Some("repl") {
+        return repl(&contract, endpoint, chain_id, &cache, &args[1..]).await;
+    }
+    run_command(&contract, endpoint, chain_id, &cache, &args).await
+}
+
+// Run a single command of the command line against the contract
+async fn run_command<M: Middleware + 'static>(
+    contract: &SimpleStorage<M>,
+    endpoint: Option<&RpcEndpoint>,
+    chain_id: u64,
+    cache: &ReadCache,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    // --decimals N formats the stored value as a decimal number with N decimals
+    let mut args = args.to_vec();
+    let decimals = take_value(&mut args, "--decimals")?
+        .map(|value| value.parse::<u32>())
+        .transpose()?;
+
+    // --nonce and --gas-limit override the automatic values when sending
+    // --value attaches ether to a payable function
+    // --dry-run checks and prints send and batch transactions without broadcasting them
+    let overrides = TxOverrides {
+        nonce: take_value(&mut args, "--nonce")?
+            .map(|value| value.parse::<u64>().map(U256::from))
+            .transpose()?,
+        gas_limit: take_value(&mut args, "--gas-limit")?
+            .map(|value| value.parse::<u64>().map(U256::from))
+            .transpose()?,
+        value: take_value(&mut args, "--value")?
+            .map(|value| parse_ether(&value))
+            .transpose()?,
+        check_pending: take_flag(&mut args, "--check-pending"),
+        replace: take_flag(&mut args, "--replace"),
+        simulate: take_flag(&mut args, "--simulate"),
+        force: take_flag(&mut args, "--force"),
+        dry_run: take_flag(&mut args, "--dry-run"),
+    };
 
-    // // call set() function
-    // // This function sets a value in the contract
-    // // The value to set is passed as a U256
-    // // The send() method sends the transaction to the Ethereum network
-    // // sending 42 as an example value
-    // // The transaction is awaited to get the transaction hash
-    // let send_value = contract.set(U256::from(42));
-    // let tx = send_value.send().await?;
-    // println!("Transaction hash: {:?}", tx);
+    // --from-block and --to-block select the block range of `logs`
+    let from_block = take_value(&mut args, "--from-block")?
+        .map(|value| value.parse::<u64>())
+        .transpose()?;
+    let to_block = take_value(&mut args, "--to-block")?
+        .filter(|value| value != "latest")
+        .map(|value| value.parse::<u64>())
+        .transpose()?;
 
+    // --stop-on-error ends a batch at its first failed call
+    let stop_on_error = take_flag(&mut args, "--stop-on-error");
 
-    // comment out set() function code before running get()
-    // Call get() function
-    // This function retrieves the stored value from the contract
-    // The call() method is used to call a view function that does not require a transaction
-    // The result is awaited to get the value stored in the contract
-    // The value is returned as a U256
-    // This is a read-only operation and does not require gas
-    let value = contract.get().call().await?;
-    println!("Stored value: {}", value);
+    // --interval sets how often watch-value polls, --verbose prints every polled value
+    let interval = take_value(&mut args, "--interval")?
+        .map(|value| parse_interval(&value))
+        .transpose()?
+        .unwrap_or(Duration::from_secs(DEFAULT_WATCH_INTERVAL_SECS));
+    let verbose = take_flag(&mut args, "--verbose");
+
+    // --retries and --retry-delay retry the reads of get, call and multiget on transient failures
+    let retry = ReadRetry {
+        retries: take_value(&mut args, "--retries")?
+            .map(|value| value.parse::<u32>())
+            .transpose()?
+            .unwrap_or(0),
+        delay: take_value(&mut args, "--retry-delay")?
+            .map(|value| parse_interval(&value))
+            .transpose()?
+            .unwrap_or(Duration::from_secs(DEFAULT_RETRY_DELAY_SECS)),
+    };
+
+    // AUDIT_LOG_PATH appends every sent transaction to a JSON Lines file, see audit.rs
+    let audit_log = env::var("AUDIT_LOG_PATH").ok();
+    let audit_log = audit_log.as_deref();
+
+    // Dispatch on the command line
+    // call <function> [args...] calls a read-only function
+    // multiget <function> [function...] reads several functions without arguments in one batch
+    // send <function> [args...] sends a transaction to a state-changing function
+    // logs --from-block N [--to-block M] prints the logs of the contract in a block range
+    // batch <file> sends the calls listed in a file one by one
+    // watch-value [--interval 5s] [--verbose] polls the stored value until Ctrl-C
+    // repl reads these commands from a prompt, see repl() above
+    // Without a command the stored value is read with get(), any other word is an error
+    match args.first().map(String::as_str) {
+        Some("call") => call_function(contract, &args[1..], retry, cache).await?,
+        Some("multiget") => multi_get(contract, &args[1..], endpoint, retry).await?,
+        Some("send") => {
+            // A sent transaction may change what the cached reads return
+            let sent = send_function(contract, &args[1..], overrides, false, chain_id, audit_log).await;
+            cache.clear();
+            sent?
+        }
+        Some("watch-value") => watch_value(contract, interval, verbose, decimals, cache).await?,
+        Some("batch") => {
+            let path = args.get(1).ok_or("usage: batch <file> [--stop-on-error]")?;
+            let sent = run_batch(contract, path, overrides, stop_on_error, chain_id, audit_log).await;
+            cache.clear();
+            sent?
+        }
+        Some("logs") => {
+            let from_block = from_block.ok_or("usage: logs --from-block N [--to-block M]")?;
+            print_logs(contract, from_block, to_block).await?
+        }
+        Some(command) => {
+            return Err(format!("unknown command {}, expected one of {} or repl", command, COMMANDS.join(", ")).into());
+        }
+        None => {
+            // Call get() function
+            // This function retrieves the stored value from the contract
+            // The call is run with eth_call, a view function that does not require a transaction,
+            // and retried on transient failures as set by --retries
+            // The value is returned as a U256
+            // This is a read-only operation and does not require gas
+            let get = contract.get();
+            let output = cached_call(contract.client().as_ref(), &get.tx, retry, cache).await?;
+            let value = U256::from_tokens(get.function.decode_output(&output)?)?;
+            match decimals {
+                Some(decimals) => println!("Stored value: {}", format_value(value, decimals)?),
+                None => println!("Stored value: {}", value),
+            }
+        }
+    }
 
     // The program has completed successfully
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fmt::Debug, sync::Mutex};
+    use async_trait::async_trait;
+    use serde::{de::DeserializeOwned, Serialize};
+    use local::LOCAL_CHAIN_ID;
+
+    // A local node that records the methods it is called with
+    // and fails the next `failures` eth_calls as if the connection had dropped
+    // With `reverting` set every eth_call reverts like a call of an unknown function
+    #[derive(Debug, Clone, Default)]
+    struct RecordingNode {
+        node: LocalNode,
+        methods: Arc<Mutex<Vec<String>>>,
+        failures: Arc<Mutex<u32>>,
+        reverting: bool,
+    }
+
+    impl RecordingNode {
+        fn failing(failures: u32) -> Self {
+            RecordingNode { failures: Arc::new(Mutex::new(failures)), ..Default::default() }
+        }
+
+        fn reverting() -> Self {
+            RecordingNode { reverting: true, ..Default::default() }
+        }
+
+        // How often `method` was called
+        fn count(&self, method: &str) -> usize {
+            self.methods.lock().unwrap().iter().filter(|called| *called == method).count()
+        }
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for RecordingNode {
+        type Error = ProviderError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+        where
+            T: Debug + Serialize + Send + Sync,
+            R: DeserializeOwned + Send,
+        {
+            self.methods.lock().unwrap().push(method.to_string());
+            if method == "eth_call" {
+                {
+                    let mut failures = self.failures.lock().unwrap();
+                    if *failures > 0 {
+                        *failures -= 1;
+                        return Err(ProviderError::CustomError("connection reset".to_string()));
+                    }
+                }
+                if self.reverting {
+                    return self.node.request(method, json!([{ "input": "0xdeadbeef" }])).await;
+                }
+            }
+            self.node.request(method, params).await
+        }
+    }
+
+    type LocalClient = SignerMiddleware<Provider<RecordingNode>, LocalWallet>;
+    type LocalContract = SimpleStorage<LocalClient>;
+
+    // The SimpleStorage contract on `node`, with the dev wallet as sender
+    fn local_contract(node: &RecordingNode) -> LocalContract {
+        let provider = Provider::new(node.clone()).interval(Duration::from_millis(1));
+        let wallet = DEV_PRIVATE_KEY.parse::<LocalWallet>().unwrap().with_chain_id(LOCAL_CHAIN_ID);
+        let client = Arc::new(SignerMiddleware::new(provider, wallet));
+        SimpleStorage::new(LOCAL_CONTRACT_ADDRESS.parse::<Address>().unwrap(), client)
+    }
+
+    fn retry(retries: u32) -> ReadRetry {
+        ReadRetry { retries, delay: Duration::ZERO }
+    }
+
+    #[test]
+    fn format_value_with_0_6_and_18_decimals() {
+        assert_eq!(format_value(U256::from(42), 0).unwrap(), "42.0");
+        assert_eq!(format_value(U256::from(100), 0).unwrap(), "100.0");
+        assert_eq!(format_value(U256::from(1_500_000), 6).unwrap(), "1.5");
+        assert_eq!(format_value(U256::from(123), 6).unwrap(), "0.000123");
+        let wei = U256::exp10(18);
+        assert_eq!(format_value(wei, 18).unwrap(), "1.0");
+        assert_eq!(format_value(wei * 3 / 4, 18).unwrap(), "0.75");
+        assert_eq!(format_value(U256::zero(), 18).unwrap(), "0.0");
+    }
+
+    #[test]
+    fn build_transaction_applies_the_overrides() {
+        let to: Address = LOCAL_CONTRACT_ADDRESS.parse().unwrap();
+        let data = Bytes::from(vec![0x60, 0xfe, 0x47, 0xb1]);
+        let overrides = TxOverrides {
+            nonce: Some(U256::from(7)),
+            gas_limit: Some(U256::from(100_000)),
+            value: Some(U256::from(5)),
+            ..Default::default()
+        };
+
+        let tx = build_transaction(to, data.clone(), overrides);
+        assert_eq!(tx.to, Some(to.into()));
+        assert_eq!(tx.data, Some(data));
+        assert_eq!(tx.nonce, Some(U256::from(7)));
+        assert_eq!(tx.gas, Some(U256::from(100_000)));
+        assert_eq!(tx.value, Some(U256::from(5)));
+    }
+
+    #[test]
+    fn build_transaction_leaves_missing_overrides_to_ethers() {
+        let to: Address = LOCAL_CONTRACT_ADDRESS.parse().unwrap();
+        let tx = build_transaction(to, Bytes::default(), TxOverrides::default());
+        assert_eq!(tx.nonce, None);
+        assert_eq!(tx.gas, None);
+        assert_eq!(tx.value, None);
+    }
+
+    #[test]
+    fn nonce_gap_counts_the_pending_transactions() {
+        assert_eq!(nonce_gap(U256::from(5), U256::from(5)), U256::zero());
+        assert_eq!(nonce_gap(U256::from(7), U256::from(5)), U256::from(2));
+        // A node that lags behind may report fewer pending than confirmed transactions
+        assert_eq!(nonce_gap(U256::from(4), U256::from(5)), U256::zero());
+    }
+
+    #[test]
+    fn check_chain_id_accepts_a_match_or_no_expectation() {
+        assert_eq!(check_chain_id(Some(11155111), 11155111), Ok(()));
+        assert_eq!(check_chain_id(None, 1), Ok(()));
+    }
+
+    #[test]
+    fn check_chain_id_rejects_a_mismatch() {
+        assert_eq!(
+            check_chain_id(Some(11155111), 1),
+            Err("chain id mismatch: EXPECTED_CHAIN_ID is 11155111 but the RPC endpoint is on chain 1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_endpoint_times_out_with_a_clear_error() {
+        // Accept connections but never answer them
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                connections.push(socket);
+            }
+        });
+
+        let endpoint = RpcEndpoint::new(&url, Duration::from_millis(200)).unwrap();
+        let err = endpoint.provider().get_chainid().await.unwrap_err();
+        assert!(reqwest_error(&err).is_some_and(|e| e.is_timeout()));
+
+        let err = describe_timeout(Box::new(err), 1);
+        assert_eq!(
+            err.to_string(),
+            "RPC request timed out after 1s, check ALCHEMY_RPC_URL or raise RPC_TIMEOUT_SECS"
+        );
+    }
+
+    #[test]
+    fn other_errors_are_not_described_as_timeouts() {
+        let err = describe_timeout("unsupported RPC".into(), 30);
+        assert_eq!(err.to_string(), "unsupported RPC");
+    }
+
+    #[test]
+    fn parse_ether_converts_to_wei() {
+        assert_eq!(parse_ether("1").unwrap(), U256::exp10(18));
+        assert_eq!(parse_ether("0.5").unwrap(), U256::exp10(17) * 5);
+        assert_eq!(parse_ether("0.000000000000000001").unwrap(), U256::one());
+        assert_eq!(parse_ether("0").unwrap(), U256::zero());
+    }
+
+    #[test]
+    fn parse_ether_rejects_invalid_amounts() {
+        assert_eq!(
+            parse_ether("0.0000000000000000001").unwrap_err().to_string(),
+            "invalid value 0.0000000000000000001: at most 18 decimals are allowed"
+        );
+        assert_eq!(parse_ether("-1").unwrap_err().to_string(), "invalid value -1: must not be negative");
+        assert!(parse_ether("one").is_err());
+    }
+
+    #[test]
+    fn value_is_set_on_the_request_in_wei() {
+        let to: Address = LOCAL_CONTRACT_ADDRESS.parse().unwrap();
+        let overrides = TxOverrides { value: Some(parse_ether("0.25").unwrap()), ..Default::default() };
+        let tx = build_transaction(to, Bytes::default(), overrides);
+        assert_eq!(tx.value, Some(U256::from(250_000_000_000_000_000u64)));
+    }
+
+    #[test]
+    fn block_chunks_split_at_the_chunk_size() {
+        assert_eq!(block_chunks(0, 4500, 2000), [(0, 1999), (2000, 3999), (4000, 4500)]);
+        assert_eq!(block_chunks(100, 2099, 2000), [(100, 2099)]);
+        assert_eq!(block_chunks(100, 2100, 2000), [(100, 2099), (2100, 2100)]);
+        assert_eq!(block_chunks(5, 5, 2000), [(5, 5)]);
+        assert_eq!(block_chunks(1, 3, 1), [(1, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn block_chunks_handle_empty_and_edge_ranges() {
+        assert!(block_chunks(10, 9, 2000).is_empty());
+        assert_eq!(block_chunks(0, 2, 0), [(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(block_chunks(u64::MAX - 1, u64::MAX, 2000), [(u64::MAX - 1, u64::MAX)]);
+    }
+
+    #[test]
+    fn parse_batch_reads_one_call_per_line() {
+        let contents = "# nightly reset\nset 0\n\n  set   42  \ntransfer 0xabc 5\n";
+        assert_eq!(
+            parse_batch(contents),
+            [
+                BatchCall { line: 2, function: "set".to_string(), args: vec!["0".to_string()] },
+                BatchCall { line: 4, function: "set".to_string(), args: vec!["42".to_string()] },
+                BatchCall {
+                    line: 5,
+                    function: "transfer".to_string(),
+                    args: vec!["0xabc".to_string(), "5".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_batch_keeps_calls_without_arguments() {
+        assert_eq!(parse_batch("pause"), [BatchCall { line: 1, function: "pause".to_string(), args: Vec::new() }]);
+        assert!(parse_batch("\n  \n# only comments\n").is_empty());
+    }
+
+    #[test]
+    fn wallet_is_decrypted_from_the_keystore() {
+        let dir = env::temp_dir().join(format!("keystore-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let key = hex::decode(DEV_PRIVATE_KEY).unwrap();
+        let (expected, _) =
+            LocalWallet::encrypt_keystore(&dir, &mut ethers::core::rand::thread_rng(), key, "secret", Some("dev.json"))
+                .unwrap();
+        let path = dir.join("dev.json").to_string_lossy().into_owned();
+
+        let vars = |password: &'static str| {
+            let path = path.clone();
+            move |name: &str| match name {
+                "KEYSTORE_PATH" => Some(path.clone()),
+                "KEYSTORE_PASSWORD" => Some(password.to_string()),
+                // The keystore takes precedence over a private key
+                "PRIVATE_KEY" => Some("not a key".to_string()),
+                _ => None,
+            }
+        };
+        let wallet = wallet_from(vars("secret")).unwrap();
+        let wrong_password = wallet_from(vars("wrong")).unwrap_err().to_string();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(wallet.address(), expected.address());
+        assert_eq!(wallet.address(), DEV_PRIVATE_KEY.parse::<LocalWallet>().unwrap().address());
+        assert!(wrong_password.starts_with(&format!("failed to decrypt keystore {}: ", path)));
+    }
+
+    #[test]
+    fn wallet_falls_back_to_the_private_key() {
+        let wallet = wallet_from(|name| (name == "PRIVATE_KEY").then(|| DEV_PRIVATE_KEY.to_string())).unwrap();
+        assert_eq!(wallet.address(), DEV_PRIVATE_KEY.parse::<LocalWallet>().unwrap().address());
+
+        assert_eq!(wallet_from(|_| None).unwrap_err().to_string(), "PRIVATE_KEY or KEYSTORE_PATH not set");
+        let no_password = wallet_from(|name| (name == "KEYSTORE_PATH").then(|| "key.json".to_string()));
+        assert_eq!(no_password.unwrap_err().to_string(), "KEYSTORE_PASSWORD not set");
+    }
+
+    #[test]
+    fn parse_interval_reads_the_unit() {
+        assert_eq!(parse_interval("5s"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse_interval("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_interval("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_interval("7"), Ok(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn parse_interval_rejects_bad_values() {
+        assert_eq!(parse_interval("5h"), Err("invalid interval: 5h (use ms, s or m)".to_string()));
+        assert_eq!(parse_interval("s"), Err("invalid interval: s".to_string()));
+        assert_eq!(parse_interval(""), Err("invalid interval: ".to_string()));
+        assert_eq!(parse_interval("0s"), Err("the interval must be greater than zero".to_string()));
+        assert_eq!(
+            parse_interval("18446744073709551615m"),
+            Err("interval too large: 18446744073709551615m".to_string())
+        );
+    }
+
+    #[test]
+    fn value_changed_on_the_first_value_and_every_change() {
+        assert!(value_changed(None, U256::zero()));
+        assert!(value_changed(Some(U256::from(1)), U256::from(2)));
+        assert!(!value_changed(Some(U256::from(2)), U256::from(2)));
+    }
+
+    #[test]
+    fn batch_has_one_eth_call_per_function() {
+        let to: Address = LOCAL_CONTRACT_ADDRESS.parse().unwrap();
+        let get = SIMPLESTORAGE_ABI.function("get").unwrap();
+        let calls: Vec<Bytes> = (0..3).map(|_| get.encode_input(&[]).unwrap().into()).collect();
+
+        let requests = batch_requests(to, &calls);
+        assert_eq!(requests.len(), 3);
+        for (id, request) in requests.iter().enumerate() {
+            assert_eq!(request["id"], id);
+            assert_eq!(request["method"], "eth_call");
+            assert_eq!(request["params"][0]["to"], json!(to));
+            assert_eq!(request["params"][0]["data"], json!(calls[id]));
+            assert_eq!(request["params"][1], "latest");
+        }
+        assert!(batch_requests(to, &[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn reads_are_retried_on_transient_errors() {
+        let node = RecordingNode::failing(2);
+        let contract = local_contract(&node);
+        let get = contract.get();
+
+        let output = call_with_retry(contract.client().as_ref(), &get.tx, retry(2)).await.unwrap();
+        assert_eq!(U256::from_tokens(get.function.decode_output(&output).unwrap()).unwrap(), U256::zero());
+        assert_eq!(node.count("eth_call"), 3);
+    }
+
+    #[tokio::test]
+    async fn reads_give_up_after_the_configured_retries() {
+        let node = RecordingNode::failing(5);
+        let contract = local_contract(&node);
+
+        let err = call_with_retry(contract.client().as_ref(), &contract.get().tx, retry(3)).await.unwrap_err();
+        assert!(err.to_string().contains("connection reset"));
+        assert_eq!(node.count("eth_call"), 4);
+
+        let node = RecordingNode::failing(1);
+        let contract = local_contract(&node);
+        assert!(call_with_retry(contract.client().as_ref(), &contract.get().tx, ReadRetry::default()).await.is_err());
+        assert_eq!(node.count("eth_call"), 1);
+    }
+
+    #[tokio::test]
+    async fn reverts_are_not_retried() {
+        let node = RecordingNode::default();
+        let contract = local_contract(&node);
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(contract.address())
+            .data(Bytes::from(vec![0xde, 0xad, 0xbe, 0xef]))
+            .into();
+
+        let err = call_with_retry(contract.client().as_ref(), &tx, retry(3)).await.unwrap_err();
+        assert!(err.is_error_response());
+        assert_eq!(node.count("eth_call"), 1);
+    }
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[tokio::test]
+    async fn dry_run_sends_nothing() {
+        let node = RecordingNode::default();
+        let contract = local_contract(&node);
+        let overrides = TxOverrides { dry_run: true, ..Default::default() };
+
+        send_function(&contract, &args("set 42"), overrides, true, LOCAL_CHAIN_ID, None).await.unwrap();
+        assert_eq!(node.count("eth_sendRawTransaction"), 0);
+        // The transaction is still simulated and filled in
+        assert_eq!(node.count("eth_call"), 1);
+        assert_eq!(node.count("eth_estimateGas"), 1);
+        assert_eq!(contract.get().call().await.unwrap(), U256::zero());
+    }
+
+    #[tokio::test]
+    async fn dry_run_batch_sends_nothing() {
+        let node = RecordingNode::default();
+        let contract = local_contract(&node);
+        let path = env::temp_dir().join(format!("batch-dry-run-{}.txt", std::process::id()));
+        fs::write(&path, "set 1\nset 2\n").unwrap();
+        let overrides = TxOverrides { dry_run: true, ..Default::default() };
+
+        let result = run_batch(&contract, path.to_str().unwrap(), overrides, true, LOCAL_CHAIN_ID, None).await;
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(node.count("eth_sendRawTransaction"), 0);
+        assert_eq!(node.count("eth_call"), 2);
+    }
+
+    #[tokio::test]
+    async fn revert_reason_decodes_the_error_string_of_a_revert() {
+        let node = RecordingNode::default();
+        let contract = local_contract(&node);
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(contract.address())
+            .data(Bytes::from(vec![0xde, 0xad, 0xbe, 0xef]))
+            .into();
+
+        let err = contract.client().call(&tx, None).await.unwrap_err();
+        assert_eq!(
+            revert_reason::<LocalClient>(&err).as_deref(),
+            Some("function selector was not recognized")
+        );
+    }
+
+    #[tokio::test]
+    async fn revert_reason_is_none_without_an_error_response() {
+        let node = RecordingNode::failing(1);
+        let contract = local_contract(&node);
+
+        let err = contract.client().call(&contract.get().tx, None).await.unwrap_err();
+        assert_eq!(revert_reason::<LocalClient>(&err), None);
+    }
+
+    #[tokio::test]
+    async fn simulated_revert_refuses_to_send() {
+        let node = RecordingNode::reverting();
+        let contract = local_contract(&node);
+        let overrides = TxOverrides { simulate: true, ..Default::default() };
+
+        let err = send_function(&contract, &args("set 42"), overrides, true, LOCAL_CHAIN_ID, None).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "simulation reverted: function selector was not recognized, use --force to send anyway"
+        );
+        assert_eq!(node.count("eth_sendRawTransaction"), 0);
+    }
+
+    #[tokio::test]
+    async fn simulated_revert_is_sent_with_force() {
+        let node = RecordingNode::reverting();
+        let contract = local_contract(&node);
+        let overrides = TxOverrides { simulate: true, force: true, ..Default::default() };
+
+        send_function(&contract, &args("set 42"), overrides, true, LOCAL_CHAIN_ID, None).await.unwrap();
+        assert_eq!(node.count("eth_sendRawTransaction"), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_simulation_without_a_revert_is_not_forced() {
+        let node = RecordingNode::failing(1);
+        let contract = local_contract(&node);
+        let overrides = TxOverrides { simulate: true, force: true, ..Default::default() };
+
+        let err = send_function(&contract, &args("set 42"), overrides, true, LOCAL_CHAIN_ID, None).await.unwrap_err();
+        assert!(err.to_string().contains("connection reset"), "{}", err);
+        assert_eq!(node.count("eth_sendRawTransaction"), 0);
+    }
+
+    #[tokio::test]
+    async fn local_mode_sends_with_the_dev_wallet_to_the_emulated_contract() {
+        let node = LocalNode::default();
+
+        run_local(node.clone(), &args("send set 7")).await.unwrap();
+        run_local(node.clone(), &args("call get")).await.unwrap();
+
+        // The transaction signed by the dev wallet changed the state of the node
+        let contract = local_contract(&RecordingNode { node, ..Default::default() });
+        assert_eq!(contract.get().call().await.unwrap(), U256::from(7));
+    }
+
+    #[tokio::test]
+    async fn without_dry_run_the_transaction_is_sent() {
+        let node = RecordingNode::default();
+        let contract = local_contract(&node);
+
+        send_function(&contract, &args("set 42"), TxOverrides::default(), true, LOCAL_CHAIN_ID, None).await.unwrap();
+        assert_eq!(node.count("eth_sendRawTransaction"), 1);
+        assert_eq!(contract.get().call().await.unwrap(), U256::from(42));
+    }
+
+    #[test]
+    fn repl_lines_become_commands() {
+        let abi = &*SIMPLESTORAGE_ABI;
+        assert_eq!(repl_command("get", abi), args("call get"));
+        assert_eq!(repl_command("  set   42 ", abi), args("send set 42"));
+        assert_eq!(repl_command("call get", abi), args("call get"));
+        assert_eq!(repl_command("multiget get get", abi), args("multiget get get"));
+        assert_eq!(repl_command("send set 1 --dry-run", abi), args("send set 1 --dry-run"));
+        assert_eq!(repl_command("watch-value --interval 1s", abi), args("watch-value --interval 1s"));
+    }
+
+    #[test]
+    fn unknown_repl_lines_are_left_as_typed() {
+        let abi = &*SIMPLESTORAGE_ABI;
+        assert_eq!(repl_command("status", abi), args("status"));
+        assert!(repl_command("   ", abi).is_empty());
+    }
+
+    #[tokio::test]
+    async fn run_command_reads_get_without_a_command() {
+        let node = RecordingNode::default();
+        let contract = local_contract(&node);
+        let cache = ReadCache::new(Duration::ZERO);
+
+        run_command(&contract, None, LOCAL_CHAIN_ID, &cache, &args("--decimals 18")).await.unwrap();
+        assert_eq!(node.count("eth_call"), 1);
+    }
+
+    #[tokio::test]
+    async fn run_command_rejects_unknown_commands() {
+        let node = RecordingNode::default();
+        let contract = local_contract(&node);
+        let cache = ReadCache::new(Duration::ZERO);
+
+        let err = run_command(&contract, None, LOCAL_CHAIN_ID, &cache, &args("sned set 5")).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown command sned, expected one of call, multiget, send, watch-value, batch, logs or repl"
+        );
+        assert_eq!(node.count("eth_call"), 0);
+        assert_eq!(node.count("eth_sendRawTransaction"), 0);
+    }
+
+    #[tokio::test]
+    async fn cached_reads_within_the_ttl_do_not_hit_the_provider() {
+        let node = RecordingNode::default();
+        let contract = local_contract(&node);
+        let cache = ReadCache::new(Duration::from_secs(60));
+        let get = contract.get();
+
+        let first = cached_call(contract.client().as_ref(), &get.tx, ReadRetry::default(), &cache).await.unwrap();
+        let second = cached_call(contract.client().as_ref(), &get.tx, ReadRetry::default(), &cache).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(node.count("eth_call"), 1);
+    }
+
+    #[tokio::test]
+    async fn a_send_clears_the_cached_reads() {
+        let node = RecordingNode::default();
+        let contract = local_contract(&node);
+        let cache = ReadCache::new(Duration::from_secs(60));
+
+        run_command(&contract, None, LOCAL_CHAIN_ID, &cache, &[]).await.unwrap();
+        run_command(&contract, None, LOCAL_CHAIN_ID, &cache, &[]).await.unwrap();
+        assert_eq!(node.count("eth_call"), 1);
+
+        run_command(&contract, None, LOCAL_CHAIN_ID, &cache, &args("send set 7")).await.unwrap();
+        let get = contract.get();
+        let output = cached_call(contract.client().as_ref(), &get.tx, ReadRetry::default(), &cache).await.unwrap();
+        assert_eq!(U256::from_tokens(get.function.decode_output(&output).unwrap()).unwrap(), U256::from(7));
+        assert_eq!(node.count("eth_call"), 2);
+    }
+}